@@ -0,0 +1,164 @@
+//! Burning one image to several drives at once: every drive gets the usual `BurnJob::run`
+//! on a thread of its own, and a status line of its own while they run.
+use std::io::IsTerminal;
+use std::sync::mpsc::{self, Sender};
+use std::time::{Duration, Instant};
+use burn_rs::image::Written;
+use burn_rs::interrupt;
+use burn_rs::progress::{Progress, ProgressKind, ProgressSink, Step, StepEnd, StepSink};
+use burn_rs::{BurnError, BurnJob, RunOptions};
+use crate::color::ceprint;
+
+/// What every drive gets, and what to do when one fails.
+#[derive(Clone)]
+pub struct BatchOptions {
+    /// The steps after the write and the checks to let pass, see `BurnJob::run`.
+    pub run: RunOptions,
+    /// Let the other drives finish when one fails, instead of stopping them all.
+    pub keep_going: bool,
+    pub progress: ProgressKind,
+}
+
+/// How the burn went on one drive.
+pub struct Outcome {
+    pub destination: String,
+    pub result: Result<Written, BurnError>,
+    /// Went wrong after the image was on the drive, like ejecting, so the burn still counts.
+    pub warning: Option<String>,
+}
+
+/// What the burn threads tell the status lines, each with the number of its drive.
+enum Update {
+    Step(usize, String),
+    Progress(usize, Progress),
+    Warning(usize, String),
+    Finished(usize, Result<Written, BurnError>),
+}
+
+/// Sends the progress of one drive over to the status lines.
+struct ChannelProgress(usize, Sender<Update>);
+
+impl ProgressSink for ChannelProgress {
+    fn update(&mut self, progress: &Progress) {
+        let _ = self.1.send(Update::Progress(self.0, *progress));
+    }
+}
+
+/// Sends the steps of one drive over to the status lines, and the warnings over to its outcome.
+struct ChannelSteps(usize, Sender<Update>);
+
+impl StepSink for ChannelSteps {
+    fn start(&mut self, job: &BurnJob, step: Step) {
+        let _ = self.1.send(Update::Step(self.0, step.status(job)));
+    }
+
+    fn end(&mut self, _job: &BurnJob, _step: Step, end: StepEnd<'_>) {
+        if let StepEnd::Warning(warning) = end {
+            let _ = self.1.send(Update::Warning(self.0, warning));
+        }
+    }
+}
+
+/// Where one drive is at.
+struct Status {
+    destination: String,
+    step: String,
+    progress: Option<Progress>,
+    result: Option<Result<(), String>>,
+}
+
+impl Status {
+    fn line(&self) -> String {
+        match &self.result {
+            Some(Ok(())) => format!("\x1b[1m[\x1b[32m DONE \x1b[39m] {}\x1b[0m", self.destination),
+            Some(Err(e)) => format!("\x1b[1m[\x1b[31m FAILED \x1b[39m] {}: {}\x1b[0m", self.destination, e),
+            None => match self.progress {
+                Some(progress) if progress.total > 0 => format!(
+                    "\x1b[1m[ {:>3.0}% ] {}: {} ({}/{} mb, {:.1} MB/s)\x1b[0m",
+                    progress.percent().min(100.0), self.destination, self.step, progress.written / 1024 / 1024, progress.total / 1024 / 1024, progress.speed / 1024.0 / 1024.0,
+                ),
+                _ => format!("\x1b[1m[ .... ] {}: {}\x1b[0m", self.destination, self.step),
+            },
+        }
+    }
+}
+
+/// Burns every job on its own thread and waits for all of them, in the order they were given.
+/// Unless `keep_going` is set, the first failure stops the others at their next safe point, like Ctrl-C does.
+/// Also returns whether a failure stopped them, rather than the user.
+pub fn run(jobs: Vec<BurnJob>, options: BatchOptions) -> (Vec<Outcome>, bool) {
+    let (tx, rx) = mpsc::channel();
+    let mut statuses: Vec<Status> = jobs.iter()
+        .map(|job| Status { destination: job.destination.clone(), step: "Waiting...".to_string(), progress: None, result: None })
+        .collect();
+    let mut outcomes: Vec<Option<Outcome>> = jobs.iter().map(|_| None).collect();
+    let mut warnings: Vec<Option<String>> = jobs.iter().map(|_| None).collect();
+    for (i, mut job) in jobs.into_iter().enumerate() {
+        let tx = tx.clone();
+        let options = options.clone();
+        std::thread::spawn(move || {
+            let result = job.run(&options.run, &mut ChannelSteps(i, tx.clone()), &mut ChannelProgress(i, tx.clone()));
+            let _ = tx.send(Update::Finished(i, result));
+        });
+    }
+    drop(tx);
+
+    // Redrawing the lines only makes a mess of logs and pipes, those get a line per step instead.
+    let redraw = options.progress == ProgressKind::Human && std::io::stderr().is_terminal();
+    if redraw {
+        for status in &statuses {
+            ceprint!("{}\n", status.line());
+        }
+    }
+    let mut drawn = Instant::now();
+    let mut stopped = false;
+    let mut remaining = statuses.len();
+    while remaining > 0 {
+        match rx.recv_timeout(Duration::from_millis(200)) {
+            Ok(Update::Step(i, step)) => {
+                statuses[i].step = step;
+                statuses[i].progress = None;
+                if !redraw {
+                    ceprint!("{}\n", statuses[i].line());
+                }
+            }
+            Ok(Update::Progress(i, progress)) => statuses[i].progress = Some(progress),
+            Ok(Update::Warning(i, warning)) => warnings[i] = Some(warning),
+            Ok(Update::Finished(i, result)) => {
+                remaining -= 1;
+                if let Err(e) = &result
+                    && !matches!(e, BurnError::Aborted) && !options.keep_going && !interrupt::requested() {
+                    stopped = true;
+                    interrupt::request();
+                }
+                statuses[i].result = Some(result.as_ref().map(|_| ()).map_err(|e| match e {
+                    BurnError::Aborted if stopped => "stopped, another drive failed".to_string(),
+                    e => e.to_string(),
+                }));
+                if !redraw {
+                    ceprint!("{}\n", statuses[i].line());
+                }
+                outcomes[i] = Some(Outcome { destination: statuses[i].destination.clone(), result, warning: warnings[i].take() });
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            // Every thread is gone, only a panic gets here before they all finished.
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        }
+        // The progress comes in far more often than anyone can read it
+        if redraw && (drawn.elapsed() >= Duration::from_millis(100) || remaining == 0) {
+            drawn = Instant::now();
+            ceprint!("\x1b[{}A", statuses.len());
+            for status in &statuses {
+                ceprint!("\r\x1b[2K{}\n", status.line());
+            }
+        }
+    }
+    let outcomes = outcomes.into_iter().zip(statuses)
+        .map(|(outcome, status)| outcome.unwrap_or_else(|| Outcome {
+            destination: status.destination,
+            result: Err(BurnError::Io(std::io::Error::other("the burn thread crashed"))),
+            warning: None,
+        }))
+        .collect();
+    (outcomes, stopped)
+}
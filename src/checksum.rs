@@ -0,0 +1,147 @@
+//! Hashing the source image, to check it against what the distro published.
+use std::fmt;
+use std::fs::File;
+use std::io::{ErrorKind, Read};
+use std::path::Path;
+use std::str::FromStr;
+use md5::Md5;
+use sha2::{Digest, Sha256, Sha512};
+use crate::error::BurnError;
+
+/// Hash algorithms distros publish checksums in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChecksumAlgorithm {
+    Sha256,
+    Sha512,
+    Md5,
+}
+
+impl ChecksumAlgorithm {
+    /// Guesses the algorithm from the length of a hex checksum.
+    pub fn from_hex_len(len: usize) -> Option<ChecksumAlgorithm> {
+        match len {
+            32 => Some(ChecksumAlgorithm::Md5),
+            64 => Some(ChecksumAlgorithm::Sha256),
+            128 => Some(ChecksumAlgorithm::Sha512),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for ChecksumAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "sha256" => Ok(ChecksumAlgorithm::Sha256),
+            "sha512" => Ok(ChecksumAlgorithm::Sha512),
+            "md5" => Ok(ChecksumAlgorithm::Md5),
+            _ => Err(format!("unknown checksum {} (expected sha256, sha512 or md5)", s)),
+        }
+    }
+}
+
+impl fmt::Display for ChecksumAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChecksumAlgorithm::Sha256 => write!(f, "sha256"),
+            ChecksumAlgorithm::Sha512 => write!(f, "sha512"),
+            ChecksumAlgorithm::Md5 => write!(f, "md5"),
+        }
+    }
+}
+
+/// Formats bytes as lowercase hex.
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Looks `file_name` up in a checksum list like the `SHA256SUMS` distros publish:
+/// lines of `<hex>  <name>`, or `<hex> *<name>` for ones made in binary mode.
+/// Names may carry a directory, only the last part has to match. Returns the checksum in lowercase hex.
+pub fn find_checksum(list: &str, file_name: &str) -> Option<String> {
+    list.lines().find_map(|line| {
+        let (hex, name) = line.trim().split_once(char::is_whitespace)?;
+        let name = name.trim_start().trim_start_matches('*');
+        let name = name.rsplit('/').next().unwrap_or(name);
+        (name == file_name && hex.chars().all(|c| c.is_ascii_hexdigit())).then(|| hex.to_lowercase())
+    })
+}
+
+/// Reads the checksum list at `list_path` and looks the image up in it, see [`find_checksum`].
+/// `None` if the image isn't listed.
+pub fn listed_checksum(list_path: &str, file_path: &str) -> Result<Option<String>, BurnError> {
+    let list = std::fs::read_to_string(list_path)?;
+    let file_name = Path::new(file_path).file_name().and_then(|name| name.to_str()).unwrap_or(file_path);
+    Ok(find_checksum(&list, file_name))
+}
+
+/// Hashes the file as it is on disk (compressed images stay compressed, that's what gets published).
+/// Returns the checksum as lowercase hex.
+pub fn file_checksum(file_path: &str, algorithm: ChecksumAlgorithm) -> Result<String, BurnError> {
+    match algorithm {
+        ChecksumAlgorithm::Sha256 => hash_file::<Sha256>(file_path),
+        ChecksumAlgorithm::Sha512 => hash_file::<Sha512>(file_path),
+        ChecksumAlgorithm::Md5 => hash_file::<Md5>(file_path),
+    }
+}
+
+fn hash_file<D: Digest>(file_path: &str) -> Result<String, BurnError> {
+    let mut file = File::open(file_path)?;
+    let mut hasher = D::new();
+    let mut buffer = vec![0u8; 1024 * 1024];
+
+    loop {
+        let n = match file.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        hasher.update(&buffer[..n]);
+    }
+
+    Ok(to_hex(&hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA: &str = "a2ba8a8d2ab8fd6a3a1e6e1b8f3e6cdbb1a2d2e6bd0c6d4a1b1f1d8e9a3c0f7e";
+
+    #[test]
+    fn finds_the_image_in_a_list() {
+        let list = format!("{}  other.iso\n{}  debian.iso\n", "0".repeat(64), SHA);
+        assert_eq!(find_checksum(&list, "debian.iso").as_deref(), Some(SHA));
+        assert_eq!(find_checksum(&list, "ubuntu.iso"), None);
+        assert_eq!(find_checksum(&list, "debian"), None);
+    }
+
+    #[test]
+    fn finds_binary_mode_entries() {
+        let list = format!("{} *debian.iso\n", SHA);
+        assert_eq!(find_checksum(&list, "debian.iso").as_deref(), Some(SHA));
+    }
+
+    #[test]
+    fn matches_only_the_last_part_of_a_path() {
+        let list = format!("{}  ./images/amd64/debian.iso\n", SHA);
+        assert_eq!(find_checksum(&list, "debian.iso").as_deref(), Some(SHA));
+        assert_eq!(find_checksum(&list, "amd64"), None);
+    }
+
+    #[test]
+    fn returns_uppercase_checksums_in_lowercase() {
+        let list = format!("  {}\tdebian.iso  \r\n", SHA.to_uppercase());
+        assert_eq!(find_checksum(&list, "debian.iso").as_deref(), Some(SHA));
+    }
+
+    #[test]
+    fn skips_lines_that_dont_start_with_a_checksum() {
+        let list = format!("# debian.iso\nSHA256 (debian.iso) = {}\n-----BEGIN PGP SIGNATURE-----\ndebian.iso\n", SHA);
+        assert_eq!(find_checksum(&list, "debian.iso"), None);
+        let list = format!("{}\nnot-hex debian.iso\n{}  debian.iso\n", SHA, SHA);
+        assert_eq!(find_checksum(&list, "debian.iso").as_deref(), Some(SHA));
+    }
+}
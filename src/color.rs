@@ -0,0 +1,135 @@
+//! Turning the ANSI styling of our output off where it would only show up as garbage.
+use std::io::IsTerminal;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static STDOUT: AtomicBool = AtomicBool::new(true);
+static STDERR: AtomicBool = AtomicBool::new(true);
+/// Set when stdout only carries machine-readable output, see [`reserve_stdout`].
+static STDOUT_RESERVED: AtomicBool = AtomicBool::new(false);
+/// Set by `--quiet`, see [`set_quiet`].
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// When to style the output, from `--color`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// Only on terminals, and not with `NO_COLOR` set.
+    Auto,
+    Always,
+    Never,
+}
+
+impl FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(format!("unknown color choice {} (expected auto, always or never)", s)),
+        }
+    }
+}
+
+/// Decides for stdout and stderr separately, one of them is often redirected on its own.
+pub fn init(choice: ColorChoice) {
+    // https://no-color.org: set and not empty means no colors.
+    let no_color = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+    let (stdout, stderr) = match choice {
+        ColorChoice::Always => (true, true),
+        ColorChoice::Never => (false, false),
+        ColorChoice::Auto => (!no_color && std::io::stdout().is_terminal(), !no_color && std::io::stderr().is_terminal()),
+    };
+    STDOUT.store(stdout, Ordering::Relaxed);
+    STDERR.store(stderr, Ordering::Relaxed);
+}
+
+pub fn stdout_enabled() -> bool {
+    STDOUT.load(Ordering::Relaxed)
+}
+
+pub fn stderr_enabled() -> bool {
+    STDERR.load(Ordering::Relaxed)
+}
+
+/// Keeps stdout for machine-readable output: from now on `cprintln!` prints to stderr instead.
+pub fn reserve_stdout() {
+    STDOUT_RESERVED.store(true, Ordering::Relaxed);
+}
+
+/// Silences `cprintln!`, `ceprint!` and `ceprintln!`, so only the machine-readable output gets through.
+/// Errors turn it off again on their way out, see `report_fatal`.
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+pub fn quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Prints a line meant for humans: to stdout, or to stderr once stdout is reserved.
+pub fn print_line(s: String) {
+    if quiet() {
+        return;
+    }
+    if STDOUT_RESERVED.load(Ordering::Relaxed) {
+        eprintln!("{}", paint(s, stderr_enabled()));
+    } else {
+        println!("{}", paint(s, stdout_enabled()));
+    }
+}
+
+/// Returns `s` as is, or without its escape sequences when `enabled` is false.
+pub fn paint(s: String, enabled: bool) -> String {
+    if enabled {
+        return s;
+    }
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            out.push(c);
+            continue;
+        }
+        // CSI sequences: ESC [, parameters, then a final byte from @ to ~.
+        if chars.next() == Some('[') {
+            for c in chars.by_ref() {
+                if ('@'..='~').contains(&c) {
+                    break;
+                }
+            }
+        }
+    }
+    out
+}
+
+/// `println!` that drops the styling when stdout shouldn't get any, see [`print_line`].
+macro_rules! cprintln {
+    () => {
+        $crate::color::print_line(String::new())
+    };
+    ($($arg:tt)*) => {
+        $crate::color::print_line(format!($($arg)*))
+    };
+}
+
+/// `eprint!` that drops the styling when stderr shouldn't get any, and prints nothing with `--quiet`.
+macro_rules! ceprint {
+    ($($arg:tt)*) => {
+        if !$crate::color::quiet() {
+            eprint!("{}", $crate::color::paint(format!($($arg)*), $crate::color::stderr_enabled()))
+        }
+    };
+}
+
+/// `eprintln!` that drops the styling when stderr shouldn't get any, and prints nothing with `--quiet`.
+macro_rules! ceprintln {
+    ($($arg:tt)*) => {
+        if !$crate::color::quiet() {
+            eprintln!("{}", $crate::color::paint(format!($($arg)*), $crate::color::stderr_enabled()))
+        }
+    };
+}
+
+pub(crate) use {ceprint, ceprintln, cprintln};
@@ -0,0 +1,45 @@
+//! Defaults from `config.toml`, for the flags people would otherwise pass every time.
+//! Flags given on the command line win, then the environment (`BURN_TABLE`, `BURN_FS`), then the file.
+use std::path::{Path, PathBuf};
+use serde::Deserialize;
+
+/// What the config file can set, all of it optional. Keys are named like the flags:
+///
+/// ```toml
+/// table = "gpt"
+/// filesystem = "exfat"
+/// block-size = 1024
+/// verify = true
+/// color = "never"
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// Default of the partition table menu, like `BURN_TABLE`.
+    pub table: Option<String>,
+    /// Default of the filesystem menu, like `BURN_FS`.
+    pub filesystem: Option<String>,
+    /// In KB, like `--block-size`.
+    pub block_size: Option<usize>,
+    pub verify: Option<bool>,
+    pub color: Option<String>,
+}
+
+/// Where the config file is looked for without `--config`: `$XDG_CONFIG_HOME/burn-rs/config.toml`,
+/// `~/.config/burn-rs/config.toml` without it, or under `%APPDATA%` on Windows.
+pub fn default_path() -> Option<PathBuf> {
+    // The spec says to ignore relative paths, they'd depend on where we're run from.
+    let dir = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| std::env::var_os(if cfg!(windows) { "APPDATA" } else { "HOME" }).map(|home| {
+            if cfg!(windows) { PathBuf::from(home) } else { Path::new(&home).join(".config") }
+        }))?;
+    Some(dir.join("burn-rs").join("config.toml"))
+}
+
+/// Reads and parses the config file at `path`. The error says what's wrong and where, for the user.
+pub fn load(path: &Path) -> Result<Config, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    toml::from_str(&text).map_err(|e| e.message().to_string())
+}
@@ -0,0 +1,975 @@
+//! Block device helpers: asking the kernel about drives, mounts, and which drives are safe to touch.
+#[cfg(unix)]
+use std::ffi::CString;
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Seek, SeekFrom};
+#[cfg(any(target_os = "linux", target_os = "macos", target_os = "freebsd"))]
+use std::os::fd::AsRawFd;
+#[cfg(unix)]
+use std::os::unix::ffi::OsStrExt;
+#[cfg(windows)]
+use std::os::windows::fs::OpenOptionsExt;
+#[cfg(windows)]
+use std::os::windows::io::AsRawHandle;
+use std::path::Path;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+#[cfg(unix)]
+use sysinfo::Disks;
+use crate::error::BurnError;
+
+/// Asks the kernel for the logical sector size of a block device (512 on most drives, 4096 on 4Kn ones).
+#[cfg(target_os = "linux")]
+pub fn logical_sector_size(file: &File) -> std::io::Result<u32> {
+    // Image files have no sectors of their own, they get the usual 512.
+    if file.metadata()?.is_file() {
+        return Ok(512);
+    }
+    let mut size: libc::c_int = 0;
+    if unsafe { libc::ioctl(file.as_raw_fd(), libc::BLKSSZGET, &mut size) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(size as u32)
+}
+
+/// Asks the drive for its sector size (512 on most drives, 4096 on 4Kn ones).
+#[cfg(windows)]
+pub fn logical_sector_size(file: &File) -> std::io::Result<u32> {
+    use windows_sys::Win32::System::Ioctl::{DISK_GEOMETRY, IOCTL_DISK_GET_DRIVE_GEOMETRY};
+    if file.metadata()?.is_file() {
+        return Ok(512);
+    }
+    let mut geometry: DISK_GEOMETRY = unsafe { std::mem::zeroed() };
+    ioctl(file, IOCTL_DISK_GET_DRIVE_GEOMETRY, None::<&()>, Some(&mut geometry))?;
+    Ok(geometry.BytesPerSector)
+}
+
+// _IOR('d', 24, uint32_t) and _IOR('d', 25, uint64_t) from <sys/disk.h>; libc doesn't export them.
+#[cfg(target_os = "macos")]
+const DKIOCGETBLOCKSIZE: libc::c_ulong = 0x40046418;
+#[cfg(target_os = "macos")]
+const DKIOCGETBLOCKCOUNT: libc::c_ulong = 0x40086419;
+
+// _IOR('d', 128, u_int) and _IOR('d', 129, off_t) from <sys/disk.h>, same story.
+#[cfg(target_os = "freebsd")]
+const DIOCGSECTORSIZE: libc::c_ulong = 0x40046480;
+#[cfg(target_os = "freebsd")]
+const DIOCGMEDIASIZE: libc::c_ulong = 0x40086481;
+
+/// Asks the kernel for the block size of a disk node (512 on most drives, 4096 on 4Kn ones).
+#[cfg(target_os = "macos")]
+pub fn logical_sector_size(file: &File) -> std::io::Result<u32> {
+    if file.metadata()?.is_file() {
+        return Ok(512);
+    }
+    let mut size: u32 = 0;
+    if unsafe { libc::ioctl(file.as_raw_fd(), DKIOCGETBLOCKSIZE, &mut size) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(size)
+}
+
+/// Asks GEOM for the sector size of a provider (512 on most drives, 4096 on 4Kn ones).
+#[cfg(target_os = "freebsd")]
+pub fn logical_sector_size(file: &File) -> std::io::Result<u32> {
+    if file.metadata()?.is_file() {
+        return Ok(512);
+    }
+    let mut size: libc::c_uint = 0;
+    if unsafe { libc::ioctl(file.as_raw_fd(), DIOCGSECTORSIZE, &mut size) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(size)
+}
+
+/// Logical sector size of a block device. Other systems can't be asked for now, so assume 512 there.
+#[cfg(not(any(target_os = "linux", windows, target_os = "macos", target_os = "freebsd")))]
+pub fn logical_sector_size(_file: &File) -> std::io::Result<u32> {
+    Ok(512)
+}
+
+/// Size of a drive or partition in bytes. Seeking to the end works for Linux block devices;
+/// the file is rewound afterwards.
+#[cfg(all(unix, not(any(target_os = "macos", target_os = "freebsd"))))]
+pub fn device_size(mut file: &File) -> std::io::Result<u64> {
+    let size = file.seek(SeekFrom::End(0))?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(size)
+}
+
+/// Size of a drive in bytes. Disk nodes report 0 when seeked to the end on macOS, so they are asked
+/// for their block count instead; plain files are seeked to the end.
+#[cfg(target_os = "macos")]
+pub fn device_size(mut file: &File) -> std::io::Result<u64> {
+    if file.metadata()?.is_file() {
+        let size = file.seek(SeekFrom::End(0))?;
+        file.seek(SeekFrom::Start(0))?;
+        return Ok(size);
+    }
+    let mut count: u64 = 0;
+    if unsafe { libc::ioctl(file.as_raw_fd(), DKIOCGETBLOCKCOUNT, &mut count) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(count * logical_sector_size(file)? as u64)
+}
+
+/// Size of a drive in bytes. GEOM providers are asked for their media size, plain files are seeked to the end.
+#[cfg(target_os = "freebsd")]
+pub fn device_size(mut file: &File) -> std::io::Result<u64> {
+    if file.metadata()?.is_file() {
+        let size = file.seek(SeekFrom::End(0))?;
+        file.seek(SeekFrom::Start(0))?;
+        return Ok(size);
+    }
+    let mut size: libc::off_t = 0;
+    if unsafe { libc::ioctl(file.as_raw_fd(), DIOCGMEDIASIZE, &mut size) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(size as u64)
+}
+
+/// Size of a drive in bytes. Physical drives have to be asked, plain files are seeked to the end.
+#[cfg(windows)]
+pub fn device_size(mut file: &File) -> std::io::Result<u64> {
+    use windows_sys::Win32::System::Ioctl::{GET_LENGTH_INFORMATION, IOCTL_DISK_GET_LENGTH_INFO};
+    let mut length = GET_LENGTH_INFORMATION { Length: 0 };
+    if ioctl(file, IOCTL_DISK_GET_LENGTH_INFO, None::<&()>, Some(&mut length)).is_ok() {
+        return Ok(length.Length as u64);
+    }
+    let size = file.seek(SeekFrom::End(0))?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(size)
+}
+
+/// Sends a DeviceIoControl request with `input` and fills in `output`.
+#[cfg(windows)]
+fn ioctl<I, O>(file: &File, code: u32, input: Option<&I>, output: Option<&mut O>) -> std::io::Result<()> {
+    use std::ffi::c_void;
+    let (input, input_len) = match input {
+        Some(input) => (input as *const I as *const c_void, size_of::<I>() as u32),
+        None => (std::ptr::null(), 0),
+    };
+    let (output, output_len) = match output {
+        Some(output) => (output as *mut O as *mut c_void, size_of::<O>() as u32),
+        None => (std::ptr::null_mut(), 0),
+    };
+    let mut returned = 0u32;
+    let ok = unsafe {
+        windows_sys::Win32::System::IO::DeviceIoControl(file.as_raw_handle(), code, input, input_len, output, output_len, &mut returned, std::ptr::null_mut())
+    };
+    if ok == 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Opens a drive or volume only to ask it things, which needs no access rights.
+#[cfg(windows)]
+fn open_query(path: &str) -> std::io::Result<File> {
+    OpenOptions::new().access_mode(0).open(path)
+}
+
+/// The N of a `\\.\PhysicalDriveN` path.
+#[cfg(windows)]
+fn disk_number(path: &str) -> Option<u32> {
+    let prefix = r"\\.\PhysicalDrive";
+    let head = path.get(..prefix.len())?;
+    if !head.eq_ignore_ascii_case(prefix) {
+        return None;
+    }
+    path[prefix.len()..].parse().ok()
+}
+
+/// Drive letters that are in use, as `C:`.
+#[cfg(windows)]
+fn drive_letters() -> Vec<String> {
+    let mask = unsafe { windows_sys::Win32::Storage::FileSystem::GetLogicalDrives() };
+    (0..26).filter(|i| mask & (1 << i) != 0)
+        .map(|i| format!("{}:", (b'A' + i as u8) as char))
+        .collect()
+}
+
+/// The physical drives a volume (`C:`) lives on, there can be several for spanned volumes.
+#[cfg(windows)]
+fn volume_disks(volume: &str) -> Vec<u32> {
+    use windows_sys::Win32::Storage::FileSystem::IOCTL_VOLUME_GET_VOLUME_DISK_EXTENTS;
+    use windows_sys::Win32::System::Ioctl::{DISK_EXTENT, VOLUME_DISK_EXTENTS};
+    /// Room for a few extents after the one VOLUME_DISK_EXTENTS declares.
+    #[repr(C)]
+    struct Extents {
+        head: VOLUME_DISK_EXTENTS,
+        more: [DISK_EXTENT; 7],
+    }
+    let Ok(file) = open_query(&format!(r"\\.\{}", volume)) else { return Vec::new() };
+    let mut extents: Extents = unsafe { std::mem::zeroed() };
+    if ioctl(&file, IOCTL_VOLUME_GET_VOLUME_DISK_EXTENTS, None::<&()>, Some(&mut extents)).is_err() {
+        return Vec::new();
+    }
+    let count = (extents.head.NumberOfDiskExtents as usize).min(8);
+    std::iter::once(extents.head.Extents[0]).chain(extents.more).take(count)
+        .map(|extent| extent.DiskNumber)
+        .collect()
+}
+
+/// Whether the drive says it's removable (or sits on a USB/SD bus), its vendor and model, and its serial number.
+#[cfg(windows)]
+fn storage_descriptor(file: &File) -> std::io::Result<(bool, String, String)> {
+    use windows_sys::Win32::Storage::FileSystem::{BusTypeMmc, BusTypeSd, BusTypeUsb};
+    use windows_sys::Win32::System::Ioctl::{IOCTL_STORAGE_QUERY_PROPERTY, PropertyStandardQuery, STORAGE_DEVICE_DESCRIPTOR, STORAGE_PROPERTY_QUERY, StorageDeviceProperty};
+    /// The descriptor with the strings its offsets point into.
+    #[repr(C)]
+    struct Descriptor {
+        head: STORAGE_DEVICE_DESCRIPTOR,
+        strings: [u8; 512],
+    }
+    let query = STORAGE_PROPERTY_QUERY { PropertyId: StorageDeviceProperty, QueryType: PropertyStandardQuery, AdditionalParameters: [0] };
+    let mut descriptor: Descriptor = unsafe { std::mem::zeroed() };
+    ioctl(file, IOCTL_STORAGE_QUERY_PROPERTY, Some(&query), Some(&mut descriptor))?;
+    let head = descriptor.head;
+    let bytes = unsafe { std::slice::from_raw_parts(&descriptor as *const Descriptor as *const u8, size_of::<Descriptor>()) };
+    let string_at = |offset: u32| {
+        let Some(rest) = bytes.get(offset as usize..).filter(|_| offset != 0) else { return String::new() };
+        let end = rest.iter().position(|&b| b == 0).unwrap_or(rest.len());
+        String::from_utf8_lossy(&rest[..end]).trim().to_string()
+    };
+    let model = format!("{} {}", string_at(head.VendorIdOffset), string_at(head.ProductIdOffset)).trim().to_string();
+    let removable = head.RemovableMedia != 0 || [BusTypeUsb, BusTypeSd, BusTypeMmc].contains(&head.BusType);
+    Ok((removable, model, string_at(head.SerialNumberOffset)))
+}
+
+// _IO(0x12, 97) from <linux/fs.h>; libc doesn't export it. The "no data" direction bit differs per arch.
+#[cfg(all(target_os = "linux", any(target_arch = "mips", target_arch = "mips64", target_arch = "powerpc", target_arch = "powerpc64", target_arch = "sparc64")))]
+const BLKFLSBUF: libc::Ioctl = 0x20001261;
+#[cfg(all(target_os = "linux", not(any(target_arch = "mips", target_arch = "mips64", target_arch = "powerpc", target_arch = "powerpc64", target_arch = "sparc64"))))]
+const BLKFLSBUF: libc::Ioctl = 0x1261;
+
+// _IO(0x12, 95) from <linux/fs.h>, same story as BLKFLSBUF.
+#[cfg(all(target_os = "linux", any(target_arch = "mips", target_arch = "mips64", target_arch = "powerpc", target_arch = "powerpc64", target_arch = "sparc64")))]
+const BLKRRPART: libc::Ioctl = 0x2000125f;
+#[cfg(all(target_os = "linux", not(any(target_arch = "mips", target_arch = "mips64", target_arch = "powerpc", target_arch = "powerpc64", target_arch = "sparc64"))))]
+const BLKRRPART: libc::Ioctl = 0x125f;
+
+/// Asks the kernel to read the partition table of a whole drive again, so the partition nodes
+/// (`/dev/sdb1`...) match what we just wrote.
+#[cfg(target_os = "linux")]
+pub fn reread_partitions(file: &File) -> std::io::Result<()> {
+    if unsafe { libc::ioctl(file.as_raw_fd(), BLKRRPART) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// The kernel picks up new partition tables by itself outside Linux.
+#[cfg(not(target_os = "linux"))]
+pub fn reread_partitions(_file: &File) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Waits up to `timeout` for a device node to show up. Returns whether it did.
+pub fn wait_for_node(path: &str, timeout: Duration) -> bool {
+    let start = Instant::now();
+    while !Path::new(path).exists() {
+        if start.elapsed() >= timeout {
+            return false;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    true
+}
+
+/// Tells the kernel to write out and drop its buffers for a block device.
+#[cfg(target_os = "linux")]
+pub fn flush_buffers(file: &File) -> std::io::Result<()> {
+    // Image files have nothing to flush past sync_all.
+    if file.metadata()?.is_file() {
+        return Ok(());
+    }
+    if unsafe { libc::ioctl(file.as_raw_fd(), BLKFLSBUF) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Flushes the kernel buffers of a block device. `sync_all` already covers this outside Linux.
+#[cfg(not(target_os = "linux"))]
+pub fn flush_buffers(_file: &File) -> std::io::Result<()> {
+    Ok(())
+}
+
+// From <linux/cdrom.h>, a plain number rather than an _IO() one.
+#[cfg(target_os = "linux")]
+const CDROMEJECT: libc::Ioctl = 0x5309;
+
+/// Ejects the drive once it's written, so it can be pulled out: optical drives open their tray,
+/// anything else is powered off through udisks, or detached from the kernel if udisks isn't there.
+#[cfg(target_os = "linux")]
+pub fn eject(dest_path: &str) -> Result<(), BurnError> {
+    // Desktops like to mount freshly formatted partitions, which would keep the drive busy.
+    unmount_partitions(dest_path)?;
+    let file = File::open(dest_path)?;
+    flush_buffers(&file)?;
+    let Ok(device) = std::fs::canonicalize(dest_path) else { return Err(BurnError::Eject(format!("{} not found", dest_path))) };
+    let sys = Path::new("/sys/class/block").join(device.file_name().unwrap_or_default());
+    // SCSI peripheral type 5 is a CD/DVD drive.
+    if read_sys(sys.join("device/type")).as_deref() == Some("5") {
+        log::info!("opening the tray of {}", dest_path);
+        if unsafe { libc::ioctl(file.as_raw_fd(), CDROMEJECT) } != 0 {
+            return Err(BurnError::Eject(std::io::Error::last_os_error().to_string()));
+        }
+        return Ok(());
+    }
+    drop(file);
+    if crate::format::has_tool("udisksctl") {
+        log::info!("powering off {} through udisksctl", dest_path);
+        let output = std::process::Command::new("udisksctl").args(["power-off", "-b", dest_path]).output()?;
+        if !output.status.success() {
+            return Err(BurnError::Eject(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+        }
+        return Ok(());
+    }
+    log::info!("detaching {} from the kernel", dest_path);
+    std::fs::write(sys.join("device/delete"), "1").map_err(|e| BurnError::Eject(e.to_string()))
+}
+
+/// Ejects the drive once it's written, through diskutil.
+#[cfg(target_os = "macos")]
+pub fn eject(dest_path: &str) -> Result<(), BurnError> {
+    let output = std::process::Command::new("diskutil").args(["eject", dest_path]).output()?;
+    if !output.status.success() {
+        return Err(BurnError::Eject(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+    }
+    Ok(())
+}
+
+/// Ejects the drive once it's written. The volumes on it are already locked and dismounted.
+#[cfg(windows)]
+pub fn eject(dest_path: &str) -> Result<(), BurnError> {
+    use windows_sys::Win32::System::Ioctl::IOCTL_STORAGE_EJECT_MEDIA;
+    let file = open_device(dest_path)?;
+    ioctl(&file, IOCTL_STORAGE_EJECT_MEDIA, None::<&()>, None::<&mut ()>).map_err(|e| BurnError::Eject(e.to_string()))
+}
+
+/// Ejecting isn't supported here yet.
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+pub fn eject(_dest_path: &str) -> Result<(), BurnError> {
+    Err(BurnError::Eject("not supported on this system".to_string()))
+}
+
+/// Path of partition `n` on the drive at `dest`. Drives whose name ends in a digit
+/// (`/dev/nvme0n1`, `/dev/mmcblk0`, `/dev/loop0`) get a `p` before the number, macOS numbers slices with `s`.
+/// Partition 0 is the whole drive, for drives without a partition table.
+pub fn partition_path(dest: &str, n: u32) -> String {
+    if n == 0 {
+        dest.to_string()
+    } else {
+        format!("{}{}{}", dest, partition_separator(dest, cfg!(target_os = "macos")), n)
+    }
+}
+
+/// What goes between the drive's name and a partition number, see [`partition_path`]. `slices` is for macOS.
+fn partition_separator(dest: &str, slices: bool) -> &'static str {
+    if slices {
+        "s"
+    } else if dest.ends_with(|c: char| c.is_ascii_digit()) {
+        "p"
+    } else {
+        ""
+    }
+}
+
+/// Checks whether the device `name` is the drive at `dest` or one of its partitions, as named by [`partition_path`]:
+/// `/dev/sdb1` is on `/dev/sdb` but `/dev/sdba` isn't, and `/dev/loop10` isn't on `/dev/loop1`.
+pub fn is_on_drive(name: &str, dest: &str) -> bool {
+    is_on_drive_named(name, dest, cfg!(target_os = "macos"))
+}
+
+fn is_on_drive_named(name: &str, dest: &str, slices: bool) -> bool {
+    let Some(rest) = name.strip_prefix(dest) else { return false };
+    rest.is_empty() || rest.strip_prefix(partition_separator(dest, slices))
+        .is_some_and(|number| !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// Opens a drive or partition for reading and writing.
+pub fn open_device(path: &str) -> Result<File, BurnError> {
+    OpenOptions::new().read(true).write(true).open(path).map_err(|e| open_error(path, e))
+}
+
+/// Opens a drive for writing around the page cache (O_DIRECT).
+/// Writes then have to be whole sectors, from sector-aligned memory.
+#[cfg(target_os = "linux")]
+pub fn open_device_direct(path: &str) -> Result<File, BurnError> {
+    use std::os::unix::fs::OpenOptionsExt;
+    OpenOptions::new().read(true).write(true).custom_flags(libc::O_DIRECT).open(path).map_err(|e| open_error(path, e))
+}
+
+/// Opens a drive for writing around the cache (FILE_FLAG_NO_BUFFERING).
+/// Writes then have to come from sector-aligned memory.
+#[cfg(windows)]
+pub fn open_device_direct(path: &str) -> Result<File, BurnError> {
+    use windows_sys::Win32::Storage::FileSystem::{FILE_FLAG_NO_BUFFERING, FILE_FLAG_WRITE_THROUGH};
+    OpenOptions::new().read(true).write(true).custom_flags(FILE_FLAG_NO_BUFFERING | FILE_FLAG_WRITE_THROUGH).open(path).map_err(|e| open_error(path, e))
+}
+
+/// O_DIRECT is Linux only, elsewhere the drive is opened normally.
+#[cfg(not(any(target_os = "linux", windows)))]
+pub fn open_device_direct(path: &str) -> Result<File, BurnError> {
+    open_device(path)
+}
+
+/// Turns O_DIRECT on for a drive that was opened normally.
+#[cfg(target_os = "linux")]
+pub fn set_direct(file: &File) -> std::io::Result<()> {
+    let flags = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GETFL) };
+    if flags < 0 || unsafe { libc::fcntl(file.as_raw_fd(), libc::F_SETFL, flags | libc::O_DIRECT) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// O_DIRECT is Linux only, and Windows can't change how an open handle buffers.
+#[cfg(not(target_os = "linux"))]
+pub fn set_direct(_file: &File) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Turns O_DIRECT back off, for a last write that isn't a whole sector.
+#[cfg(target_os = "linux")]
+pub fn clear_direct(file: &File) -> std::io::Result<()> {
+    let flags = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GETFL) };
+    if flags < 0 || unsafe { libc::fcntl(file.as_raw_fd(), libc::F_SETFL, flags & !libc::O_DIRECT) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Turns O_DIRECT back off. Nothing to do outside Linux.
+/// Windows can't turn it off, but only ever takes whole sectors from drives anyway.
+#[cfg(not(target_os = "linux"))]
+pub fn clear_direct(_file: &File) -> std::io::Result<()> {
+    Ok(())
+}
+
+fn open_error(path: &str, e: std::io::Error) -> BurnError {
+    match e.kind() {
+        ErrorKind::PermissionDenied => BurnError::PermissionDenied(path.to_string()),
+        // Card readers without a card, and empty optical drives
+        #[cfg(target_os = "linux")]
+        _ if e.raw_os_error() == Some(libc::ENOMEDIUM) => BurnError::NoMedium(path.to_string()),
+        _ => BurnError::Io(e),
+    }
+}
+
+/// Checks that there is something in the drive at `path`. An empty card reader still shows up
+/// as a block device, it just can't be opened (Linux) or is 0 bytes big.
+pub fn check_medium(path: &str) -> Result<(), BurnError> {
+    let file = File::open(path).map_err(|e| open_error(path, e))?;
+    if device_size(&file)? == 0 {
+        return Err(BurnError::NoMedium(path.to_string()));
+    }
+    Ok(())
+}
+
+/// Checks whether `path` is a block (disk) device.
+#[cfg(unix)]
+pub fn is_block(path: &str) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+    match std::fs::metadata(path) {
+        Ok(metadata) => metadata.file_type().is_block_device(),
+        Err(_) => false,
+    }
+}
+
+/// Checks whether `path` is a physical drive (`\\.\PhysicalDriveN`) that exists.
+#[cfg(windows)]
+pub fn is_block(path: &str) -> bool {
+    disk_number(path).is_some() && open_query(path).is_ok()
+}
+
+/// Checks whether `path` is a regular file, a disk image to burn into instead of a drive.
+pub fn is_image_file(path: &str) -> bool {
+    std::fs::metadata(path).is_ok_and(|metadata| metadata.is_file())
+}
+
+/// Creates (or truncates) the image file at `path` and makes it `size` bytes long. The file starts out sparse.
+pub fn create_image(path: &str, size: u64) -> Result<(), BurnError> {
+    let file = OpenOptions::new().write(true).create(true).truncate(true).open(path)?;
+    file.set_len(size)?;
+    Ok(())
+}
+
+/// Unmounts every mounted partition that lives on the `dest_path` drive.
+#[cfg(unix)]
+pub fn unmount_partitions(dest_path: &str) -> Result<(), BurnError> {
+    let disks = Disks::new_with_refreshed_list();
+    for disk in disks.list() {
+        let name = disk.name().to_string_lossy();
+        if !is_on_drive(&name, dest_path) {
+            continue;
+        }
+        log::info!("unmounting {} from {}", name, disk.mount_point().display());
+        let mount_point = CString::new(disk.mount_point().as_os_str().as_bytes())
+            .map_err(|e| BurnError::Io(std::io::Error::new(ErrorKind::InvalidInput, e)))?;
+        #[cfg(target_os = "linux")]
+        let ret = unsafe { libc::umount2(mount_point.as_ptr(), 0) };
+        #[cfg(not(target_os = "linux"))]
+        let ret = unsafe { libc::unmount(mount_point.as_ptr(), 0) };
+        if ret != 0 {
+            let err = std::io::Error::last_os_error();
+            match err.raw_os_error() {
+                // Already gone (e.g. listed twice).
+                Some(libc::EINVAL) => continue,
+                Some(libc::EBUSY) => {
+                    return Err(BurnError::Busy(format!("{} is busy (mounted on {}), close anything using it and try again", name, disk.mount_point().display())));
+                }
+                _ => return Err(err.into()),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Locks and dismounts every volume that lives on the `dest_path` drive, Windows won't let us
+/// write over a mounted one. The locks are held until we exit so nothing mounts them again meanwhile.
+#[cfg(windows)]
+pub fn unmount_partitions(dest_path: &str) -> Result<(), BurnError> {
+    use windows_sys::Win32::System::Ioctl::{FSCTL_DISMOUNT_VOLUME, FSCTL_LOCK_VOLUME};
+    let Some(disk) = disk_number(dest_path) else { return Ok(()) };
+    for letter in drive_letters() {
+        if !volume_disks(&letter).contains(&disk) {
+            continue;
+        }
+        let volume = OpenOptions::new().read(true).write(true).open(format!(r"\\.\{}", letter))?;
+        if ioctl(&volume, FSCTL_LOCK_VOLUME, None::<&()>, None::<&mut ()>).is_err() {
+            return Err(BurnError::Busy(format!("{} is busy (mounted on {}\\), close anything using it and try again", dest_path, letter)));
+        }
+        ioctl(&volume, FSCTL_DISMOUNT_VOLUME, None::<&()>, None::<&mut ()>)?;
+        // Closing the handle would drop the lock.
+        std::mem::forget(volume);
+    }
+    Ok(())
+}
+
+/// A temporary mount point, unmounted and removed again when dropped so errors don't leave it behind.
+#[cfg(target_os = "linux")]
+struct TempMount {
+    path: PathBuf,
+    mounted: bool,
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for TempMount {
+    fn drop(&mut self) {
+        if self.mounted && let Ok(path) = CString::new(self.path.as_os_str().as_bytes()) {
+            // Lazily, so a stuck unmount can't keep the directory from going away.
+            if unsafe { libc::umount2(path.as_ptr(), libc::MNT_DETACH) } != 0 {
+                log::warn!("could not unmount {}: {}", self.path.display(), std::io::Error::last_os_error());
+            }
+        }
+        let _ = std::fs::remove_dir(&self.path);
+    }
+}
+
+/// Mounts `partition` read-only on a temporary directory, lists its root and unmounts it again,
+/// to catch a partition that got its bytes but not a filesystem the kernel understands.
+/// Like mount(8), every filesystem type the kernel has is tried in turn.
+/// Returns the type that mounted and how many entries the root has.
+#[cfg(target_os = "linux")]
+pub fn mount_check(partition: &str) -> Result<(String, usize), BurnError> {
+    let source = CString::new(partition).map_err(|e| BurnError::Io(std::io::Error::new(ErrorKind::InvalidInput, e)))?;
+    // Filesystems that need a device, the others are marked nodev.
+    let types: Vec<String> = std::fs::read_to_string("/proc/filesystems")?
+        .lines()
+        .filter(|line| !line.starts_with("nodev"))
+        .map(|line| line.trim().to_string())
+        .collect();
+    let mut mount = TempMount { path: std::env::temp_dir().join(format!("burn-mount-check-{}", std::process::id())), mounted: false };
+    std::fs::create_dir(&mount.path)?;
+    let target = CString::new(mount.path.as_os_str().as_bytes()).map_err(|e| BurnError::Io(std::io::Error::new(ErrorKind::InvalidInput, e)))?;
+    let mut fstype = None;
+    for name in types {
+        let Ok(c_name) = CString::new(name.as_str()) else { continue };
+        let ret = unsafe { libc::mount(source.as_ptr(), target.as_ptr(), c_name.as_ptr(), libc::MS_RDONLY, std::ptr::null()) };
+        if ret == 0 {
+            fstype = Some(name);
+            break;
+        }
+        log::debug!("mounting {} as {}: {}", partition, name, std::io::Error::last_os_error());
+    }
+    let Some(fstype) = fstype else {
+        return Err(BurnError::MountCheck(format!("{} has no filesystem the kernel can mount", partition)));
+    };
+    mount.mounted = true;
+    log::info!("mounted {} as {} on {}", partition, fstype, mount.path.display());
+    let entries = std::fs::read_dir(&mount.path).map_err(|e| BurnError::MountCheck(format!("could not list {}: {}", partition, e)))?.count();
+    Ok((fstype, entries))
+}
+
+/// Mounting a partition to check it isn't supported here yet.
+#[cfg(not(target_os = "linux"))]
+pub fn mount_check(_partition: &str) -> Result<(String, usize), BurnError> {
+    Err(BurnError::MountCheck("not supported on this system".to_string()))
+}
+
+/// Returns `device` plus everything it is built on: the whole disk of a partition and,
+/// for device-mapper/md devices (LVM, LUKS, RAID), the devices listed in `slaves/`.
+#[cfg(target_os = "linux")]
+fn backing_devices(device: &Path) -> Vec<PathBuf> {
+    let mut found = Vec::new();
+    let Ok(device) = std::fs::canonicalize(device) else { return found };
+    let Some(name) = device.file_name() else { return found };
+    let Ok(sys) = std::fs::canonicalize(Path::new("/sys/class/block").join(name)) else {
+        found.push(device);
+        return found;
+    };
+    // A partition lives inside its disk's directory: /sys/devices/.../block/sda/sda2
+    if sys.join("partition").exists() && let Some(disk) = sys.parent().and_then(|p| p.file_name()) {
+        found.push(Path::new("/dev").join(disk));
+    }
+    if let Ok(slaves) = std::fs::read_dir(sys.join("slaves")) {
+        for slave in slaves.flatten() {
+            found.extend(backing_devices(&Path::new("/dev").join(slave.file_name())));
+        }
+    }
+    found.push(device);
+    found
+}
+
+/// Returns `device` plus everything it is built on.
+/// Without sysfs we can only go by name, so this is just the device itself.
+#[cfg(all(unix, not(target_os = "linux")))]
+fn backing_devices(device: &Path) -> Vec<PathBuf> {
+    vec![std::fs::canonicalize(device).unwrap_or_else(|_| device.to_path_buf())]
+}
+
+/// Checks whether `dest_path` is (or is part of) the drive holding `/` or `/boot`.
+#[cfg(unix)]
+pub fn is_system_disk(dest_path: &str) -> bool {
+    let dest = std::fs::canonicalize(dest_path).unwrap_or_else(|_| PathBuf::from(dest_path));
+    let disks = Disks::new_with_refreshed_list();
+    disks.list().iter()
+        .filter(|d| d.mount_point() == Path::new("/") || d.mount_point() == Path::new("/boot"))
+        .flat_map(|d| backing_devices(Path::new(d.name())))
+        .any(|backing| {
+            // Elsewhere partitions are only recognizable by their name (disk0 -> disk0s1).
+            backing == dest || (!cfg!(target_os = "linux") && is_on_drive(&backing.to_string_lossy(), &dest.to_string_lossy()))
+        })
+}
+
+/// Checks whether `dest_path` is the drive Windows runs from.
+#[cfg(windows)]
+pub fn is_system_disk(dest_path: &str) -> bool {
+    let Some(disk) = disk_number(dest_path) else { return false };
+    let system = std::env::var("SystemDrive").unwrap_or_else(|_| "C:".to_string());
+    volume_disks(&system).contains(&disk)
+}
+
+/// This function checks the permissions to read the source file and write to the destination file.
+/// Each comes back with why it couldn't be opened, a missing permission is only one reason.
+pub fn check_permissions(file_path: &str, dest_path: &str) -> (Result<(), BurnError>, Result<(), BurnError>) {
+    // Check read permission for the file and write permissions for the destination
+    let file_perm = OpenOptions::new().read(true).open(file_path).map(drop).map_err(|e| open_error(file_path, e));
+    let dest_perm = OpenOptions::new().write(true).open(dest_path).map(drop).map_err(|e| open_error(dest_path, e));
+    (file_perm, dest_perm)
+}
+
+/// Whether we run as root, which writing to drives takes on most systems.
+#[cfg(unix)]
+pub fn is_root() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+/// Whether we run elevated. Not checked on Windows, where a denied drive almost always means we aren't.
+#[cfg(windows)]
+pub fn is_root() -> bool {
+    false
+}
+
+/// Checks whether `dest_path` is a removable drive (or a partition on one).
+/// USB drives count even when they don't set the removable flag, as most USB hard drives and card readers don't.
+#[cfg(target_os = "linux")]
+pub fn is_removable(dest_path: &str) -> bool {
+    let Ok(device) = std::fs::canonicalize(dest_path) else { return false };
+    let Some(name) = device.file_name() else { return false };
+    let Ok(mut sys) = std::fs::canonicalize(Path::new("/sys/class/block").join(name)) else { return false };
+    if sys.join("partition").exists() && let Some(disk) = sys.parent() {
+        sys = disk.to_path_buf();
+    }
+    read_sys(sys.join("removable")).as_deref() == Some("1") || sys.components().any(|c| c.as_os_str().to_string_lossy().starts_with("usb"))
+}
+
+/// Checks whether `dest_path` is a removable drive, USB and SD card drives count too.
+#[cfg(windows)]
+pub fn is_removable(dest_path: &str) -> bool {
+    open_query(dest_path).and_then(|file| storage_descriptor(&file)).is_ok_and(|(removable, _, _)| removable)
+}
+
+/// Checks whether `dest_path` is a removable drive.
+/// Without sysfs only drives with a mounted filesystem are known, anything else counts as internal.
+#[cfg(all(unix, not(target_os = "linux")))]
+pub fn is_removable(dest_path: &str) -> bool {
+    let disks = Disks::new_with_refreshed_list();
+    disks.list().iter()
+        .filter(|d| is_on_drive(&d.name().to_string_lossy(), dest_path))
+        .any(|d| d.is_removable())
+}
+
+/// Size in bytes of the erase blocks of the SD/MMC card at `dest_path` (or the card a partition is on),
+/// from `preferred_erase_size` in sysfs. `None` for anything that isn't an SD/MMC card, or doesn't say.
+#[cfg(target_os = "linux")]
+pub fn erase_block_size(dest_path: &str) -> Option<u64> {
+    let device = std::fs::canonicalize(dest_path).ok()?;
+    let name = device.file_name()?;
+    if !name.to_string_lossy().starts_with("mmcblk") {
+        return None;
+    }
+    let mut sys = std::fs::canonicalize(Path::new("/sys/class/block").join(name)).ok()?;
+    if sys.join("partition").exists() && let Some(disk) = sys.parent() {
+        sys = disk.to_path_buf();
+    }
+    read_sys(sys.join("device/preferred_erase_size"))?.parse().ok().filter(|&size| size > 0)
+}
+
+/// Size in bytes of the erase blocks of an SD/MMC card. Only sysfs tells, so it's unknown here.
+#[cfg(not(target_os = "linux"))]
+pub fn erase_block_size(_dest_path: &str) -> Option<u64> {
+    None
+}
+
+/// If `path` is a partition, returns the drive it is on (`/dev/sdb1` -> `/dev/sdb`).
+#[cfg(target_os = "linux")]
+pub fn whole_disk(path: &str) -> Option<String> {
+    let device = std::fs::canonicalize(path).ok()?;
+    let sys = std::fs::canonicalize(Path::new("/sys/class/block").join(device.file_name()?)).ok()?;
+    if !sys.join("partition").exists() {
+        return None;
+    }
+    let disk = sys.parent()?.file_name()?;
+    Some(Path::new("/dev").join(disk).to_string_lossy().into_owned())
+}
+
+/// If `path` is a partition, returns the drive it is on.
+/// Goes by name: `disk2s1` on macOS, `da0p1` or `da0s1` on the BSDs.
+#[cfg(all(unix, not(target_os = "linux")))]
+pub fn whole_disk(path: &str) -> Option<String> {
+    let rest = path.trim_end_matches(|c: char| c.is_ascii_digit());
+    if rest.len() == path.len() {
+        return None;
+    }
+    let disk = rest.strip_suffix(['s', 'p'])?;
+    disk.ends_with(|c: char| c.is_ascii_digit()).then(|| disk.to_string())
+}
+
+/// Drives are opened as `\\.\PhysicalDriveN`, which is always a whole drive.
+#[cfg(windows)]
+pub fn whole_disk(_path: &str) -> Option<String> {
+    None
+}
+
+/// A drive that can be picked as the destination.
+pub struct Drive {
+    /// Device node, e.g. `/dev/sdb`.
+    pub path: String,
+    /// Vendor and model as reported by the drive.
+    pub model: String,
+    /// Serial number, empty if the drive doesn't report one.
+    pub serial: String,
+    /// Capacity in bytes.
+    pub size: u64,
+    pub removable: bool,
+    /// Where the drive's partitions are mounted.
+    pub mount_points: Vec<String>,
+}
+
+/// Reads a small sysfs attribute, trimmed.
+#[cfg(target_os = "linux")]
+pub(crate) fn read_sys(path: impl AsRef<Path>) -> Option<String> {
+    std::fs::read_to_string(path).ok().map(|s| s.trim().to_string())
+}
+
+/// Describes the drive behind a `/sys/block` entry.
+#[cfg(target_os = "linux")]
+fn sys_drive(sys: &Path, disks: &Disks) -> Drive {
+    let name = sys.file_name().unwrap_or_default().to_string_lossy().to_string();
+    let path = format!("/dev/{}", name);
+    let vendor = read_sys(sys.join("device/vendor")).unwrap_or_default();
+    let model = read_sys(sys.join("device/model")).unwrap_or_default();
+    // NVMe and MMC drives have a serial attribute, for the rest only udev has asked the drive.
+    let serial = read_sys(sys.join("device/serial"))
+        .or_else(|| {
+            let dev = read_sys(sys.join("dev"))?;
+            let data = std::fs::read_to_string(format!("/run/udev/data/b{}", dev)).ok()?;
+            data.lines().find_map(|line| line.strip_prefix("E:ID_SERIAL_SHORT=")).map(str::to_string)
+        })
+        .unwrap_or_default();
+    // size is always counted in 512 byte sectors, whatever the drive uses.
+    let size = read_sys(sys.join("size")).and_then(|s| s.parse::<u64>().ok()).unwrap_or(0) * 512;
+    let removable = read_sys(sys.join("removable")).as_deref() == Some("1");
+    let mount_points = disks.list().iter()
+        .filter(|d| is_on_drive(&d.name().to_string_lossy(), &path))
+        .map(|d| d.mount_point().display().to_string())
+        .collect();
+    Drive { path, model: format!("{} {}", vendor, model).trim().to_string(), serial, size, removable, mount_points }
+}
+
+/// Lists the physical drives attached to the system.
+#[cfg(target_os = "linux")]
+pub fn list_drives() -> Vec<Drive> {
+    let disks = Disks::new_with_refreshed_list();
+    let mut drives = Vec::new();
+    let Ok(entries) = std::fs::read_dir("/sys/block") else { return drives };
+    for entry in entries.flatten() {
+        // Only real hardware has a device/ link, this skips loop, ram, zram and dm devices.
+        if !entry.path().join("device").exists() {
+            continue;
+        }
+        drives.push(sys_drive(&entry.path(), &disks));
+    }
+    drives.sort_by(|a, b| a.path.cmp(&b.path));
+    drives
+}
+
+/// Describes the drive at `dest_path`, whatever kind of block device it is.
+#[cfg(target_os = "linux")]
+pub fn drive_info(dest_path: &str) -> Option<Drive> {
+    let device = std::fs::canonicalize(dest_path).ok()?;
+    let sys = std::fs::canonicalize(Path::new("/sys/class/block").join(device.file_name()?)).ok()?;
+    let mut drive = sys_drive(&sys, &Disks::new_with_refreshed_list());
+    drive.path = dest_path.to_string();
+    Some(drive)
+}
+
+/// Lists the physical drives attached to the system.
+#[cfg(windows)]
+pub fn list_drives() -> Vec<Drive> {
+    let letters: Vec<(String, Vec<u32>)> = drive_letters().into_iter()
+        .map(|letter| { let disks = volume_disks(&letter); (letter, disks) })
+        .collect();
+    let mut drives = Vec::new();
+    // Drive numbers can have gaps once something is unplugged, so try them all.
+    for number in 0..64 {
+        let path = format!(r"\\.\PhysicalDrive{}", number);
+        let Ok(file) = open_query(&path) else { continue };
+        let (removable, model, serial) = storage_descriptor(&file).unwrap_or_default();
+        let size = device_size(&file).unwrap_or(0);
+        let mount_points = letters.iter()
+            .filter(|(_, disks)| disks.contains(&number))
+            .map(|(letter, _)| format!("{}\\", letter))
+            .collect();
+        drives.push(Drive { path, model, serial, size, removable, mount_points });
+    }
+    drives
+}
+
+/// Describes the drive at `dest_path`.
+#[cfg(windows)]
+pub fn drive_info(dest_path: &str) -> Option<Drive> {
+    let number = disk_number(dest_path)?;
+    list_drives().into_iter().find(|d| disk_number(&d.path) == Some(number))
+}
+
+/// Lists the drives attached to the system.
+/// Without sysfs only drives with a mounted filesystem can be found.
+#[cfg(all(unix, not(target_os = "linux")))]
+pub fn list_drives() -> Vec<Drive> {
+    let disks = Disks::new_with_refreshed_list();
+    disks.list().iter()
+        .map(|d| Drive {
+            path: d.name().to_string_lossy().to_string(),
+            model: String::new(),
+            serial: String::new(),
+            size: d.total_space(),
+            removable: d.is_removable(),
+            mount_points: vec![d.mount_point().display().to_string()],
+        })
+        .collect()
+}
+
+/// Describes the drive at `dest_path`. Only the size is known for drives without a mounted filesystem.
+#[cfg(all(unix, not(target_os = "linux")))]
+pub fn drive_info(dest_path: &str) -> Option<Drive> {
+    if let Some(drive) = list_drives().into_iter().find(|d| d.path == dest_path) {
+        return Some(drive);
+    }
+    let size = device_size(&File::open(dest_path).ok()?).ok()?;
+    Some(Drive { path: dest_path.to_string(), model: String::new(), serial: String::new(), size, removable: is_removable(dest_path), mount_points: Vec::new() })
+}
+
+/// Reads an allowlist of drives: one serial number or device path per line, `#` starts a comment.
+pub fn read_allowlist(path: &str) -> Result<Vec<String>, BurnError> {
+    let list = std::fs::read_to_string(path)?;
+    Ok(list.lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// Whether the drive at `dest_path` is on the allowlist, by its serial number or its path.
+/// Paths are compared resolved, so `/dev/disk/by-id/...` entries match the `/dev/sdX` they point to.
+pub fn is_allowed(dest_path: &str, allowlist: &[String]) -> bool {
+    let serial = drive_info(dest_path).map(|d| d.serial).unwrap_or_default();
+    let resolved = std::fs::canonicalize(dest_path).ok();
+    allowlist.iter().any(|entry| {
+        (!serial.is_empty() && entry.eq_ignore_ascii_case(&serial))
+            || entry == dest_path
+            || (resolved.is_some() && std::fs::canonicalize(entry).ok() == resolved)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(not(target_os = "macos"))]
+    #[test]
+    fn partitions_are_numbered_after_the_drive() {
+        assert_eq!(partition_path("/dev/sdb", 1), "/dev/sdb1");
+        assert_eq!(partition_path("/dev/sdb", 12), "/dev/sdb12");
+        assert_eq!(partition_path("/dev/nvme0n1", 1), "/dev/nvme0n1p1");
+        assert_eq!(partition_path("/dev/mmcblk0", 2), "/dev/mmcblk0p2");
+        assert_eq!(partition_path("/dev/loop1", 1), "/dev/loop1p1");
+        assert_eq!(partition_path("/dev/sdb", 0), "/dev/sdb");
+        assert_eq!(partition_path("/dev/nvme0n1", 0), "/dev/nvme0n1");
+    }
+
+    #[cfg(target_os = "macos")]
+    #[test]
+    fn partitions_are_numbered_after_the_drive() {
+        assert_eq!(partition_path("/dev/disk2", 1), "/dev/disk2s1");
+        assert_eq!(partition_path("/dev/rdisk2", 2), "/dev/rdisk2s2");
+        assert_eq!(partition_path("/dev/disk2", 0), "/dev/disk2");
+    }
+
+    #[test]
+    fn macos_numbers_slices_with_s() {
+        assert_eq!(partition_separator("/dev/disk2", true), "s");
+        assert_eq!(partition_separator("/dev/disk2", false), "p");
+        assert_eq!(partition_separator("/dev/sdb", false), "");
+    }
+
+    #[test]
+    fn only_the_drive_and_its_partitions_are_on_it() {
+        for (name, dest) in [
+            ("/dev/sdb", "/dev/sdb"), ("/dev/sdb1", "/dev/sdb"), ("/dev/sdb10", "/dev/sdb"),
+            ("/dev/nvme0n1", "/dev/nvme0n1"), ("/dev/nvme0n1p1", "/dev/nvme0n1"), ("/dev/nvme0n1p12", "/dev/nvme0n1"),
+            ("/dev/loop1p2", "/dev/loop1"), ("/dev/mmcblk0p1", "/dev/mmcblk0"),
+        ] {
+            assert!(is_on_drive_named(name, dest, false), "{} is on {}", name, dest);
+        }
+        for (name, dest) in [
+            ("/dev/sdba", "/dev/sdb"), ("/dev/sdb1", "/dev/sdc"), ("/dev/sdb10", "/dev/sdb1"), ("/dev/sdbp1", "/dev/sdb"),
+            ("/dev/nvme0n10", "/dev/nvme0n1"), ("/dev/nvme0n1p", "/dev/nvme0n1"), ("/dev/loop10", "/dev/loop1"), ("/dev/sd", "/dev/sdb"),
+        ] {
+            assert!(!is_on_drive_named(name, dest, false), "{} is not on {}", name, dest);
+        }
+    }
+
+    #[test]
+    fn macos_slices_are_on_their_disk() {
+        assert!(is_on_drive_named("/dev/disk2", "/dev/disk2", true));
+        assert!(is_on_drive_named("/dev/disk2s1", "/dev/disk2", true));
+        assert!(!is_on_drive_named("/dev/disk20", "/dev/disk2", true));
+        assert!(!is_on_drive_named("/dev/disk20s1", "/dev/disk2", true));
+    }
+}
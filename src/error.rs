@@ -0,0 +1,136 @@
+//! The error type shared by everything in the library.
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Everything that can go wrong while burning an image.
+#[derive(Debug)]
+pub enum BurnError {
+    /// Not allowed to open the given path (usually: not running as root).
+    PermissionDenied(String),
+    /// The given path is not a block (disk) device.
+    NotBlockDevice(String),
+    /// The given drive holds the running system.
+    SystemDisk(String),
+    /// Something still uses the given partition, so it can't be unmounted.
+    Busy(String),
+    /// Creating the partition table failed.
+    PartitionTable(String),
+    /// Formatting the volume failed.
+    Format(String),
+    /// Copying an `--add-file` onto the new volume failed.
+    AddFile(String),
+    /// The files of the image can't go onto the new volume: it's no uncompressed ISO, or they don't fit on FAT.
+    CopyFiles(String),
+    /// Less than `--min-free` is left on the volume: bytes asked for, bytes free.
+    MinFree(u64, u64),
+    /// An external program we need (e.g. `mkfs.ntfs`) isn't installed.
+    MissingTool(String),
+    /// `make_fat` was asked for a FAT width it can't make.
+    UnsupportedFatType(u8),
+    /// The requested cluster size (in bytes) can't be used.
+    InvalidClusterSize(u32),
+    /// The requested write block size (in bytes) is out of range.
+    InvalidBlockSize(usize),
+    /// The requested number of write buffers is out of range.
+    InvalidBuffers(usize),
+    /// The destination is too small: bytes needed, bytes available.
+    DeviceTooSmall(u64, u64),
+    /// The volume (in bytes) is too large for the given FAT width.
+    VolumeTooLarge(u8, u64),
+    /// The drive didn't store what we wrote: flushing it failed, or it reads back differently.
+    WriteFailed(String),
+    /// A `--seek` or `--skip` offset that can't be used.
+    Offset(String),
+    /// The write made no progress for this many seconds, the drive is probably wedged.
+    Timeout(u64),
+    /// The given file is named like an ISO but has no ISO9660 volume descriptor.
+    NotIso(String),
+    /// The first partition didn't mount after the burn.
+    MountCheck(String),
+    /// The drive was written but couldn't be ejected.
+    Eject(String),
+    /// The given drive has nothing in it, like a card reader without a card.
+    NoMedium(String),
+    /// The user hit Ctrl-C, the drive was left half-written.
+    Aborted,
+    /// Reading or writing failed.
+    Io(io::Error),
+}
+
+impl fmt::Display for BurnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BurnError::PermissionDenied(path) => write!(f, "permission denied on {}", path),
+            BurnError::NotBlockDevice(path) => write!(f, "{} is not a block (disk) device", path),
+            BurnError::SystemDisk(path) => write!(f, "{} holds the running system (/ or /boot)", path),
+            BurnError::Busy(msg) => write!(f, "{}", msg),
+            BurnError::PartitionTable(msg) => write!(f, "could not create the partition table: {}", msg),
+            BurnError::Format(msg) => write!(f, "could not format the volume: {}", msg),
+            BurnError::AddFile(msg) => write!(f, "could not add the file to the volume: {}", msg),
+            BurnError::CopyFiles(msg) => write!(f, "can't copy the files of the image: {}", msg),
+            BurnError::MinFree(wanted, free) => write!(f, "only {} bytes are free on the volume, {} were asked for", free, wanted),
+            BurnError::MissingTool(tool) => write!(f, "{} is not installed", tool),
+            BurnError::UnsupportedFatType(fat) => write!(f, "FAT{} is not supported", fat),
+            BurnError::InvalidClusterSize(size) => write!(f, "invalid cluster size {} (expected a power of two from {} to {} bytes, at least one sector)", size, crate::format::MIN_CLUSTER_SIZE, crate::format::MAX_CLUSTER_SIZE),
+            BurnError::InvalidBlockSize(size) => write!(f, "invalid block size {} (expected {} to {} bytes)", size, crate::image::MIN_BLOCK_SIZE, crate::image::MAX_BLOCK_SIZE),
+            BurnError::InvalidBuffers(buffers) => write!(f, "invalid buffer count {} (expected 1 to {})", buffers, crate::image::MAX_BUFFERS),
+            BurnError::DeviceTooSmall(needed, available) => write!(f, "the destination is too small ({} bytes needed, {} available)", needed, available),
+            BurnError::VolumeTooLarge(fat, size) => write!(f, "the volume is {} bytes, too large for FAT{}", size, fat),
+            BurnError::WriteFailed(msg) => write!(f, "device write failed: {}", msg),
+            BurnError::Offset(msg) => write!(f, "invalid offset: {}", msg),
+            BurnError::Timeout(secs) => write!(f, "the drive made no progress for {} seconds", secs),
+            BurnError::NotIso(path) => write!(f, "{} is not an ISO9660 image (no CD001 volume descriptor at sector 16)", path),
+            BurnError::MountCheck(msg) => write!(f, "could not mount the partition: {}", msg),
+            BurnError::Eject(msg) => write!(f, "could not eject the drive: {}", msg),
+            BurnError::NoMedium(path) => write!(f, "there is no medium in {}", path),
+            BurnError::Aborted => write!(f, "aborted by the user"),
+            BurnError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for BurnError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BurnError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for BurnError {
+    fn from(e: io::Error) -> Self {
+        BurnError::Io(e)
+    }
+}
+
+impl From<gpt::GptError> for BurnError {
+    fn from(e: gpt::GptError) -> Self {
+        BurnError::PartitionTable(e.to_string())
+    }
+}
+
+impl From<gpt::mbr::MBRError> for BurnError {
+    fn from(e: gpt::mbr::MBRError) -> Self {
+        BurnError::PartitionTable(e.to_string())
+    }
+}
+
+impl From<mbrman::Error> for BurnError {
+    fn from(e: mbrman::Error) -> Self {
+        BurnError::PartitionTable(e.to_string())
+    }
+}
+
+impl From<exfat_fs::error::ExfatError> for BurnError {
+    fn from(e: exfat_fs::error::ExfatError) -> Self {
+        BurnError::Format(e.to_string())
+    }
+}
+
+impl From<exfat_fs::format::FormatVolumeOptionsBuilderError> for BurnError {
+    fn from(e: exfat_fs::format::FormatVolumeOptionsBuilderError) -> Self {
+        BurnError::Format(e.to_string())
+    }
+}
@@ -0,0 +1,518 @@
+//! Filesystem creation on the freshly made partition.
+use exfat_fs::format::{Exfat, FormatVolumeOptionsBuilder, Label};
+use fatfs::{format_volume, FatType, FormatVolumeOptions};
+use fatfs::FatType::{Fat12, Fat16, Fat32};
+use crate::device::{device_size, is_image_file, logical_sector_size, open_device, partition_path};
+use std::fs::File;
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::process::Command;
+use std::time::Instant;
+use crate::error::BurnError;
+use crate::image::IsoFile;
+use crate::interrupt;
+use crate::progress::{Progress, ProgressSink};
+use crate::table::{partition_range, PERSISTENCE_LABEL};
+use crate::Filesystem;
+
+/// Largest volume FAT12 can address: 4084 clusters of 32 KiB.
+pub const FAT12_MAX_SIZE: u64 = 4084 * 32 * 1024;
+/// Largest volume FAT16 can address: 65524 clusters of 64 KiB.
+pub const FAT16_MAX_SIZE: u64 = 65524 * 64 * 1024;
+/// Largest volume FAT32 can address with 512 byte sectors, its sector count is 32 bits.
+pub const FAT32_MAX_SIZE: u64 = u32::MAX as u64 * 512;
+
+/// Largest volume `filesystem` can be made on, `None` for the ones that fit any drive.
+pub fn max_volume_size(filesystem: Filesystem) -> Option<u64> {
+    match filesystem {
+        Filesystem::Fat12 => Some(FAT12_MAX_SIZE),
+        Filesystem::Fat16 => Some(FAT16_MAX_SIZE),
+        Filesystem::Fat32 => Some(FAT32_MAX_SIZE),
+        _ => None,
+    }
+}
+
+/// Longest volume label FAT can store, in bytes.
+pub const FAT_LABEL_MAX: usize = 11;
+/// Longest volume label NTFS can store, in UTF-16 characters.
+pub const NTFS_LABEL_MAX: usize = 32;
+/// Longest volume label ext4 can store, in bytes.
+pub const EXT4_LABEL_MAX: usize = 16;
+/// Longest volume label exFAT can store, in UTF-16 characters.
+pub const EXFAT_LABEL_MAX: usize = 15;
+
+/// Characters FAT refuses in volume labels, on top of control characters and anything that isn't ASCII.
+const FAT_LABEL_INVALID: &str = "\"*+,./:;<=>?[\\]|";
+/// Characters exFAT refuses in volume labels, the ones Windows refuses in file names.
+const EXFAT_LABEL_INVALID: &str = "\"*/:<>?\\|";
+
+/// Longest label the filesystem can store, in bytes (UTF-16 characters for exFAT and NTFS), see [`label_len`].
+pub fn max_label_len(filesystem: Filesystem) -> usize {
+    match filesystem {
+        Filesystem::Exfat => EXFAT_LABEL_MAX,
+        Filesystem::Ntfs => NTFS_LABEL_MAX,
+        Filesystem::Ext4 => EXT4_LABEL_MAX,
+        _ => FAT_LABEL_MAX,
+    }
+}
+
+/// Length of `label` the way the filesystem counts it: UTF-16 characters for exFAT and NTFS, bytes for the others.
+pub fn label_len(label: &str, filesystem: Filesystem) -> usize {
+    match filesystem {
+        Filesystem::Exfat | Filesystem::Ntfs => label.encode_utf16().count(),
+        _ => label.len(),
+    }
+}
+
+/// Makes a label fit the filesystem: FAT labels are uppercase ASCII, exFAT ones keep their case and spaces,
+/// both lose the characters they refuse. Every label gets cut to [`max_label_len`].
+pub fn fit_label(label: &str, filesystem: Filesystem) -> String {
+    let label: String = match filesystem {
+        Filesystem::Exfat => label.chars().filter(|&c| !c.is_control() && !EXFAT_LABEL_INVALID.contains(c)).collect(),
+        Filesystem::Ntfs | Filesystem::Ext4 => label.to_string(),
+        _ => label.chars().filter(|&c| c.is_ascii() && !c.is_ascii_control() && !FAT_LABEL_INVALID.contains(c)).collect::<String>().to_uppercase(),
+    };
+    let max = max_label_len(filesystem);
+    let mut fitted = String::new();
+    for c in label.chars() {
+        fitted.push(c);
+        if label_len(&fitted, filesystem) > max {
+            fitted.pop();
+            break;
+        }
+    }
+    fitted
+}
+
+/// Smallest FAT cluster size we accept, one 512-byte sector.
+pub const MIN_CLUSTER_SIZE: u32 = 512;
+/// Largest FAT cluster size we accept. 32 KiB is the safer choice, some old implementations stop there.
+pub const MAX_CLUSTER_SIZE: u32 = 64 * 1024;
+
+/// Checks a FAT cluster size before anything touches the disk.
+pub fn check_cluster_size(bytes: u32) -> Result<(), BurnError> {
+    if !bytes.is_power_of_two() || !(MIN_CLUSTER_SIZE..=MAX_CLUSTER_SIZE).contains(&bytes) {
+        return Err(BurnError::InvalidClusterSize(bytes));
+    }
+    Ok(())
+}
+
+/// A partition to format: its own device node, or a stretch of an image file.
+/// Reads, writes and seeks stay within the partition.
+struct Volume {
+    file: File,
+    offset: u64,
+    len: u64,
+}
+
+impl Volume {
+    /// Opens partition `number` of `disk`, the drive (or image file) at `drive_path`.
+    /// Image files share the handle we already have, drives get their partition node opened.
+    /// Partition 0 is the whole drive, which shares the handle too.
+    fn open(disk: &File, drive_path: &str, number: u32) -> Result<Volume, BurnError> {
+        if number == 0 {
+            let mut file = disk.try_clone()?;
+            file.seek(SeekFrom::Start(0))?;
+            let len = device_size(&file)?;
+            log::debug!("{}: {} bytes, no partition table", drive_path, len);
+            return Ok(Volume { file, offset: 0, len });
+        }
+        if is_image_file(drive_path) {
+            let (offset, len) = partition_range(disk, drive_path, number)?;
+            let mut file = disk.try_clone()?;
+            file.seek(SeekFrom::Start(offset))?;
+            log::debug!("partition {} of {}: {} bytes from byte {}", number, drive_path, len, offset);
+            return Ok(Volume { file, offset, len });
+        }
+        let file = open_device(&partition_path(drive_path, number))?;
+        let len = device_size(&file)?;
+        log::debug!("{}: {} bytes", partition_path(drive_path, number), len);
+        Ok(Volume { file, offset: 0, len })
+    }
+
+    /// Bytes left before the end of the partition.
+    fn remaining(&mut self) -> std::io::Result<usize> {
+        let position = self.file.stream_position()? - self.offset;
+        Ok(self.len.saturating_sub(position).min(usize::MAX as u64) as usize)
+    }
+}
+
+impl Read for Volume {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = buf.len().min(self.remaining()?);
+        self.file.read(&mut buf[..n])
+    }
+}
+
+impl Write for Volume {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = buf.len().min(self.remaining()?);
+        self.file.write(&buf[..n])
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Seek for Volume {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(n) => self.len.checked_add_signed(n),
+            SeekFrom::Current(n) => (self.file.stream_position()? - self.offset).checked_add_signed(n),
+        };
+        let Some(target) = target else {
+            return Err(std::io::Error::new(ErrorKind::InvalidInput, "seek before the start of the partition"));
+        };
+        self.file.seek(SeekFrom::Start(self.offset + target))?;
+        Ok(target)
+    }
+}
+
+/// Where an external mkfs tool should put partition `number`: its device node,
+/// or the image file itself plus the partition's byte offset and length.
+/// Partition 0 is the whole drive (or image file).
+fn mkfs_target(disk: &File, drive_path: &str, number: u32) -> Result<(String, Option<(u64, u64)>), BurnError> {
+    if number > 0 && is_image_file(drive_path) {
+        return Ok((drive_path.to_string(), Some(partition_range(disk, drive_path, number)?)));
+    }
+    Ok((partition_path(drive_path, number), None))
+}
+
+/// Runs an ext4 mkfs tool on partition `number`, telling it where the partition is inside image files.
+fn run_mke2fs(tool: &str, args: &[&str], disk: &File, drive_path: &str, number: u32) -> Result<(), BurnError> {
+    let (path, range) = mkfs_target(disk, drive_path, number)?;
+    let mut args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+    match range {
+        // mke2fs takes the size in 1 KiB blocks with a k suffix.
+        Some((offset, len)) => args.extend(["-E".to_string(), format!("offset={}", offset), path, format!("{}k", len / 1024)]),
+        None => args.push(path),
+    }
+    run_mkfs(tool, &args.iter().map(String::as_str).collect::<Vec<_>>())
+}
+
+/// Use the exfat-fs crate to format partition `number` as exFAT.
+/// The filesystem spans the whole partition, whatever alignment made of its size.
+/// `full_format` zeroes the whole partition instead of just the metadata, which takes as long as writing it;
+/// `pack_bitmap` tucks the allocation bitmap in before the cluster heap to save a little space.
+pub fn make_exfat(disk: &File, drive_path: &str, number: u32, label: &str, full_format: bool, pack_bitmap: bool) -> Result<(), BurnError> {
+    let mut volume = Volume::open(disk, drive_path, number)?;
+    let ss = logical_sector_size(&volume.file)?;
+    let label = fit_label(label, Filesystem::Exfat);
+    log::info!("formatting {} bytes as exFAT ({} byte sectors, label {:?}, full format {})", volume.len, ss, label, full_format);
+    // exfat-fs stops at 11 bytes and miscounts anything but ASCII, the label goes in afterwards.
+    let format_options = FormatVolumeOptionsBuilder::default()
+        .pack_bitmap(pack_bitmap)
+        .full_format(full_format)
+        .label(Label::default())
+        .dev_size(volume.len)
+        .bytes_per_sector(ss as u16)
+        .build()?;
+
+    let mut formatter = Exfat::try_from(format_options)?;
+    formatter.write(&mut volume)?;
+    write_exfat_label(&mut volume, &label)?;
+
+    Ok(())
+}
+
+/// Puts `label` (at most [`EXFAT_LABEL_MAX`] UTF-16 characters) in the volume label entry of a fresh exFAT volume.
+/// The entry lives in the root directory and isn't covered by the boot region checksum.
+fn write_exfat_label(volume: &mut Volume, label: &str) -> Result<(), BurnError> {
+    let mut boot = [0u8; 512];
+    volume.seek(SeekFrom::Start(0))?;
+    volume.read_exact(&mut boot)?;
+    let heap_offset = u32::from_le_bytes([boot[88], boot[89], boot[90], boot[91]]) as u64;
+    let root_cluster = u32::from_le_bytes([boot[96], boot[97], boot[98], boot[99]]) as u64;
+    let (sector_shift, cluster_shift) = (boot[108] as u64, boot[109] as u64);
+    let root = (heap_offset + ((root_cluster - 2) << cluster_shift)) << sector_shift;
+    // The label entry is the first one exfat-fs writes, look for it rather than count on that.
+    let mut entries = vec![0u8; 1 << (sector_shift + cluster_shift)];
+    volume.seek(SeekFrom::Start(root))?;
+    volume.read_exact(&mut entries)?;
+    let Some(index) = entries.chunks(32).position(|entry| entry[0] & 0x7f == 0x03) else {
+        return Err(BurnError::Format("the exFAT root directory has no volume label entry".to_string()));
+    };
+    let units: Vec<u16> = label.encode_utf16().take(EXFAT_LABEL_MAX).collect();
+    let mut entry = [0u8; 32];
+    entry[0] = 0x83;
+    entry[1] = units.len() as u8;
+    for (i, unit) in units.iter().enumerate() {
+        entry[2 + i * 2..4 + i * 2].copy_from_slice(&unit.to_le_bytes());
+    }
+    volume.seek(SeekFrom::Start(root + index as u64 * 32))?;
+    volume.write_all(&entry)?;
+    volume.flush()?;
+    Ok(())
+}
+
+/// Use the fatfs crate to format partition `number` as fat.
+/// Leave `cluster_size` out to let fatfs pick one that suits the volume size.
+pub fn make_fat(disk: &File, drive_path: &str, number: u32, label: &str, fat: u8, cluster_size: Option<u32>) -> Result<(), BurnError> {
+    let mut volume = Volume::open(disk, drive_path, number)?;
+    let ss = logical_sector_size(&volume.file)?;
+    let fat_type: FatType = match fat {
+        12 => {
+            Fat12
+        },
+        16 => {
+            Fat16
+        },
+        32 => {
+            Fat32
+        },
+        _ => {
+            return Err(BurnError::UnsupportedFatType(fat));
+        }
+    };
+    // fatfs happily makes out-of-spec volumes, so check the size ourselves.
+    let size = volume.len;
+    let filesystem = match fat_type {
+        Fat12 => Filesystem::Fat12,
+        Fat16 => Filesystem::Fat16,
+        Fat32 => Filesystem::Fat32,
+    };
+    if max_volume_size(filesystem).is_some_and(|max| size > max) {
+        return Err(BurnError::VolumeTooLarge(fat, size));
+    }
+    let mut volume_label = [0u8; FAT_LABEL_MAX];
+    for (i, &b) in fit_label(label, Filesystem::Fat32).as_bytes().iter().enumerate() {
+        volume_label[i] = b;
+    }
+
+    log::info!("formatting {} bytes as FAT{} ({} byte sectors, cluster size {:?})", size, fat, ss, cluster_size);
+    let mut options = FormatVolumeOptions::new().fat_type(fat_type).bytes_per_sector(ss as u16).volume_label(volume_label);
+    if let Some(cluster_size) = cluster_size {
+        // A cluster is a whole number of sectors, at most 128 of them.
+        check_cluster_size(cluster_size)?;
+        if cluster_size < ss || cluster_size / ss > 128 {
+            return Err(BurnError::InvalidClusterSize(cluster_size));
+        }
+        options = options.bytes_per_cluster(cluster_size);
+    }
+
+    format_volume(&mut volume, options)
+        .map_err(|e| BurnError::Format(e.to_string()))?;
+
+    Ok(())
+}
+
+/// Copies the file at `source` onto the FAT volume on partition `number`, as `dest` (a `/` separated path
+/// from the root, missing directories are created). An existing file there is replaced.
+/// Goes through the fatfs crate like [`make_fat`], nothing gets mounted.
+pub fn add_file(disk: &File, drive_path: &str, number: u32, source: &str, dest: &str) -> Result<(), BurnError> {
+    let fail = |e: std::io::Error| BurnError::AddFile(format!("{}: {}", dest, e));
+    let mut input = File::open(source)?;
+    let volume = Volume::open(disk, drive_path, number)?;
+    let fs = fatfs::FileSystem::new(volume, fatfs::FsOptions::new()).map_err(fail)?;
+    let mut dir = fs.root_dir();
+    let mut parts: Vec<&str> = dest.split('/').filter(|part| !part.is_empty()).collect();
+    let Some(name) = parts.pop() else {
+        return Err(BurnError::AddFile(format!("{} has no file name", dest)));
+    };
+    for part in parts {
+        // create_dir hands back the one that's already there.
+        dir = dir.create_dir(part).map_err(fail)?;
+    }
+    let mut file = dir.create_file(name).map_err(fail)?;
+    file.truncate().map_err(fail)?;
+    let copied = std::io::copy(&mut input, &mut file).map_err(fail)?;
+    file.flush().map_err(fail)?;
+    drop(file);
+    drop(dir);
+    fs.unmount().map_err(fail)?;
+    log::info!("added {} ({} bytes) to partition {} as {}", source, copied, number, dest);
+    Ok(())
+}
+
+/// Bytes free on the FAT volume on partition `number`, going by its FAT rather than the FSInfo sector's guess.
+pub fn free_space(disk: &File, drive_path: &str, number: u32) -> Result<u64, BurnError> {
+    let volume = Volume::open(disk, drive_path, number)?;
+    let fs = fatfs::FileSystem::new(volume, fatfs::FsOptions::new())?;
+    let stats = fs.stats()?;
+    Ok(stats.free_clusters() as u64 * stats.cluster_size() as u64)
+}
+
+/// Bytes a FAT volume takes to hold `files`: each one rounded up to the largest cluster,
+/// the two FATs at 4 bytes for every cluster of the smallest size, and a MiB for the reserved sectors.
+/// More than it takes, never less, whatever cluster size the volume ends up with.
+pub fn fat_room(files: &[IsoFile]) -> u64 {
+    let cluster = MAX_CLUSTER_SIZE as u64;
+    let data: u64 = files.iter().map(|file| file.len.max(1).div_ceil(cluster) * cluster).sum();
+    data + data / MIN_CLUSTER_SIZE as u64 * 8 + 1024 * 1024
+}
+
+/// Copies the files of the ISO at `source` onto the FAT volume on partition `number`, instead of the image itself,
+/// for a plain data drive. `files` is what [`crate::image::iso_files`] found in it. Returns the bytes copied.
+/// Ctrl-C stops it between chunks, the volume then holds the files copied so far.
+pub fn extract_iso(disk: &File, drive_path: &str, number: u32, source: &str, files: &[IsoFile], progress: &mut dyn ProgressSink) -> Result<u64, BurnError> {
+    let fail = |path: &str, e: std::io::Error| BurnError::AddFile(format!("{}: {}", path, e));
+    let mut iso = File::open(source)?;
+    let volume = Volume::open(disk, drive_path, number)?;
+    let fs = fatfs::FileSystem::new(volume, fatfs::FsOptions::new()).map_err(|e| fail("/", e))?;
+    let root = fs.root_dir();
+    let total = files.iter().filter(|file| !file.directory).map(|file| file.len).sum();
+    let mut copied = 0;
+    let mut buffer = vec![0u8; 1024 * 1024];
+    let start = Instant::now();
+    for entry in files {
+        if entry.directory {
+            // Parents come first, so every directory goes into one that's already there.
+            root.create_dir(&entry.path).map_err(|e| fail(&entry.path, e))?;
+            continue;
+        }
+        let mut file = root.create_file(&entry.path).map_err(|e| fail(&entry.path, e))?;
+        file.truncate().map_err(|e| fail(&entry.path, e))?;
+        iso.seek(SeekFrom::Start(entry.offset))?;
+        let mut left = entry.len;
+        while left > 0 {
+            if interrupt::requested() {
+                return Err(BurnError::Aborted);
+            }
+            let n = left.min(buffer.len() as u64) as usize;
+            iso.read_exact(&mut buffer[..n])?;
+            file.write_all(&buffer[..n]).map_err(|e| fail(&entry.path, e))?;
+            left -= n as u64;
+            copied += n as u64;
+            let speed = copied as f64 / start.elapsed().as_secs_f64().max(0.001);
+            progress.update(&Progress { written: copied, total, speed });
+        }
+        file.flush().map_err(|e| fail(&entry.path, e))?;
+    }
+    drop(root);
+    fs.unmount().map_err(|e| fail("/", e))?;
+    log::info!("copied {} files ({} bytes) from {} to partition {}", files.iter().filter(|file| !file.directory).count(), copied, source, number);
+    Ok(copied)
+}
+
+/// Reads the files [`extract_iso`] copied back from the FAT volume on partition `number` and compares them
+/// with the ISO at `source`. Returns `false` when one is missing or differs.
+pub fn verify_iso(disk: &File, drive_path: &str, number: u32, source: &str, files: &[IsoFile]) -> Result<bool, BurnError> {
+    let mut iso = File::open(source)?;
+    let volume = Volume::open(disk, drive_path, number)?;
+    let fs = fatfs::FileSystem::new(volume, fatfs::FsOptions::new())?;
+    let root = fs.root_dir();
+    let (mut expected, mut found) = (vec![0u8; 1024 * 1024], vec![0u8; 1024 * 1024]);
+    for entry in files.iter().filter(|file| !file.directory) {
+        let mut file = match root.open_file(&entry.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                log::info!("{} is missing from partition {}", entry.path, number);
+                return Ok(false);
+            }
+            Err(e) => return Err(e.into()),
+        };
+        iso.seek(SeekFrom::Start(entry.offset))?;
+        let mut left = entry.len;
+        while left > 0 {
+            if interrupt::requested() {
+                return Err(BurnError::Aborted);
+            }
+            let n = left.min(expected.len() as u64) as usize;
+            iso.read_exact(&mut expected[..n])?;
+            match file.read_exact(&mut found[..n]) {
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(false),
+                result => result?,
+            }
+            if expected[..n] != found[..n] {
+                log::info!("{} on partition {} differs from the ISO", entry.path, number);
+                return Ok(false);
+            }
+            left -= n as u64;
+        }
+        // Nothing may follow, a longer file is as wrong as a shorter one.
+        if file.read(&mut found[..1])? != 0 {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Formats the second partition as ext4 for live-USB persistence, using `mkfs.ext4`.
+/// Returns `false` if `mkfs.ext4` isn't installed, the partition is then left unformatted.
+pub fn make_persistence(disk: &File, drive_path: &str) -> Result<bool, BurnError> {
+    match run_mke2fs("mkfs.ext4", &["-F", "-q", "-L", PERSISTENCE_LABEL], disk, drive_path, 2) {
+        Ok(()) => Ok(true),
+        Err(BurnError::MissingTool(_)) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Shell out to `mkfs.ntfs` (from ntfs-3g) to format partition `number` as NTFS.
+pub fn make_ntfs(disk: &File, drive_path: &str, number: u32, label: &str) -> Result<(), BurnError> {
+    let (path_to_volume, range) = mkfs_target(disk, drive_path, number)?;
+    if range.is_some() {
+        return Err(BurnError::Format("mkfs.ntfs can't format a partition inside an image file".to_string()));
+    }
+    // --fast skips zeroing the whole partition, a new volume has nothing on it to hide.
+    run_mkfs("mkfs.ntfs", &["--fast", "--force", "--quiet", "--label", &fit_label(label, Filesystem::Ntfs), &path_to_volume])
+}
+
+/// Shell out to `mke2fs` (from e2fsprogs) to format partition `number` as ext4.
+pub fn make_ext4(disk: &File, drive_path: &str, number: u32, label: &str) -> Result<(), BurnError> {
+    run_mke2fs("mke2fs", &["-t", "ext4", "-F", "-q", "-L", &fit_label(label, Filesystem::Ext4)], disk, drive_path, number)
+}
+
+/// Whether an external program is on the `PATH`, to check before anything touches the disk.
+pub fn has_tool(tool: &str) -> bool {
+    std::env::var_os("PATH")
+        .map(|path| std::env::split_paths(&path).any(|dir| dir.join(tool).is_file()))
+        .unwrap_or(false)
+}
+
+/// Runs an external mkfs tool, turning a missing binary into [`BurnError::MissingTool`].
+fn run_mkfs(tool: &str, args: &[&str]) -> Result<(), BurnError> {
+    log::info!("running {} {}", tool, args.join(" "));
+    let output = match Command::new(tool).args(args).output() {
+        Ok(output) => output,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Err(BurnError::MissingTool(tool.to_string())),
+        Err(e) => return Err(e.into()),
+    };
+    if !output.status.success() {
+        return Err(BurnError::Format(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_are_counted_like_the_filesystem_does() {
+        for (label, filesystem, len) in [
+            ("BURN", Filesystem::Fat32, 4),
+            ("é", Filesystem::Fat32, 2),
+            ("é", Filesystem::Exfat, 1),
+            ("😀", Filesystem::Exfat, 2),
+            ("😀", Filesystem::Ntfs, 2),
+            ("Ünïcødé", Filesystem::Ntfs, 7),
+            ("Ünïcødé", Filesystem::Ext4, 11),
+        ] {
+            assert_eq!(label_len(label, filesystem), len, "{:?} on {}", label, filesystem);
+        }
+    }
+
+    #[test]
+    fn labels_are_fitted_to_the_filesystem() {
+        let emoji = "😀".repeat(20);
+        for (label, filesystem, fitted) in [
+            ("debian 12", Filesystem::Fat32, "DEBIAN 12".to_string()),
+            ("a.b/c:d", Filesystem::Fat16, "ABCD".to_string()),
+            ("Grüße", Filesystem::Fat32, "GRE".to_string()),
+            ("a\tb", Filesystem::Fat32, "AB".to_string()),
+            ("Ubuntu 24.04 LTS amd64", Filesystem::Fat32, "UBUNTU 2404".to_string()),
+            ("Grüße", Filesystem::Exfat, "Grüße".to_string()),
+            ("a:b*c?", Filesystem::Exfat, "abc".to_string()),
+            ("Debian 12.5 live", Filesystem::Exfat, "Debian 12.5 liv".to_string()),
+            (&emoji, Filesystem::Exfat, "😀".repeat(7)),
+            (&emoji, Filesystem::Ntfs, "😀".repeat(16)),
+            ("Fedora-Workstation-Live", Filesystem::Ext4, "Fedora-Workstati".to_string()),
+            ("éééééééééé", Filesystem::Ext4, "é".repeat(8)),
+        ] {
+            let label = fit_label(label, filesystem);
+            assert_eq!(label, fitted, "on {}", filesystem);
+            assert!(label_len(&label, filesystem) <= max_label_len(filesystem), "{:?} on {}", label, filesystem);
+        }
+    }
+}
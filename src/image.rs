@@ -0,0 +1,996 @@
+//! Reading the source image and copying it onto the drive.
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::str::FromStr;
+#[cfg(target_os = "linux")]
+use std::os::fd::AsRawFd;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
+use flate2::read::MultiGzDecoder;
+use sha2::{Digest, Sha256};
+use xz2::read::XzDecoder;
+use crate::device::{clear_direct, flush_buffers, logical_sector_size, open_device_direct, set_direct};
+use crate::error::BurnError;
+use crate::interrupt;
+use crate::progress::{Progress, ProgressSink};
+
+/// Size of the chunks the image is copied in, unless told otherwise.
+pub const DEFAULT_BLOCK_SIZE: usize = 64 * 1024;
+/// Smallest block size worth using.
+pub const MIN_BLOCK_SIZE: usize = 4 * 1024;
+/// Largest block size we allocate.
+pub const MAX_BLOCK_SIZE: usize = 16 * 1024 * 1024;
+
+/// Blocks in flight between reading the image and writing the drive, unless told otherwise.
+/// Two is enough for reading one while the other is written.
+pub const DEFAULT_BUFFERS: usize = 2;
+/// Most blocks we keep in flight, they cost a block size of memory each.
+pub const MAX_BUFFERS: usize = 16;
+
+/// How much of the end of the image is read back after writing, to catch drives that take
+/// writes into their cache and then fail to store them.
+pub const TAIL_CHECK_SIZE: usize = 4 * 1024 * 1024;
+
+/// How many times a block is tried before the write gives up. Cheap USB controllers sometimes
+/// fail a single write that goes through the second time.
+pub const WRITE_ATTEMPTS: u32 = 3;
+
+/// With [`WriteOptions::verify`], the drive is synced every this many bytes and read back up to there.
+pub const READ_BACK_STEP: u64 = 32 * 1024 * 1024;
+
+/// Checks a write block size before anything touches the disk.
+pub fn check_block_size(bytes: usize) -> Result<(), BurnError> {
+    if !(MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&bytes) {
+        return Err(BurnError::InvalidBlockSize(bytes));
+    }
+    Ok(())
+}
+
+/// Checks a buffer count before anything touches the disk.
+pub fn check_buffers(buffers: usize) -> Result<(), BurnError> {
+    if !(1..=MAX_BUFFERS).contains(&buffers) {
+        return Err(BurnError::InvalidBuffers(buffers));
+    }
+    Ok(())
+}
+
+/// How [`write_image`] copies the image.
+#[derive(Clone, Copy, Debug)]
+pub struct WriteOptions {
+    /// Size of the chunks the image is copied in, in bytes.
+    pub block_size: usize,
+    /// Write around the page cache (O_DIRECT, Linux only). The block size gets rounded up to whole sectors.
+    pub direct: bool,
+    /// Skip the blocks that are already on the drive from an earlier, interrupted burn.
+    pub resume: bool,
+    /// Keep the average write rate under this many bytes per second.
+    pub limit: Option<u64>,
+    /// Start writing this many bytes into the destination, like dd's `seek=` (but in bytes).
+    pub seek: u64,
+    /// Leave out this many bytes from the start of the (decompressed) image, like dd's `skip=`.
+    pub skip: u64,
+    /// Blocks in flight: with more than one, a thread reads the image ahead while the drive is written.
+    pub buffers: usize,
+    /// Read the drive back on a thread of its own while it is written, see [`Written::read_back`].
+    pub verify: bool,
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        WriteOptions {
+            block_size: DEFAULT_BLOCK_SIZE,
+            direct: false,
+            resume: false,
+            limit: None,
+            seek: 0,
+            skip: 0,
+            buffers: DEFAULT_BUFFERS,
+            verify: false,
+        }
+    }
+}
+
+/// What [`write_image`] put on the drive.
+#[derive(Clone, Debug)]
+pub struct Written {
+    /// Bytes of the (decompressed) image written.
+    pub len: u64,
+    /// SHA-256 of the (decompressed) image.
+    pub sha256: Vec<u8>,
+    /// SHA-256 of the same bytes read back from the drive during the write, with [`WriteOptions::verify`].
+    /// `None` when it wasn't asked for, or the drive couldn't be read while it was being written;
+    /// then it takes a pass of its own, see [`verify_image`].
+    pub read_back: Option<Vec<u8>>,
+}
+
+/// How the source image is compressed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Xz,
+    Zstd,
+}
+
+impl Compression {
+    /// Detects the compression from the magic bytes at the start of the file.
+    pub fn detect(file_path: &str) -> Result<Compression, BurnError> {
+        let mut magic = [0u8; 6];
+        let mut file = File::open(file_path)?;
+        let mut got = 0;
+        while got < magic.len() {
+            match file.read(&mut magic[got..]) {
+                Ok(0) => break,
+                Ok(n) => got += n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(Compression::from_magic(&magic[..got]))
+    }
+
+    /// Tells the compression from the first bytes of the image.
+    fn from_magic(magic: &[u8]) -> Compression {
+        if magic.starts_with(&[0x1f, 0x8b]) {
+            Compression::Gzip
+        } else if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Compression::Xz
+        } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+}
+
+/// Strips a compression extension (`.gz`, `.xz`, `.zst`) off a file name, `foo.img.xz` becomes `foo.img`.
+pub fn strip_compression_extension(file_name: &str) -> &str {
+    for ext in [".gz", ".xz", ".zst", ".zstd"] {
+        if let Some(stripped) = file_name.strip_suffix(ext) {
+            return stripped;
+        }
+    }
+    file_name
+}
+
+/// How to treat the source, from `--source-type`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SourceType {
+    /// Go by the extension: `.iso` is an ISO, `.img` a raw disk image, anything else is refused.
+    Auto,
+    /// An ISO9660 image whatever its name, its Primary Volume Descriptor is checked and its volume name read.
+    Iso,
+    /// A raw disk image whatever its name, written as it is. The label comes from the file name.
+    Raw,
+}
+
+impl SourceType {
+    /// Settles `Auto` by the extension of `file_path` (past a compression one), `None` if it is neither `.iso` nor `.img`.
+    pub fn resolve(self, file_path: &str) -> Option<SourceType> {
+        if self != SourceType::Auto {
+            return Some(self);
+        }
+        let file_name = Path::new(file_path).file_name().and_then(|n| n.to_str()).unwrap_or_default();
+        match strip_compression_extension(file_name) {
+            name if name.ends_with(".iso") => Some(SourceType::Iso),
+            name if name.ends_with(".img") => Some(SourceType::Raw),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for SourceType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "auto" => Ok(SourceType::Auto),
+            "iso" => Ok(SourceType::Iso),
+            "raw" => Ok(SourceType::Raw),
+            _ => Err(format!("unknown source type {} (expected iso, raw or auto)", s)),
+        }
+    }
+}
+
+impl fmt::Display for SourceType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SourceType::Auto => write!(f, "auto"),
+            SourceType::Iso => write!(f, "iso"),
+            SourceType::Raw => write!(f, "raw"),
+        }
+    }
+}
+
+/// Shared count of the bytes read from the file.
+type ReadCount = Arc<AtomicU64>;
+
+/// Counts the bytes read through it, so progress can follow the compressed file.
+struct CountingReader<R> {
+    inner: R,
+    count: ReadCount,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+/// Source path that stands for standard input, for images piped in (`curl ... | burn --stdin`).
+/// It can only be read once and has no size, so only the write itself and [`compare_image`] take it.
+pub const STDIN: &str = "-";
+
+/// Opens the image for streaming, decompressing it on the fly if needed.
+/// Also returns a counter of how many bytes of the file itself have been read.
+fn open_source(file_path: &str) -> Result<(Box<dyn Read + Send>, ReadCount), BurnError> {
+    let count = Arc::new(AtomicU64::new(0));
+    let (compression, inner): (Compression, Box<dyn Read + Send>) = if file_path == STDIN {
+        // Nothing to open twice, peek at the magic bytes through the buffer instead.
+        let mut stdin = BufReader::with_capacity(64 * 1024, std::io::stdin());
+        let compression = Compression::from_magic(stdin.fill_buf()?);
+        (compression, Box::new(stdin))
+    } else {
+        (Compression::detect(file_path)?, Box::new(File::open(file_path)?))
+    };
+    let file = CountingReader { inner, count: count.clone() };
+    let source: Box<dyn Read + Send> = match compression {
+        Compression::None => Box::new(file),
+        Compression::Gzip => Box::new(MultiGzDecoder::new(file)),
+        Compression::Xz => Box::new(XzDecoder::new_multi_decoder(file)),
+        Compression::Zstd => Box::new(zstd::stream::read::Decoder::new(file)?),
+    };
+    Ok((source, count))
+}
+
+/// Size of the image once decompressed.
+/// Compressed images don't reliably store that, so they are decompressed once just to count.
+pub fn image_size(file_path: &str) -> Result<u64, BurnError> {
+    if file_path == STDIN {
+        // Counting would use up the image.
+        return Err(std::io::Error::new(ErrorKind::Unsupported, "the size of an image on stdin is unknown").into());
+    }
+    if Compression::detect(file_path)? == Compression::None {
+        return Ok(std::fs::metadata(file_path)?.len());
+    }
+    let (mut source, _) = open_source(file_path)?;
+    Ok(std::io::copy(&mut source, &mut std::io::sink())?)
+}
+
+/// Whether the image starts with its own partition table (an isohybrid ISO or a disk image).
+/// Those are meant to be written raw, a new partition table would throw their layout away.
+pub fn is_hybrid(file_path: &str) -> Result<bool, BurnError> {
+    let (mut source, _) = open_source(file_path)?;
+    let mut lba0 = [0u8; 512];
+    if source.read_exact(&mut lba0).is_err() {
+        return Ok(false);
+    }
+    if lba0[510..512] != [0x55, 0xaa] {
+        return Ok(false);
+    }
+    // Four 16-byte entries from offset 446, the type byte of a used one is not zero.
+    Ok((0..4).any(|i| lba0[446 + i * 16 + 4] != 0))
+}
+
+/// Byte offset of the first volume descriptor of an ISO9660 image, sector 16 of 2048 bytes.
+pub const VOLUME_DESCRIPTOR_OFFSET: usize = 16 * 2048;
+
+/// Checks that the image really is ISO9660: the Primary Volume Descriptor at sector 16 starts with
+/// type 1 and the `CD001` identifier. Compressed images are looked into.
+pub fn check_iso(file_path: &str) -> Result<(), BurnError> {
+    let (mut source, _) = open_source(file_path)?;
+    let mut head = vec![0u8; VOLUME_DESCRIPTOR_OFFSET + 6];
+    // Too short to hold a descriptor counts as not an ISO, not as an I/O error.
+    if fill_buffer(&mut source, &mut head)? < head.len() || head[VOLUME_DESCRIPTOR_OFFSET] != 1 || &head[VOLUME_DESCRIPTOR_OFFSET + 1..] != b"CD001" {
+        return Err(BurnError::NotIso(file_path.to_string()));
+    }
+    Ok(())
+}
+
+/// Whether the ISO declares an El Torito boot catalog, i.e. whether BIOSes and UEFI can boot it.
+/// Looks for the boot record among the volume descriptors, which start at sector 16.
+pub fn has_boot_catalog(file_path: &str) -> Result<bool, BurnError> {
+    let mut file = File::open(file_path)?;
+    let mut descriptor = [0u8; 2048];
+    // Real ISOs have a handful of descriptors, don't wander through the whole file if it's garbage.
+    for sector in 16..64 {
+        file.seek(SeekFrom::Start(sector * 2048))?;
+        if file.read_exact(&mut descriptor).is_err() {
+            break;
+        }
+        if &descriptor[1..6] != b"CD001" {
+            break;
+        }
+        match descriptor[0] {
+            // Boot record: the boot system identifier is null-padded to 32 bytes.
+            0 if descriptor[7..39].starts_with(b"EL TORITO SPECIFICATION") => return Ok(true),
+            // Volume descriptor set terminator.
+            255 => break,
+            _ => {}
+        }
+    }
+    Ok(false)
+}
+
+/// Largest root directory we read looking for the label, real ones fit in a sector or two.
+const MAX_ROOT_DIRECTORY: u64 = 1024 * 1024;
+
+/// Derives a volume label from the ISO's root directory: the name of its first entry after
+/// `.` and `..`, without its `;1` version. Falls back to `NO_NAME` when the directory is empty or makes no sense.
+/// [`crate::format::fit_label`] then makes it fit the filesystem.
+pub fn iso_label(file_path: &str) -> Result<String, BurnError> {
+    check_iso(file_path)?;
+    let mut file = File::open(file_path)?;
+    // The root directory record sits at byte 156 of the Primary Volume Descriptor.
+    let mut root = [0u8; 34];
+    file.seek(SeekFrom::Start(VOLUME_DESCRIPTOR_OFFSET as u64 + 156))?;
+    file.read_exact(&mut root)?;
+    let extent = u32::from_le_bytes([root[2], root[3], root[4], root[5]]) as u64;
+    let len = u32::from_le_bytes([root[10], root[11], root[12], root[13]]) as u64;
+    let mut directory = vec![0u8; len.min(MAX_ROOT_DIRECTORY) as usize];
+    file.seek(SeekFrom::Start(extent * 2048))?;
+    let read = fill_buffer(&mut file, &mut directory)?;
+    directory.truncate(read);
+    let name = root_entries(&directory).into_iter().nth(2).unwrap_or_default();
+    let name = name.split(';').next().unwrap_or_default();
+    Ok(or_no_name(name))
+}
+
+/// Names of the first three records of an ISO9660 directory, `.` and `..` come first as `\0` and `\1`.
+fn root_entries(directory: &[u8]) -> Vec<String> {
+    let mut names = Vec::new();
+    let mut pos = 0;
+    while pos < directory.len() && names.len() < 3 {
+        let record_len = directory[pos] as usize;
+        if record_len == 0 {
+            // Records don't cross sectors, the rest of this one is padding.
+            pos = (pos / 2048 + 1) * 2048;
+            continue;
+        }
+        let Some(record) = directory.get(pos..pos + record_len).filter(|r| r.len() > 33) else { break };
+        let Some(name) = record.get(33..33 + record[32] as usize) else { break };
+        names.push(String::from_utf8_lossy(name).to_string());
+        pos += record_len;
+    }
+    names
+}
+
+/// A file or directory of an ISO9660 image, see [`iso_files`].
+#[derive(Clone, Debug)]
+pub struct IsoFile {
+    /// Path from the root, `/` separated, without the `;1` version.
+    pub path: String,
+    pub directory: bool,
+    /// Where the contents start in the image, in bytes.
+    pub offset: u64,
+    pub len: u64,
+}
+
+/// Deepest directory nesting we follow. ISO9660 stops at 8 levels, Joliet images go a little further.
+const MAX_ISO_DEPTH: usize = 64;
+/// Largest directory we read, tens of thousands of entries. Anything bigger is a broken image.
+const MAX_ISO_DIRECTORY: u64 = 16 * 1024 * 1024;
+
+/// Lists every file and directory of an ISO, parents before what's in them. The Joliet names are used
+/// when the image has them (nearly all do), they keep their case and length; otherwise the ISO9660 ones.
+/// Hidden entries are listed too, files split over several extents (4 GiB and up) are refused.
+pub fn iso_files(file_path: &str) -> Result<Vec<IsoFile>, BurnError> {
+    check_iso(file_path)?;
+    let mut file = File::open(file_path)?;
+    let mut descriptor = [0u8; 2048];
+    let mut root = None;
+    let mut joliet = None;
+    for sector in 16..64 {
+        file.seek(SeekFrom::Start(sector * 2048))?;
+        if file.read_exact(&mut descriptor).is_err() || &descriptor[1..6] != b"CD001" {
+            break;
+        }
+        match descriptor[0] {
+            // The root directory record sits at byte 156 of both the primary and the supplementary descriptor.
+            1 if root.is_none() => root = Some(directory_extent(&descriptor[156..190])),
+            // A supplementary descriptor with one of the UCS-2 escape sequences is the Joliet one.
+            2 if joliet.is_none() && matches!(&descriptor[88..91], b"%/@" | b"%/C" | b"%/E") => joliet = Some(directory_extent(&descriptor[156..190])),
+            255 => break,
+            _ => {}
+        }
+    }
+    let Some(root) = joliet.or(root) else {
+        return Err(BurnError::NotIso(file_path.to_string()));
+    };
+    let mut files = Vec::new();
+    let mut directories = vec![(String::new(), root, 0)];
+    let mut seen = std::collections::HashSet::new();
+    while let Some((prefix, (extent, len), depth)) = directories.pop() {
+        // Directories that point back up would have us going round forever.
+        if depth > MAX_ISO_DEPTH || !seen.insert(extent) {
+            return Err(std::io::Error::new(ErrorKind::InvalidData, format!("the directories of {} loop or nest too deep", file_path)).into());
+        }
+        if len > MAX_ISO_DIRECTORY {
+            return Err(std::io::Error::new(ErrorKind::InvalidData, format!("{} has a directory of {} bytes", file_path, len)).into());
+        }
+        let mut directory = vec![0u8; len as usize];
+        file.seek(SeekFrom::Start(extent * 2048))?;
+        file.read_exact(&mut directory)?;
+        let mut pos = 0;
+        while pos < directory.len() {
+            let record_len = directory[pos] as usize;
+            if record_len == 0 {
+                // Records don't cross sectors, the rest of this one is padding.
+                pos = (pos / 2048 + 1) * 2048;
+                continue;
+            }
+            let Some(record) = directory.get(pos..pos + record_len).filter(|r| r.len() > 33) else { break };
+            pos += record_len;
+            let Some(name) = record.get(33..33 + record[32] as usize) else { break };
+            // `.` and `..`
+            if name == [0] || name == [1] {
+                continue;
+            }
+            let name = if joliet.is_some() {
+                let units: Vec<u16> = name.chunks_exact(2).map(|unit| u16::from_be_bytes([unit[0], unit[1]])).collect();
+                String::from_utf16_lossy(&units)
+            } else {
+                String::from_utf8_lossy(name).to_string()
+            };
+            // Level 1 names end in `;1`, and in a dot when they have no extension.
+            let name = name.split(';').next().unwrap_or_default().trim_end_matches('.').to_string();
+            if name.is_empty() || name.contains('/') {
+                continue;
+            }
+            let path = if prefix.is_empty() { name } else { format!("{}/{}", prefix, name) };
+            let (extent, len) = directory_extent(record);
+            let flags = record[25];
+            if flags & 0x80 != 0 {
+                return Err(std::io::Error::new(ErrorKind::Unsupported, format!("{} is split over several extents, too large to copy", path)).into());
+            }
+            let is_directory = flags & 0x02 != 0;
+            files.push(IsoFile { path: path.clone(), directory: is_directory, offset: extent * 2048, len });
+            if is_directory {
+                directories.push((path, (extent, len), depth + 1));
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// The extent (in 2048 byte sectors) and length (in bytes) of an ISO9660 directory record.
+fn directory_extent(record: &[u8]) -> (u64, u64) {
+    let extent = u32::from_le_bytes([record[2], record[3], record[4], record[5]]) as u64;
+    let len = u32::from_le_bytes([record[10], record[11], record[12], record[13]]) as u64;
+    (extent, len)
+}
+
+/// Derives a volume label from the file name, for images that aren't ISOs.
+/// `raspios-lite.img` (or `raspios-lite.img.xz`) becomes `raspios-lite`.
+/// [`crate::format::fit_label`] then makes it fit the filesystem.
+pub fn file_label(file_path: &str) -> String {
+    let file_name = Path::new(file_path).file_name().and_then(|s| s.to_str()).unwrap_or_default();
+    let stem = Path::new(strip_compression_extension(file_name)).file_stem().and_then(|s| s.to_str()).unwrap_or_default();
+    or_no_name(stem)
+}
+
+/// The label as is, or `NO_NAME` for an empty one.
+fn or_no_name(label: &str) -> String {
+    match label.trim() {
+        "" => "NO_NAME".to_string(),
+        label => label.to_string(),
+    }
+}
+
+/// Reads the drive back on a thread of its own while the image is still being written to it.
+/// It only goes as far as the writer has synced, and drops that stretch from the page cache first,
+/// so what it hashes is what the drive stored rather than what we handed the kernel.
+struct ReadBack {
+    synced: mpsc::Sender<u64>,
+    worker: std::thread::JoinHandle<std::io::Result<Vec<u8>>>,
+}
+
+impl ReadBack {
+    /// Opens a handle of its own on `dest_path`, reading starts `offset` bytes in once [`ReadBack::advance`] allows it.
+    fn start(dest_path: &str, offset: u64) -> std::io::Result<ReadBack> {
+        let mut drive = File::open(dest_path)?;
+        drive.seek(SeekFrom::Start(offset))?;
+        let (synced, up_to) = mpsc::channel::<u64>();
+        let worker = std::thread::spawn(move || {
+            let mut hasher = Sha256::new();
+            // Whole sectors, drives on Windows refuse anything else.
+            let mut buffer = vec![0u8; 1024 * 1024];
+            let mut done: u64 = 0;
+            // Ends once the writer hangs up.
+            for limit in up_to {
+                #[cfg(target_os = "linux")]
+                unsafe {
+                    libc::posix_fadvise(drive.as_raw_fd(), (offset + done) as libc::off_t, (limit - done) as libc::off_t, libc::POSIX_FADV_DONTNEED);
+                }
+                while done < limit {
+                    if interrupt::requested() {
+                        return Err(std::io::Error::from(ErrorKind::Interrupted));
+                    }
+                    let want = ((limit - done) as usize).min(buffer.len());
+                    let read = fill_buffer(&mut drive, &mut buffer[..want.next_multiple_of(4096)])?;
+                    if read < want {
+                        // The drive ends before the image does, the digests won't match.
+                        hasher.update(&buffer[..read]);
+                        return Ok(hasher.finalize().to_vec());
+                    }
+                    hasher.update(&buffer[..want]);
+                    done += want as u64;
+                    // The padding went past what's synced, start the next read right after the image bytes.
+                    drive.seek(SeekFrom::Start(offset + done))?;
+                }
+            }
+            Ok(hasher.finalize().to_vec())
+        });
+        Ok(ReadBack { synced, worker })
+    }
+
+    /// Lets the reader go up to `len` bytes into the image, all of them synced to the drive by now.
+    fn advance(&self, len: u64) {
+        let _ = self.synced.send(len);
+    }
+
+    /// Waits for the reader to get through all `len` bytes and returns the SHA-256 of what it read,
+    /// `None` if it failed on the way (some drives won't be read while they're written).
+    fn finish(self, len: u64) -> Option<Vec<u8>> {
+        self.advance(len);
+        drop(self.synced);
+        match self.worker.join() {
+            Ok(Ok(digest)) => {
+                log::info!("read {} bytes back from the drive during the write", len);
+                Some(digest)
+            }
+            Ok(Err(e)) => {
+                log::warn!("Reading the drive back during the write failed ({}), verifying it afterwards instead.", e);
+                None
+            }
+            Err(_) => None,
+        }
+    }
+}
+
+/// Writes an image to the disk drive.
+/// The image goes raw onto the whole device (not `{dest_path}1`), so hybrid ISOs keep their own boot layout.
+/// Compressed images are decompressed on the fly, progress then follows the compressed file.
+/// The copy goes in chunks of `options.block_size` bytes, bigger ones help on fast drives.
+/// Each chunk is reported to `progress`.
+/// `dest` is the drive at `dest_path`, already open.
+/// With `options.verify` the drive is read back alongside, see [`Written::read_back`].
+pub fn write_image(file_path: &str, dest: &File, dest_path: &str, options: &WriteOptions, progress: &mut dyn ProgressSink) -> Result<Written, BurnError> {
+    check_block_size(options.block_size)?;
+    check_buffers(options.buffers)?;
+    let (mut file, read_pos) = open_source(file_path)?;
+    // Unknown for stdin, progress then goes without a percentage.
+    let file_size = if file_path == STDIN { 0 } else { std::fs::metadata(file_path)?.len() };
+    let mut bytes_written: u64 = 0;
+    let mut hasher = Sha256::new();
+    skip_source(&mut file, options.skip)?;
+    // Where the copy starts in the file itself, the skipped part doesn't count towards the speed.
+    let base = read_pos.load(Ordering::Relaxed);
+
+    let (mut dest, sector_size, block_size) = if options.direct || cfg!(windows) {
+        let dest = if options.direct && cfg!(windows) {
+            // Windows can't change how an open handle buffers, that takes a handle of its own.
+            open_device_direct(dest_path)?
+        } else {
+            dest.try_clone()?
+        };
+        if options.direct {
+            set_direct(&dest)?;
+        }
+        let ss = logical_sector_size(&dest)? as usize;
+        // O_DIRECT only takes whole sectors, and Windows never takes anything else from a drive.
+        let block_size = options.block_size.div_ceil(ss) * ss;
+        (dest, ss, block_size)
+    } else {
+        (dest.try_clone()?, 1, options.block_size)
+    };
+    if !options.seek.is_multiple_of(sector_size as u64) {
+        return Err(BurnError::Offset(format!("{} is not a whole number of {} byte sectors", options.seek, sector_size)));
+    }
+    dest.seek(SeekFrom::Start(options.seek))?;
+    log::info!("writing {} to {} in {} byte blocks (sector size {}, direct {})", file_path, dest_path, block_size, sector_size, options.direct);
+    // On the heap, it can be up to 16mb. Align it for O_DIRECT (4096 covers every sector size in use).
+    let align = sector_size.max(4096);
+    let mut blocks = BlockSource::new(file, read_pos, options.buffers, block_size, align);
+    // What's already on the drive, when resuming.
+    let mut resuming = options.resume;
+    let mut check_storage = vec![0u8; if resuming { block_size + align } else { 0 }];
+    let check_offset = check_storage.as_ptr().align_offset(align);
+
+    // Throughput is measured over roughly the last second so stalls show up quickly.
+    let start = Instant::now();
+    let mut window_start = start;
+    let mut window_bytes: u64 = base;
+    let mut speed: f64 = 0.0; // bytes per second
+    // What actually went to the drive, for --limit (resumed blocks don't count).
+    let mut paced_bytes: u64 = 0;
+    // The last few blocks of the image, whole blocks so the read back starts on a sector.
+    let mut tail: VecDeque<Vec<u8>> = VecDeque::new();
+    let mut tail_len: usize = 0;
+    let read_back = if options.verify {
+        ReadBack::start(dest_path, options.seek)
+            .inspect_err(|e| log::warn!("Can't read {} back during the write ({}), verifying it afterwards instead.", dest_path, e))
+            .ok()
+    } else {
+        None
+    };
+    let mut synced: u64 = 0;
+
+    loop {
+        if interrupt::requested() {
+            // Stop here, but still get what we wrote onto the drive.
+            dest.flush()?;
+            dest.sync_all()?;
+            clear_direct(&dest)?;
+            return Err(BurnError::Aborted);
+        }
+        // Only the final block may come up short, so only ever write out the bytes we actually got.
+        // `position` is where that left us in the file itself, which is behind for compressed images.
+        let (buffer, bytes_read, position) = blocks.next()?;
+        if bytes_read == 0 {
+            break; // End of file
+        }
+        let mut write_len = bytes_read;
+        if bytes_read % sector_size != 0 {
+            if cfg!(windows) {
+                // No way around whole sectors there, pad the tail of the image with zeros.
+                write_len = bytes_read.next_multiple_of(sector_size);
+                buffer[bytes_read..write_len].fill(0);
+            } else {
+                // The tail of the image isn't a whole sector, O_DIRECT would refuse it.
+                clear_direct(&dest)?;
+            }
+        }
+        let mut on_drive = false;
+        if resuming {
+            let check = &mut check_storage[check_offset..check_offset + write_len];
+            if fill_buffer(&mut dest, check)? == write_len && check[..bytes_read] == buffer[..bytes_read] {
+                // Already written by the last attempt.
+                on_drive = true;
+            } else {
+                // First difference, carry on writing from this block.
+                resuming = false;
+                dest.seek(SeekFrom::Start(options.seek + bytes_written))?;
+            }
+        }
+        if !on_drive {
+            write_block(&mut dest, options.seek + bytes_written, &buffer[..write_len])?;
+            paced_bytes += write_len as u64;
+            if let Some(limit) = options.limit {
+                pace(start, paced_bytes, limit);
+            }
+        }
+        hasher.update(&buffer[..bytes_read]);
+        tail.push_back(buffer[..bytes_read].to_vec());
+        tail_len += bytes_read;
+        while tail.front().is_some_and(|block| tail_len - block.len() >= TAIL_CHECK_SIZE) {
+            tail_len -= tail.pop_front().map_or(0, |block| block.len());
+        }
+        bytes_written += bytes_read as u64;
+        if let Some(read_back) = &read_back
+            && bytes_written - synced >= READ_BACK_STEP {
+            dest.sync_data()?;
+            synced = bytes_written;
+            read_back.advance(synced);
+        }
+        let window = window_start.elapsed().as_secs_f64();
+        if window >= 1.0 {
+            speed = (position - window_bytes) as f64 / window;
+            window_start = Instant::now();
+            window_bytes = position;
+        } else if speed == 0.0 {
+            // Nothing measured yet, fall back to the average since the start.
+            let elapsed = start.elapsed().as_secs_f64();
+            if elapsed > 0.0 {
+                speed = (position - base) as f64 / elapsed;
+            }
+        }
+        progress.update(&Progress { written: position, total: file_size, speed });
+    }
+    dest.flush()?;
+    // flush() only empties our own buffers; make sure everything actually hit the drive
+    // before we report success, or pulling it out right away can leave a corrupt image.
+    // This is where a drive that took the writes but couldn't store them finally says so.
+    dest.sync_all().map_err(|e| BurnError::WriteFailed(e.to_string()))?;
+    flush_buffers(&dest).map_err(|e| BurnError::WriteFailed(e.to_string()))?;
+    // The handle is shared with the later steps, which don't read into aligned buffers.
+    clear_direct(&dest)?;
+    let read_back = read_back.and_then(|read_back| read_back.finish(bytes_written));
+
+    let expected: Vec<u8> = tail.into_iter().flatten().collect();
+    if !check_tail(&dest, options.seek + bytes_written - expected.len() as u64, &expected, sector_size)? {
+        return Err(BurnError::WriteFailed("the end of the image reads back differently".to_string()));
+    }
+
+    Ok(Written { len: bytes_written, sha256: hasher.finalize().to_vec(), read_back })
+}
+
+/// Reads past the first `skip` bytes of the image. Compressed images can't seek, so it's read either way.
+fn skip_source(source: &mut dyn Read, skip: u64) -> Result<(), BurnError> {
+    if skip == 0 {
+        return Ok(());
+    }
+    let skipped = std::io::copy(&mut source.take(skip), &mut std::io::sink())?;
+    if skipped < skip {
+        return Err(BurnError::Offset(format!("the image is only {} bytes, can't skip {}", skipped, skip)));
+    }
+    Ok(())
+}
+
+/// What [`compare_image`] found.
+#[derive(Clone, Copy, Debug)]
+pub struct Comparison {
+    /// Bytes of the (decompressed) image compared.
+    pub total: u64,
+    /// How many of them are on the drive too, in the same place.
+    pub matching: u64,
+    /// Where in the image the drive first differs from it, `None` if it holds the whole image.
+    pub first_difference: Option<u64>,
+}
+
+/// Reads the image and the drive side by side and compares them, without writing anything.
+/// The image is read ahead like in [`write_image`], `options` gives the block size, buffers, seek and skip.
+/// Whatever of the image doesn't fit on the drive counts as different.
+pub fn compare_image(file_path: &str, mut dest: &File, options: &WriteOptions) -> Result<Comparison, BurnError> {
+    check_block_size(options.block_size)?;
+    check_buffers(options.buffers)?;
+    let (mut file, read_pos) = open_source(file_path)?;
+    skip_source(&mut file, options.skip)?;
+    dest.seek(SeekFrom::Start(options.seek))?;
+    // Whatever is still cached from writing it proves nothing about the drive.
+    #[cfg(target_os = "linux")]
+    unsafe {
+        libc::posix_fadvise(dest.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED);
+    }
+    let mut blocks = BlockSource::new(file, read_pos, options.buffers, options.block_size, 1);
+    // Whole sectors, drives on Windows refuse anything else.
+    let mut on_drive = vec![0u8; options.block_size.next_multiple_of(512)];
+    let mut comparison = Comparison { total: 0, matching: 0, first_difference: None };
+    loop {
+        if interrupt::requested() {
+            return Err(BurnError::Aborted);
+        }
+        let (buffer, bytes_read, _) = blocks.next()?;
+        if bytes_read == 0 {
+            break;
+        }
+        let image = &buffer[..bytes_read];
+        let got = fill_buffer(&mut dest, &mut on_drive[..bytes_read.next_multiple_of(512)])?.min(bytes_read);
+        let drive = &on_drive[..got];
+        if image == drive {
+            comparison.matching += got as u64;
+        } else {
+            comparison.matching += image.iter().zip(drive).filter(|(a, b)| a == b).count() as u64;
+            if comparison.first_difference.is_none() {
+                let at = image.iter().zip(drive).position(|(a, b)| a != b).unwrap_or(got);
+                comparison.first_difference = Some(comparison.total + at as u64);
+            }
+        }
+        comparison.total += bytes_read as u64;
+    }
+    log::info!("compared {} bytes of {} with the drive: {} match, first difference at {:?}", comparison.total, file_path, comparison.matching, comparison.first_difference);
+    Ok(comparison)
+}
+
+/// Passes progress from the worker thread of [`write_image_watched`] to the caller's sink,
+/// and keeps count of the bytes done for the watchdog.
+struct WatchedProgress {
+    written: Arc<AtomicU64>,
+    updates: mpsc::Sender<Progress>,
+}
+
+impl ProgressSink for WatchedProgress {
+    fn update(&mut self, progress: &Progress) {
+        self.written.store(progress.written, Ordering::Relaxed);
+        let _ = self.updates.send(*progress);
+    }
+}
+
+/// [`write_image`] on a worker thread, giving up with [`BurnError::Timeout`] once it has gone `timeout`
+/// without getting any further. That includes the final flush, which can take a while on slow drives.
+/// A wedged drive leaves the worker stuck in the kernel, so it's left behind rather than joined.
+pub fn write_image_watched(file_path: &str, dest: &File, dest_path: &str, options: &WriteOptions, progress: &mut dyn ProgressSink, timeout: Duration) -> Result<Written, BurnError> {
+    let written = Arc::new(AtomicU64::new(0));
+    let (sender, updates) = mpsc::channel();
+    let worker = {
+        let (file_path, dest, dest_path, options) = (file_path.to_string(), dest.try_clone()?, dest_path.to_string(), *options);
+        let mut sink = WatchedProgress { written: Arc::clone(&written), updates: sender };
+        std::thread::spawn(move || write_image(&file_path, &dest, &dest_path, &options, &mut sink))
+    };
+    let mut last = 0;
+    let mut last_change = Instant::now();
+    loop {
+        match updates.recv_timeout(Duration::from_millis(100)) {
+            Ok(update) => progress.update(&update),
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            // The worker dropped its end, it's done.
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        }
+        let now = written.load(Ordering::Relaxed);
+        if now != last {
+            last = now;
+            last_change = Instant::now();
+        } else if last_change.elapsed() >= timeout {
+            log::debug!("no progress past byte {} in {:?}, giving up on {}", last, timeout, dest_path);
+            return Err(BurnError::Timeout(timeout.as_secs()));
+        }
+    }
+    worker.join().unwrap_or_else(|_| Err(BurnError::WriteFailed("the write thread panicked".to_string())))
+}
+
+/// Sleeps until writing `bytes` since `start` is no faster than `limit` bytes per second.
+/// Wakes up every so often so Ctrl-C doesn't have to wait for a long sleep.
+fn pace(start: Instant, bytes: u64, limit: u64) {
+    let due = Duration::from_secs_f64(bytes as f64 / limit as f64);
+    while !interrupt::requested() {
+        let Some(ahead) = due.checked_sub(start.elapsed()) else { break };
+        std::thread::sleep(ahead.min(Duration::from_millis(100)));
+    }
+}
+
+/// Writes `data` at `offset`, trying again a few times with a short pause if the drive errors.
+fn write_block(dest: &mut File, offset: u64, data: &[u8]) -> std::io::Result<()> {
+    let mut attempt = 1;
+    loop {
+        match dest.write_all(data) {
+            Ok(()) => return Ok(()),
+            Err(e) if attempt < WRITE_ATTEMPTS && e.kind() != ErrorKind::StorageFull => {
+                log::warn!("Write at {} failed ({}), retrying ({}/{})...", offset, e, attempt, WRITE_ATTEMPTS - 1);
+                std::thread::sleep(Duration::from_millis(200 * attempt as u64));
+                // A failed write_all may have got part of the way, start the block over.
+                dest.seek(SeekFrom::Start(offset))?;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// A block-sized buffer on the heap, aligned for O_DIRECT.
+struct AlignedBuffer {
+    storage: Vec<u8>,
+    offset: usize,
+    len: usize,
+}
+
+impl AlignedBuffer {
+    fn new(len: usize, align: usize) -> AlignedBuffer {
+        let storage = vec![0u8; len + align];
+        let offset = storage.as_ptr().align_offset(align);
+        AlignedBuffer { storage, offset, len }
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.storage[self.offset..self.offset + self.len]
+    }
+}
+
+/// A filled buffer from the read-ahead thread: how many bytes it holds, and how far into the file that got us.
+type ReadBlock = std::io::Result<(AlignedBuffer, usize, u64)>;
+
+/// Where [`write_image`] gets its blocks from: read in place into a single buffer, or from a thread
+/// that reads ahead into a few of them, so the drive never waits on the source and the other way round.
+enum BlockSource {
+    Inline {
+        source: Box<dyn Read + Send>,
+        read_pos: ReadCount,
+        buffer: AlignedBuffer,
+    },
+    ReadAhead {
+        filled: mpsc::Receiver<ReadBlock>,
+        /// Buffers the writer is done with go back to the reader over this.
+        empty: mpsc::Sender<AlignedBuffer>,
+        current: Option<AlignedBuffer>,
+    },
+}
+
+impl BlockSource {
+    fn new(mut source: Box<dyn Read + Send>, read_pos: ReadCount, buffers: usize, block_size: usize, align: usize) -> BlockSource {
+        if buffers <= 1 {
+            return BlockSource::Inline { source, read_pos, buffer: AlignedBuffer::new(block_size, align) };
+        }
+        let (filled_sender, filled) = mpsc::channel();
+        let (empty, empty_receiver) = mpsc::channel::<AlignedBuffer>();
+        for _ in 0..buffers {
+            let _ = empty.send(AlignedBuffer::new(block_size, align));
+        }
+        // Stops at the end of the image, on a read error, or once the writer hangs up.
+        std::thread::spawn(move || {
+            while let Ok(mut buffer) = empty_receiver.recv() {
+                let block = fill_buffer(&mut source, buffer.as_mut_slice()).map(|n| (buffer, n, read_pos.load(Ordering::Relaxed)));
+                let last = !matches!(block, Ok((_, n, _)) if n > 0);
+                if filled_sender.send(block).is_err() || last {
+                    break;
+                }
+            }
+        });
+        BlockSource::ReadAhead { filled, empty, current: None }
+    }
+
+    /// The next block: the whole buffer, how many bytes of it hold the image, and the position in the file.
+    /// The buffer before it goes back to be filled again.
+    fn next(&mut self) -> std::io::Result<(&mut [u8], usize, u64)> {
+        match self {
+            BlockSource::Inline { source, read_pos, buffer } => {
+                let n = fill_buffer(source, buffer.as_mut_slice())?;
+                Ok((buffer.as_mut_slice(), n, read_pos.load(Ordering::Relaxed)))
+            }
+            BlockSource::ReadAhead { filled, empty, current } => {
+                if let Some(done) = current.take() {
+                    let _ = empty.send(done);
+                }
+                let (buffer, n, position) = filled.recv()
+                    .map_err(|_| std::io::Error::other("the read-ahead thread stopped"))??;
+                Ok((current.insert(buffer).as_mut_slice(), n, position))
+            }
+        }
+    }
+}
+
+/// Reads until `buffer` is full or the source ends, so every write but the last is a whole block.
+fn fill_buffer(source: &mut dyn Read, buffer: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match source.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// Reads the drive back from `offset` and compares it against `expected`, bypassing the page cache where we can.
+fn check_tail(mut dest: &File, offset: u64, expected: &[u8], sector_size: usize) -> Result<bool, BurnError> {
+    #[cfg(target_os = "linux")]
+    unsafe {
+        libc::posix_fadvise(dest.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED);
+    }
+    dest.seek(SeekFrom::Start(offset))?;
+    // Whole sectors, for Windows.
+    let mut buffer = vec![0u8; expected.len().next_multiple_of(sector_size)];
+    let read = fill_buffer(&mut dest, &mut buffer).map_err(|e| BurnError::WriteFailed(e.to_string()))?;
+    Ok(read >= expected.len() && buffer[..expected.len()] == *expected)
+}
+
+/// Reads `len` bytes back from the drive, starting `offset` bytes in, and compares their SHA-256 against `expected`.
+pub fn verify_image(mut dest: &File, offset: u64, len: u64, expected: &[u8]) -> Result<bool, BurnError> {
+    dest.seek(SeekFrom::Start(offset))?;
+    // Drop whatever we just wrote from the page cache so we actually read the device.
+    #[cfg(target_os = "linux")]
+    unsafe {
+        libc::posix_fadvise(dest.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED);
+    }
+    let mut hasher = Sha256::new();
+    let mut bytes_read: u64 = 0;
+
+    // Always whole 64k reads, drives on Windows refuse anything that isn't whole sectors.
+    let mut buffer = [0u8; 65536];
+
+    while bytes_read < len {
+        if interrupt::requested() {
+            return Err(BurnError::Aborted);
+        }
+        let n = match dest.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => n.min((len - bytes_read) as usize),
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        hasher.update(&buffer[..n]);
+        bytes_read += n as u64;
+    }
+    if bytes_read != len {
+        // The drive is smaller than what we wrote to it.
+        return Ok(false);
+    }
+
+    Ok(hasher.finalize().as_slice() == expected)
+}
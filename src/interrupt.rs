@@ -0,0 +1,56 @@
+//! Ctrl-C handling: the first SIGINT asks the running burn to stop at the next safe point.
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static ABORT: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn on_sigint(_signal: libc::c_int) {
+    ABORT.store(true, Ordering::SeqCst);
+    // A second Ctrl-C kills the process the usual way.
+    unsafe {
+        libc::signal(libc::SIGINT, libc::SIG_DFL);
+    }
+}
+
+/// Installs the SIGINT handler. Only do this once the destructive part starts,
+/// before that Ctrl-C should just quit.
+#[cfg(unix)]
+pub fn install_handler() {
+    let handler: extern "C" fn(libc::c_int) = on_sigint;
+    unsafe {
+        libc::signal(libc::SIGINT, handler as libc::sighandler_t);
+    }
+}
+
+#[cfg(windows)]
+unsafe extern "system" fn on_ctrl_c(ctrl_type: u32) -> windows_sys::Win32::Foundation::BOOL {
+    use windows_sys::Win32::System::Console::CTRL_C_EVENT;
+    // A second Ctrl-C (or Ctrl-Break, closing the window...) goes on to the default handler, which exits.
+    if ctrl_type != CTRL_C_EVENT || ABORT.swap(true, Ordering::SeqCst) {
+        return 0;
+    }
+    1
+}
+
+/// Installs the Ctrl-C handler. Only do this once the destructive part starts,
+/// before that Ctrl-C should just quit.
+#[cfg(windows)]
+pub fn install_handler() {
+    unsafe {
+        windows_sys::Win32::System::Console::SetConsoleCtrlHandler(Some(on_ctrl_c), 1);
+    }
+}
+
+/// Installs the SIGINT handler. Not supported here yet, Ctrl-C keeps killing the process.
+#[cfg(not(any(unix, windows)))]
+pub fn install_handler() {}
+
+/// Asks the running burn to stop, for frontends that see Ctrl-C as a key press rather than a signal.
+pub fn request() {
+    ABORT.store(true, Ordering::SeqCst);
+}
+
+/// Whether the user asked us to stop.
+pub fn requested() -> bool {
+    ABORT.load(Ordering::SeqCst)
+}
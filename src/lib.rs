@@ -0,0 +1,887 @@
+//! Burn `.iso` images to drives.
+//!
+//! [`BurnJob`] describes a whole burn and runs it step by step; the modules expose
+//! each step on its own for frontends that want finer control.
+use std::cell::OnceCell;
+use std::fmt;
+use std::str::FromStr;
+
+pub mod checksum;
+pub mod device;
+pub mod error;
+pub mod format;
+pub mod image;
+pub mod interrupt;
+pub mod progress;
+pub mod table;
+
+pub use error::BurnError;
+use progress::{Step, StepEnd, StepSink};
+
+/// Partition table to create on the destination.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PartitionTable {
+    /// MBR, also known as dos.
+    Dos,
+    Gpt,
+    /// GPT plus a hybrid MBR pointing at the first partition too, for firmware that only reads MBR.
+    Hybrid,
+    /// No table at all, the filesystem takes the whole drive (a "superfloppy").
+    None,
+}
+
+impl FromStr for PartitionTable {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "dos" | "mbr" => Ok(PartitionTable::Dos),
+            "gpt" => Ok(PartitionTable::Gpt),
+            "hybrid" => Ok(PartitionTable::Hybrid),
+            "none" => Ok(PartitionTable::None),
+            _ => Err(format!("unknown partition table {} (expected dos, gpt, hybrid or none)", s)),
+        }
+    }
+}
+
+impl fmt::Display for PartitionTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PartitionTable::Dos => write!(f, "dos"),
+            PartitionTable::Gpt => write!(f, "gpt"),
+            PartitionTable::Hybrid => write!(f, "hybrid"),
+            PartitionTable::None => write!(f, "none"),
+        }
+    }
+}
+
+/// Filesystem to format the new partition with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Filesystem {
+    Fat32,
+    Fat16,
+    /// For floppy-sized volumes only, see [`format::FAT12_MAX_SIZE`].
+    Fat12,
+    Exfat,
+    /// Made by `mkfs.ntfs`, which has to be installed.
+    Ntfs,
+    /// Made by `mke2fs`, which has to be installed.
+    Ext4,
+    /// Leave the partition unformatted, for a later `dd` or a custom mkfs.
+    None,
+}
+
+impl FromStr for Filesystem {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "fat32" => Ok(Filesystem::Fat32),
+            "fat16" => Ok(Filesystem::Fat16),
+            "fat12" => Ok(Filesystem::Fat12),
+            "exfat" => Ok(Filesystem::Exfat),
+            "ntfs" => Ok(Filesystem::Ntfs),
+            "ext4" => Ok(Filesystem::Ext4),
+            "none" => Ok(Filesystem::None),
+            _ => Err(format!("unknown filesystem {} (expected fat32, fat16, fat12, exfat, ntfs, ext4 or none)", s)),
+        }
+    }
+}
+
+impl fmt::Display for Filesystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Filesystem::Fat32 => write!(f, "fat32"),
+            Filesystem::Fat16 => write!(f, "fat16"),
+            Filesystem::Fat12 => write!(f, "fat12"),
+            Filesystem::Exfat => write!(f, "exfat"),
+            Filesystem::Ntfs => write!(f, "ntfs"),
+            Filesystem::Ext4 => write!(f, "ext4"),
+            Filesystem::None => write!(f, "none"),
+        }
+    }
+}
+
+/// Parses a size like `4G`, `512M`, `64K` or a plain byte count. The suffixes are powers of 1024.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let (number, multiplier) = match s.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => {
+            let multiplier: u64 = match c.to_ascii_uppercase() {
+                'K' => 1024,
+                'M' => 1024 * 1024,
+                'G' => 1024 * 1024 * 1024,
+                'T' => 1024 * 1024 * 1024 * 1024,
+                _ => return Err(format!("unknown size suffix in {} (expected K, M, G or T)", s)),
+            };
+            (&s[..i], multiplier)
+        }
+        _ => (s, 1),
+    };
+    number.trim().parse::<u64>().ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| format!("invalid size {}", s))
+}
+
+/// Parses an `--add-file` spec, `source:dest`: the file to copy and where it goes on the volume.
+/// Split at the last colon, Windows paths have one of their own.
+pub fn parse_add_file(spec: &str) -> Result<(String, String), String> {
+    let Some((source, dest)) = spec.rsplit_once(':') else {
+        return Err("expected source:dest, like ks.cfg:/ks.cfg".to_string());
+    };
+    let dest = dest.trim_start_matches('/');
+    if source.is_empty() || dest.is_empty() || dest.ends_with('/') {
+        return Err("expected source:dest, like ks.cfg:/ks.cfg".to_string());
+    }
+    Ok((source.to_string(), dest.to_string()))
+}
+
+/// The destination of a [`BurnJob`] once it's opened, see [`BurnJob::disk`].
+/// A cloned job starts out with it closed, to open its own destination.
+#[derive(Default)]
+struct Disk(OnceCell<std::fs::File>);
+
+impl Clone for Disk {
+    fn clone(&self) -> Self {
+        Disk::default()
+    }
+}
+
+/// Everything needed to burn one image to one drive.
+///
+/// A job either writes the image onto the drive as it is ([`BurnJob::raw`]), or makes a partition table
+/// and a filesystem ([`BurnJob::new`]) and, on FAT, copies the files of the ISO onto it. Never both:
+/// the image would land on the partition table and the volume made for it.
+#[derive(Clone)]
+pub struct BurnJob {
+    /// Path to the image to burn.
+    pub source: String,
+    /// Path to the whole drive, e.g. `/dev/sdb`, or to an image file.
+    pub destination: String,
+    pub table: PartitionTable,
+    pub filesystem: Filesystem,
+    /// Volume label for the new filesystem.
+    pub label: String,
+    /// Copy the image straight onto the drive, skipping partitioning and formatting.
+    /// Otherwise the image itself is never written, see [`BurnJob::copies_files`].
+    pub raw: bool,
+    /// Make the table and the filesystem and leave the volume empty, without the files of the ISO.
+    /// Only FAT takes the files, the other filesystems have to be asked for empty.
+    pub empty: bool,
+    /// FAT cluster size in bytes, `None` picks one from the volume size.
+    pub cluster_size: Option<u32>,
+    /// Zero the whole exFAT volume while formatting, not just its metadata. Slow.
+    pub full_format: bool,
+    /// Pack the exFAT allocation bitmap in before the cluster heap.
+    pub pack_bitmap: bool,
+    /// Zero both ends of the drive before anything else, see [`table::wipe`].
+    pub wipe: bool,
+    /// Size of the chunks the image is copied in, in bytes.
+    pub block_size: usize,
+    /// Blocks in flight between reading the image and writing the drive, see [`image::WriteOptions::buffers`].
+    pub buffers: usize,
+    /// Write around the page cache (O_DIRECT, Linux only).
+    pub direct: bool,
+    /// Read the drive back while writing it, so [`BurnJob::verify`] doesn't need a pass of its own.
+    pub read_back: bool,
+    /// Size in bytes of an ext4 persistence partition after the main one, for live USBs.
+    pub persistence: Option<u64>,
+    /// MBR partition type byte, `None` picks the one that fits the filesystem.
+    pub mbr_type: Option<u8>,
+    /// Mark the MBR partition active (bootable), some old BIOSes won't boot a drive without one.
+    pub boot_flag: bool,
+    /// GPT partition type, `None` picks the one that fits the filesystem (an ESP for bootable FAT images).
+    pub gpt_type: Option<gpt::partition_types::Type>,
+    /// GPT partition name, `None` uses the volume label. MBR partitions have no name.
+    pub partition_label: Option<String>,
+    /// GPT disk GUID, `None` picks a random one.
+    pub disk_guid: Option<uuid::Uuid>,
+    /// GUID of the first GPT partition, `None` picks a random one.
+    pub partition_guid: Option<uuid::Uuid>,
+    /// Partitions start on a multiple of this many bytes, see [`table::DEFAULT_ALIGNMENT`].
+    pub alignment: u64,
+    /// Where the backup GPT goes, see [`table::GptBackup`].
+    pub gpt_backup: table::GptBackup,
+    /// GPT partitions to make instead of the single one, see [`table::parse_layout`].
+    /// `filesystem`, `label` and `persistence` are ignored with it.
+    pub layout: Option<Vec<table::LayoutEntry>>,
+    /// Files to copy onto the FAT volume once it's formatted, as (source, path on the volume) pairs.
+    pub add_files: Vec<(String, String)>,
+    /// Make the image's partition this many bytes larger than what goes on it, and check that it's still free
+    /// once the files are copied, see [`BurnJob::check_free`].
+    pub min_free: Option<u64>,
+    source_size: OnceCell<u64>,
+    disk: Disk,
+    /// Continue an interrupted burn: skip partitioning and formatting,
+    /// and only write from the first block that isn't on the drive yet.
+    pub resume: bool,
+    /// Cap on the write rate in bytes per second, `None` writes as fast as the drive goes.
+    pub limit: Option<u64>,
+    /// Start writing this many bytes into the destination. Raw jobs only.
+    pub seek: u64,
+    /// Leave out this many bytes from the start of the image. Raw jobs only.
+    pub skip: u64,
+    /// Give up on the write once it has made no progress for this long, `None` waits forever.
+    pub timeout: Option<std::time::Duration>,
+    /// Create the destination as an image file of this many bytes instead of burning to a drive.
+    /// Existing image files are burned into as they are without it.
+    pub image_size: Option<u64>,
+}
+
+impl BurnJob {
+    /// A job that makes a `table` and a `filesystem` labelled `label`, then copies the files of the ISO at `source`
+    /// onto it if it's FAT. Only uncompressed ISOs have files to copy, see [`BurnJob::check_files`].
+    pub fn new(source: &str, destination: &str, table: PartitionTable, filesystem: Filesystem, label: &str) -> Self {
+        BurnJob {
+            source: source.to_string(),
+            destination: destination.to_string(),
+            table,
+            filesystem,
+            label: label.to_string(),
+            raw: false,
+            empty: false,
+            cluster_size: None,
+            full_format: false,
+            pack_bitmap: false,
+            wipe: false,
+            block_size: image::DEFAULT_BLOCK_SIZE,
+            buffers: image::DEFAULT_BUFFERS,
+            direct: false,
+            read_back: false,
+            resume: false,
+            limit: None,
+            timeout: None,
+            seek: 0,
+            skip: 0,
+            image_size: None,
+            persistence: None,
+            mbr_type: None,
+            boot_flag: false,
+            gpt_type: None,
+            partition_label: None,
+            disk_guid: None,
+            partition_guid: None,
+            alignment: table::DEFAULT_ALIGNMENT,
+            gpt_backup: table::GptBackup::End,
+            layout: None,
+            add_files: Vec::new(),
+            min_free: None,
+            source_size: OnceCell::new(),
+            disk: Disk::default(),
+        }
+    }
+
+    /// A job that only copies the image onto the drive, like `dd` would.
+    /// `table`, `filesystem` and `label` are left at their defaults and ignored.
+    pub fn raw(source: &str, destination: &str) -> Self {
+        BurnJob {
+            raw: true,
+            ..BurnJob::new(source, destination, PartitionTable::Gpt, Filesystem::Fat32, "")
+        }
+    }
+
+    /// The same job for another destination, to burn one image to several drives.
+    /// The source size is carried over, the destination is opened on its own.
+    pub fn for_destination(&self, destination: &str) -> Self {
+        BurnJob { destination: destination.to_string(), ..self.clone() }
+    }
+
+    /// Size of the source image in bytes, once decompressed.
+    /// Worked out once, compressed images have to be decompressed for it.
+    pub fn source_size(&self) -> Result<u64, BurnError> {
+        if let Some(&size) = self.source_size.get() {
+            return Ok(size);
+        }
+        let size = image::image_size(&self.source)?;
+        Ok(*self.source_size.get_or_init(|| size))
+    }
+
+    /// The destination, opened on first use and shared by every step after that.
+    /// Only partition nodes get opened on their own, they come and go with the table.
+    pub fn disk(&self) -> Result<&std::fs::File, BurnError> {
+        if let Some(disk) = self.disk.0.get() {
+            return Ok(disk);
+        }
+        let disk = device::open_device(&self.destination)?;
+        Ok(self.disk.0.get_or_init(|| disk))
+    }
+
+    /// Checks that the source can be read and the destination is a drive (or image file) we can write to,
+    /// and that the files of the source can go onto the volume unless it's to be left empty.
+    pub fn check(&self) -> Result<(), BurnError> {
+        if !device::is_block(&self.destination) && !device::is_image_file(&self.destination) {
+            return Err(BurnError::NotBlockDevice(self.destination.clone()));
+        }
+        let (file_perm, dest_perm) = device::check_permissions(&self.source, &self.destination);
+        file_perm?;
+        dest_perm?;
+        if !self.raw && !self.empty {
+            self.check_files()?;
+        }
+        Ok(())
+    }
+
+    /// Whether the files of the ISO go onto the first volume: only FAT takes them, unless it's to be left [`BurnJob::empty`].
+    pub fn copies_files(&self) -> bool {
+        let filesystem = self.layout.as_ref().map_or(self.filesystem, |layout| layout[0].filesystem);
+        !self.raw && !self.empty && matches!(filesystem, Filesystem::Fat32 | Filesystem::Fat16 | Filesystem::Fat12)
+    }
+
+    /// Checks that the files of the source can be copied onto the job's first volume: the source is an uncompressed ISO,
+    /// the volume FAT, and none of the files larger than FAT holds.
+    pub fn check_files(&self) -> Result<(), BurnError> {
+        let filesystem = self.layout.as_ref().map_or(self.filesystem, |layout| layout[0].filesystem);
+        if !matches!(filesystem, Filesystem::Fat32 | Filesystem::Fat16 | Filesystem::Fat12) {
+            return Err(BurnError::CopyFiles(format!("they only go onto FAT, not {}", filesystem)));
+        }
+        if image::Compression::detect(&self.source)? != image::Compression::None {
+            return Err(BurnError::CopyFiles("the image is compressed".to_string()));
+        }
+        image::check_iso(&self.source)?;
+        if let Some(file) = image::iso_files(&self.source)?.iter().find(|file| file.len > u32::MAX as u64) {
+            return Err(BurnError::CopyFiles(format!("{} is larger than FAT holds in one file (4 GiB)", file.path)));
+        }
+        Ok(())
+    }
+
+    /// Creates the destination image file, if the job makes one (see [`BurnJob::image_size`]).
+    pub fn create_image(&self) -> Result<(), BurnError> {
+        match self.image_size {
+            Some(size) => device::create_image(&self.destination, size),
+            None => Ok(()),
+        }
+    }
+
+    /// Unmounts anything mounted from the destination.
+    pub fn unmount(&self) -> Result<(), BurnError> {
+        device::unmount_partitions(&self.destination)
+    }
+
+    /// Zeroes the old tables and filesystem signatures at both ends of the destination.
+    pub fn wipe(&self) -> Result<(), BurnError> {
+        let disk = self.disk()?;
+        table::wipe(disk)?;
+        disk.sync_all()?;
+        Ok(())
+    }
+
+    /// Size of the volume the filesystem goes on: the image's partition,
+    /// or the whole destination without a partition table.
+    pub fn volume_size(&self) -> Result<u64, BurnError> {
+        match (self.table, self.image_size) {
+            (PartitionTable::None, Some(size)) => Ok(size),
+            (PartitionTable::None, None) => Ok(device::device_size(self.disk()?)?),
+            _ => self.content_size(),
+        }
+    }
+
+    /// Bytes the job makes room for on the drive: the ISO's files laid out on FAT when they are copied,
+    /// else as much as the image, plus `min_free`.
+    fn content_size(&self) -> Result<u64, BurnError> {
+        let size = if self.copies_files() {
+            format::fat_room(&image::iso_files(&self.source)?)
+        } else {
+            self.source_size()?
+        };
+        Ok(size + self.min_free.unwrap_or(0))
+    }
+
+    /// Bytes of files that go onto the volume: the ISO's if they are copied, and the `add_files`.
+    pub fn files_size(&self) -> Result<u64, BurnError> {
+        let mut size = 0;
+        if self.copies_files() {
+            size += image::iso_files(&self.source)?.iter().map(|file| file.len).sum::<u64>();
+        }
+        for (source, _) in &self.add_files {
+            size += std::fs::metadata(source)?.len();
+        }
+        Ok(size)
+    }
+
+    /// Checks that at least `min_free` bytes are left on the volume once the files are on it, see [`format::free_space`].
+    pub fn check_free(&self) -> Result<(), BurnError> {
+        let Some(min_free) = self.min_free else { return Ok(()) };
+        let free = format::free_space(self.disk()?, &self.destination, self.first_partition())?;
+        if free < min_free {
+            return Err(BurnError::MinFree(min_free, free));
+        }
+        Ok(())
+    }
+
+    /// Checks that the filesystem can be made as large as the volume, see [`format::max_volume_size`].
+    /// The partitions of a layout are left to [`BurnJob::format`].
+    pub fn check_volume_size(&self) -> Result<(), BurnError> {
+        if self.raw || self.layout.is_some() {
+            return Ok(());
+        }
+        let Some(max) = format::max_volume_size(self.filesystem) else { return Ok(()) };
+        let size = self.volume_size()?;
+        if size > max {
+            let fat = match self.filesystem {
+                Filesystem::Fat12 => 12,
+                Filesystem::Fat16 => 16,
+                _ => 32,
+            };
+            return Err(BurnError::VolumeTooLarge(fat, size));
+        }
+        Ok(())
+    }
+
+    /// Whether the source is an ISO with an El Torito boot catalog. Compressed images can't be looked into.
+    pub fn is_bootable(&self) -> bool {
+        matches!(image::Compression::detect(&self.source), Ok(image::Compression::None))
+            && matches!(image::has_boot_catalog(&self.source), Ok(true))
+    }
+
+    /// Writes a fresh partition table to the destination and waits for the kernel to create the partitions.
+    pub fn partition(&self) -> Result<(), BurnError> {
+        let iso_size = self.content_size()?;
+        let disk = self.disk()?;
+        match self.table {
+            PartitionTable::Dos | PartitionTable::Hybrid if self.layout.is_some() => {
+                return Err(BurnError::PartitionTable("layouts are only supported on GPT".to_string()));
+            }
+            PartitionTable::None if self.layout.is_some() || self.persistence.is_some() => {
+                return Err(BurnError::PartitionTable("a drive without a partition table holds a single filesystem".to_string()));
+            }
+            PartitionTable::None => {
+                // Whatever table was there would still claim the drive once the filesystem is on it.
+                table::clear_table(disk)?;
+                if !device::is_image_file(&self.destination) {
+                    disk.sync_all()?;
+                    let reread = device::reread_partitions(disk);
+                    log::debug!("re-reading the partition table of {}: {:?}", self.destination, reread);
+                }
+                return Ok(());
+            }
+            PartitionTable::Dos => {
+                let sys = self.mbr_type.unwrap_or_else(|| table::mbr_type(self.filesystem));
+                table::new_dos_mbr(disk, iso_size, self.persistence, sys, self.boot_flag, self.alignment)?;
+            }
+            PartitionTable::Gpt | PartitionTable::Hybrid => {
+                let options = table::GptOptions {
+                    partitions: self.gpt_partitions(iso_size),
+                    alignment: self.alignment,
+                    disk_guid: self.disk_guid,
+                    hybrid: (self.table == PartitionTable::Hybrid).then(|| table::HybridMbr {
+                        sys: self.mbr_type.unwrap_or_else(|| table::mbr_type(self.filesystem)),
+                        active: self.boot_flag,
+                    }),
+                    backup: self.gpt_backup,
+                };
+                table::new_gpt(disk, &options)?;
+            }
+        }
+        // Formatting opens the partitions right after, they have to exist by then.
+        // Image files are formatted through the table itself, the kernel never sees them.
+        if device::is_image_file(&self.destination) {
+            return Ok(());
+        }
+        // The table went through our handle, get it onto the drive before the kernel reads it back.
+        disk.sync_all()?;
+        table::settle_partitions(disk, &self.destination, self.partition_count())
+    }
+
+    /// The GPT partitions to make: the ones in the layout, or one for the image plus the persistence one.
+    fn gpt_partitions(&self, iso_size: u64) -> Vec<table::GptPartition> {
+        let mut partitions = Vec::new();
+        if let Some(layout) = &self.layout {
+            for entry in layout {
+                // UEFI firmware only looks at the partition typed as the ESP.
+                let part_type = if entry.name.eq_ignore_ascii_case("esp") {
+                    gpt::partition_types::EFI
+                } else {
+                    table::gpt_type(entry.filesystem, false)
+                };
+                partitions.push(table::GptPartition { name: entry.name.clone(), size: entry.size, part_type, guid: None });
+            }
+        } else {
+            partitions.push(table::GptPartition {
+                name: self.partition_label.clone().unwrap_or_else(|| self.label.clone()),
+                size: table::PartitionSize::Bytes(iso_size),
+                part_type: self.gpt_type.clone().unwrap_or_else(|| table::gpt_type(self.filesystem, self.is_bootable())),
+                guid: None,
+            });
+            if let Some(size) = self.persistence {
+                partitions.push(table::GptPartition {
+                    name: table::PERSISTENCE_LABEL.to_string(),
+                    size: table::PartitionSize::Bytes(size),
+                    part_type: gpt::partition_types::LINUX_FS,
+                    guid: None,
+                });
+            }
+        }
+        // The persistence partition still gets a random one.
+        if let Some(first) = partitions.first_mut() {
+            first.guid = self.partition_guid;
+        }
+        partitions
+    }
+
+    /// Number of the partition the filesystem goes on, 0 (the whole drive) without a partition table.
+    fn first_partition(&self) -> u32 {
+        match self.table {
+            PartitionTable::None => 0,
+            _ => 1,
+        }
+    }
+
+    /// How many partitions [`BurnJob::partition`] makes.
+    pub fn partition_count(&self) -> usize {
+        match &self.layout {
+            Some(layout) => layout.len(),
+            None if self.persistence.is_some() => 2,
+            None => 1,
+        }
+    }
+
+    /// Formats the first partition of the destination, or every partition of the layout
+    /// (labelled with their names). [`Filesystem::None`] leaves a partition alone.
+    pub fn format(&self) -> Result<(), BurnError> {
+        match &self.layout {
+            Some(layout) => {
+                for (i, entry) in layout.iter().enumerate() {
+                    self.format_partition(i as u32 + 1, entry.filesystem, &entry.name)?;
+                }
+                Ok(())
+            }
+            None => self.format_partition(self.first_partition(), self.filesystem, &self.label),
+        }
+    }
+
+    fn format_partition(&self, number: u32, filesystem: Filesystem, label: &str) -> Result<(), BurnError> {
+        let disk = self.disk()?;
+        match filesystem {
+            Filesystem::Fat32 => format::make_fat(disk, &self.destination, number, label, 32, self.cluster_size),
+            Filesystem::Fat16 => format::make_fat(disk, &self.destination, number, label, 16, self.cluster_size),
+            Filesystem::Fat12 => format::make_fat(disk, &self.destination, number, label, 12, self.cluster_size),
+            Filesystem::Exfat => format::make_exfat(disk, &self.destination, number, label, self.full_format, self.pack_bitmap),
+            Filesystem::Ntfs => format::make_ntfs(disk, &self.destination, number, label),
+            Filesystem::Ext4 => format::make_ext4(disk, &self.destination, number, label),
+            Filesystem::None => Ok(()),
+        }
+    }
+
+    /// Copies the `add_files` onto the first partition (the whole drive, without a partition table),
+    /// which [`BurnJob::format`] has to have made a FAT volume, see [`format::add_file`].
+    pub fn add_files(&self) -> Result<(), BurnError> {
+        for (source, dest) in &self.add_files {
+            format::add_file(self.disk()?, &self.destination, self.first_partition(), source, dest)?;
+        }
+        Ok(())
+    }
+
+    /// Formats the persistence partition, if there is one.
+    /// Returns `false` if it had to be left unformatted because `mkfs.ext4` is missing.
+    pub fn format_persistence(&self) -> Result<bool, BurnError> {
+        match self.persistence {
+            Some(_) => format::make_persistence(self.disk()?, &self.destination),
+            None => Ok(true),
+        }
+    }
+
+    /// Bytes the burn takes on the destination: the image (past `skip`, written `seek` bytes in),
+    /// plus the partition table and the persistence partition unless it's written raw.
+    /// With a layout, the larger of the image and the fixed-size partitions plus the table.
+    pub fn required_size(&self) -> Result<u64, BurnError> {
+        let size = self.seek + self.content_size()?.saturating_sub(self.skip);
+        if self.raw || self.resume || self.table == PartitionTable::None {
+            return Ok(size);
+        }
+        if let Some(layout) = &self.layout {
+            return Ok(size.max(table::layout_size(layout) + self.table_overhead()));
+        }
+        Ok(size + self.persistence.unwrap_or(0) + self.table_overhead())
+    }
+
+    /// Room the table and the gaps before aligned partitions take, at least [`table::TABLE_OVERHEAD`].
+    fn table_overhead(&self) -> u64 {
+        table::TABLE_OVERHEAD.max(self.alignment.saturating_mul(self.partition_count() as u64 + 1))
+    }
+
+    /// Checks that everything fits on the destination, see [`BurnJob::required_size`].
+    pub fn check_capacity(&self) -> Result<(), BurnError> {
+        // An image on stdin has no size to go by, the write itself fails once the drive is full.
+        if self.source == image::STDIN {
+            return Ok(());
+        }
+        let needed = self.required_size()?;
+        let available = match self.image_size {
+            Some(size) => size,
+            None => device::device_size(self.disk()?)?,
+        };
+        if available == 0 && !device::is_image_file(&self.destination) {
+            return Err(BurnError::NoMedium(self.destination.clone()));
+        }
+        log::debug!("{} needs {} bytes, {} has {}", self.source, needed, self.destination, available);
+        if needed > available {
+            return Err(BurnError::DeviceTooSmall(needed, available));
+        }
+        Ok(())
+    }
+
+    /// Copies the image onto the destination, reporting to `progress` as it goes.
+    /// Returns how much was written and the SHA-256 of the source, see [`image::Written`].
+    /// Unless the job is raw the files of the ISO go onto the first volume instead, see [`format::extract_iso`],
+    /// or nothing does if it isn't FAT. Either way the partition table is checked to have come through
+    /// (see [`table::check_table`]). There is no image on the drive to check then, and the SHA-256 is left empty.
+    pub fn write(&self, progress: &mut dyn progress::ProgressSink) -> Result<image::Written, BurnError> {
+        if !self.raw {
+            let len = if self.copies_files() {
+                let files = image::iso_files(&self.source)?;
+                format::extract_iso(self.disk()?, &self.destination, self.first_partition(), &self.source, &files, progress)?
+            } else {
+                0
+            };
+            table::check_table(self.disk()?, self.table, self.filesystem, self.gpt_backup)?;
+            return Ok(image::Written { len, sha256: Vec::new(), read_back: None });
+        }
+        let options = image::WriteOptions {
+            block_size: self.block_size,
+            buffers: self.buffers,
+            direct: self.direct,
+            resume: self.resume,
+            limit: self.limit,
+            seek: self.seek,
+            skip: self.skip,
+            verify: self.read_back,
+        };
+        match self.timeout {
+            Some(timeout) => image::write_image_watched(&self.source, self.disk()?, &self.destination, &options, progress, timeout),
+            None => image::write_image(&self.source, self.disk()?, &self.destination, &options, progress),
+        }
+    }
+
+    /// Ejects (powers off) the destination so it can be unplugged.
+    /// Our own handle is closed first, nothing else gets done with the drive after this.
+    pub fn eject(&mut self) -> Result<(), BurnError> {
+        self.disk.0.take();
+        device::eject(&self.destination)
+    }
+
+    /// Checks that the destination holds what [`BurnJob::write`] put there: by what was read back
+    /// during the write when it got to (see [`BurnJob::read_back`]), else by reading it all back now.
+    /// Copied files are read back from the volume and compared with the ISO, see [`format::verify_iso`].
+    /// An empty volume has nothing to check.
+    pub fn verify(&self, written: &image::Written) -> Result<bool, BurnError> {
+        if self.copies_files() {
+            let files = image::iso_files(&self.source)?;
+            return format::verify_iso(self.disk()?, &self.destination, self.first_partition(), &self.source, &files);
+        }
+        if !self.raw {
+            return Ok(true);
+        }
+        match &written.read_back {
+            Some(read_back) => Ok(*read_back == written.sha256),
+            None => image::verify_image(self.disk()?, self.seek, written.len, &written.sha256),
+        }
+    }
+
+    /// Compares the destination against the source without writing anything, see [`image::compare_image`].
+    /// The destination is opened read-only for it, the shared handle isn't needed.
+    pub fn compare(&self) -> Result<image::Comparison, BurnError> {
+        let options = image::WriteOptions {
+            block_size: self.block_size,
+            buffers: self.buffers,
+            seek: self.seek,
+            skip: self.skip,
+            ..image::WriteOptions::default()
+        };
+        let dest = std::fs::File::open(&self.destination)?;
+        image::compare_image(&self.source, &dest, &options)
+    }
+
+    /// Mounts the first partition (or the whole drive, without a partition table) read-only,
+    /// lists its root and unmounts it, see [`device::mount_check`].
+    /// Returns the filesystem type that mounted and how many entries the root has.
+    pub fn mount_check(&self) -> Result<(String, usize), BurnError> {
+        if self.table == PartitionTable::None {
+            return device::mount_check(&self.destination);
+        }
+        // The kernel may not have picked up the new table yet.
+        table::settle_partitions(self.disk()?, &self.destination, 1).map_err(|e| match e {
+            BurnError::PartitionTable(msg) => BurnError::MountCheck(msg),
+            e => e,
+        })?;
+        device::mount_check(&device::partition_path(&self.destination, 1))
+    }
+
+    /// A shell command to run once the burn is done (`--after`), with the job in its environment:
+    /// `BURN_DEVICE`, `BURN_SOURCE`, and `BURN_LABEL`, `BURN_TABLE` and `BURN_FILESYSTEM` (empty for raw jobs).
+    /// The caller spawns it, and decides where its output goes.
+    pub fn after_command(&self, command: &str) -> std::process::Command {
+        let mut shell = if cfg!(windows) {
+            let mut shell = std::process::Command::new("cmd");
+            shell.arg("/C");
+            shell
+        } else {
+            let mut shell = std::process::Command::new("sh");
+            shell.arg("-c");
+            shell
+        };
+        let (label, table, filesystem) = if self.raw {
+            (String::new(), String::new(), String::new())
+        } else {
+            (self.label.clone(), self.table.to_string(), self.filesystem.to_string())
+        };
+        shell.arg(command)
+            .env("BURN_DEVICE", &self.destination)
+            .env("BURN_SOURCE", &self.source)
+            .env("BURN_LABEL", label)
+            .env("BURN_TABLE", table)
+            .env("BURN_FILESYSTEM", filesystem);
+        shell
+    }
+
+    /// Runs every step of the burn in order, telling `steps` about each and `progress` how far the write is:
+    /// create the image file (if any), check the destination, unmount, wipe, partition, format, add the files,
+    /// make the persistence partition, write, then the optional steps of `options` after it.
+    /// The checks that need asking the user come first, before the drive is erased: this only catches
+    /// what has to stop a burn anyway. Ctrl-C stops it before the next step with [`BurnError::Aborted`].
+    /// Returns what [`BurnJob::write`] does.
+    pub fn run(&mut self, options: &RunOptions, steps: &mut dyn StepSink, progress: &mut dyn progress::ProgressSink) -> Result<image::Written, BurnError> {
+        if self.image_size.is_some() {
+            self.begin(steps, Step::CreateImage)?;
+            self.end(steps, Step::CreateImage, self.create_image(), |_| StepEnd::Done(None))?;
+        }
+        self.begin(steps, Step::Check)?;
+        self.end(steps, Step::Check, self.check_destination(options), |_| StepEnd::Done(None))?;
+        if self.image_size.is_none() && !device::is_image_file(&self.destination) {
+            self.begin(steps, Step::Unmount)?;
+            let result = match self.unmount() {
+                Err(BurnError::Busy(e)) if options.force_busy => Ok(Some(e)),
+                result => result.map(|()| None),
+            };
+            self.end(steps, Step::Unmount, result, |busy| match busy {
+                Some(e) => StepEnd::Warning(format!("{}, writing anyway", e)),
+                None => StepEnd::Done(None),
+            })?;
+        }
+        if self.wipe {
+            self.begin(steps, Step::Wipe)?;
+            self.end(steps, Step::Wipe, self.wipe(), |_| StepEnd::Done(None))?;
+        }
+        if !self.raw {
+            self.begin(steps, Step::Partition)?;
+            self.end(steps, Step::Partition, self.partition(), |_| StepEnd::Done(None))?;
+            if self.layout.is_none() && self.filesystem == Filesystem::None {
+                steps.end(self, Step::Format, StepEnd::Skipped("the partition is left unformatted"));
+            } else {
+                self.begin(steps, Step::Format)?;
+                self.end(steps, Step::Format, self.format(), |_| StepEnd::Done(None))?;
+            }
+            if !self.add_files.is_empty() {
+                self.begin(steps, Step::AddFiles)?;
+                self.end(steps, Step::AddFiles, self.add_files(), |_| StepEnd::Done(None))?;
+            }
+            if self.persistence.is_some() {
+                self.begin(steps, Step::Persistence)?;
+                self.end(steps, Step::Persistence, self.format_persistence(), |formatted| if *formatted {
+                    StepEnd::Done(None)
+                } else {
+                    StepEnd::Warning("mkfs.ext4 not found, the persistence partition is left unformatted".to_string())
+                })?;
+            }
+        }
+        self.begin(steps, Step::Write)?;
+        let written = self.end(steps, Step::Write, self.write(progress), |_| if self.raw || self.copies_files() {
+            StepEnd::Done(None)
+        } else {
+            StepEnd::Skipped("the volume is left empty")
+        })?;
+        if self.min_free.is_some() {
+            self.begin(steps, Step::CheckFree)?;
+            self.end(steps, Step::CheckFree, self.check_free(), |_| StepEnd::Done(None))?;
+        }
+        if options.verify && !self.raw && !self.copies_files() {
+            steps.end(self, Step::Verify, StepEnd::Skipped("nothing was written"));
+        } else if options.verify {
+            self.begin(steps, Step::Verify)?;
+            let result = self.verify(&written).and_then(|same| if same {
+                Ok(())
+            } else {
+                Err(BurnError::WriteFailed("the data on the drive does not match the image".to_string()))
+            });
+            self.end(steps, Step::Verify, result, |_| StepEnd::Done(None))?;
+        }
+        if options.mount_check {
+            self.begin(steps, Step::MountCheck)?;
+            self.end(steps, Step::MountCheck, self.mount_check(), |(fstype, entries)| StepEnd::Done(Some(format!("{}, {} entries", fstype, entries))))?;
+        }
+        if let Some(command) = &options.after {
+            self.begin(steps, Step::After)?;
+            let mut after = self.after_command(command);
+            if options.after_to_stderr {
+                after.stdout(std::io::stderr());
+            }
+            let result = after.status().map_err(BurnError::from).and_then(|status| if status.success() {
+                Ok(status)
+            } else {
+                Err(BurnError::Io(std::io::Error::other(format!("the --after command failed ({})", status))))
+            });
+            self.end(steps, Step::After, result, |status| StepEnd::Done(Some(status.to_string())))?;
+        }
+        if options.eject {
+            self.begin(steps, Step::Eject)?;
+            // Not a failure, the image is on the drive.
+            let end = match self.eject() {
+                Ok(()) => StepEnd::Done(None),
+                Err(e) => StepEnd::Warning(format!("{}, eject it yourself before unplugging", e)),
+            };
+            steps.end(self, Step::Eject, end);
+        }
+        Ok(written)
+    }
+
+    /// What has to stop a burn whatever the user said: the running system on the destination,
+    /// or an image that doesn't fit, unless `options` let those pass.
+    fn check_destination(&self, options: &RunOptions) -> Result<(), BurnError> {
+        if !options.force_system_disk && !device::is_image_file(&self.destination) && device::is_system_disk(&self.destination) {
+            return Err(BurnError::SystemDisk(self.destination.clone()));
+        }
+        match self.check_capacity() {
+            Err(BurnError::DeviceTooSmall(..)) if options.force_capacity => {}
+            result => result?,
+        }
+        self.check_volume_size()
+    }
+
+    /// Tells `steps` that `step` starts, unless Ctrl-C was hit: then the burn stops before it.
+    fn begin(&self, steps: &mut dyn StepSink, step: Step) -> Result<(), BurnError> {
+        if interrupt::requested() {
+            return Err(BurnError::Aborted);
+        }
+        steps.start(self, step);
+        Ok(())
+    }
+
+    /// Tells `steps` how `step` went, `end` says what came of it when it worked. Passes the result on.
+    fn end<T>(&self, steps: &mut dyn StepSink, step: Step, result: Result<T, BurnError>, end: impl FnOnce(&T) -> StepEnd<'static>) -> Result<T, BurnError> {
+        match &result {
+            Ok(value) => steps.end(self, step, end(value)),
+            Err(e) => steps.end(self, step, StepEnd::Failed(e)),
+        }
+        result
+    }
+}
+
+/// The optional steps of [`BurnJob::run`], and the checks it lets pass.
+#[derive(Clone, Debug, Default)]
+pub struct RunOptions {
+    /// Read the destination back once it's written, see [`BurnJob::verify`].
+    pub verify: bool,
+    /// Mount the first partition to check it, see [`BurnJob::mount_check`].
+    pub mount_check: bool,
+    /// Shell command to run once the burn is done, see [`BurnJob::after_command`].
+    pub after: Option<String>,
+    /// Send what the `after` command prints to stderr, to keep stdout for JSON.
+    pub after_to_stderr: bool,
+    /// Eject the destination at the end, see [`BurnJob::eject`].
+    pub eject: bool,
+    /// Burn onto the drive the running system is on.
+    pub force_system_disk: bool,
+    /// Burn even when the destination looks too small, some card readers report the wrong size.
+    pub force_capacity: bool,
+    /// Write even when partitions on the destination can't be unmounted.
+    pub force_busy: bool,
+}
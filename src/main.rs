@@ -1,92 +1,112 @@
 use std::error::Error;
-use clap::Parser;
-use std::fs::OpenOptions;
-use std::io::{stdout, Cursor, Read, Seek, SeekFrom, Write};
-use gpt::{GptConfig, partition_types};
-use gpt::mbr::ProtectiveMBR;
-use sysinfo::{Disks, System};
-use uuid::Uuid;
-use std::fs::File;
-use std::os::fd::AsRawFd;
-use exfat_fs::format::{Exfat, FormatVolumeOptionsBuilder, Label};
-use fatfs::{format_volume, FatType, FormatVolumeOptions};
-use fatfs::FatType::{Fat12, Fat16, Fat32};
-use libc::{bind, ioctl, BLKSSZGET};
-use iso9660_simple::ISO9660;
-use iso9660_simple::{helpers, Read as ISORead, *};
-struct FileDevice(File);
-impl ISORead for FileDevice {
-    fn read(&mut self, position: usize, size: usize, buffer: &mut [u8]) -> Option<()> {
-        if self.0.seek(SeekFrom::Start(position as u64)).is_err() { return None; }
-        if self.0.read_exact(&mut buffer[..size]).is_ok() { Some(()) } else { None }
-    }
-}
-
-/// This function uses the `gpt` crate to create a new GPT table
-fn new_gpt(device_path: &str, iso_size: u64) -> Result<(), Box<dyn Error>> {
-    let mut disk = OpenOptions::new()
-        .read(true)
-        .write(true)
-        .open(&device_path)?;
-
-    // Initialize a new GPT partition table
-    let mut gpt = GptConfig::new()
-        .writable(true)
-        .create_from_device(&mut disk, Some(Uuid::new_v4()))?; // Creates a new GPT with a unique disk GUID
-
-    // use iso_size to make the partition size be the same as the iso.
-    gpt.add_partition(
-        "temporary",
-        iso_size+512,
-        partition_types::BASIC,
-        0,
-        None, // no guid
-    )?;
-    // Write the GPT table back to the disk
-    gpt.write()?; // This writes the GPT partition table
-    let protective_mbr = ProtectiveMBR::new();
-    protective_mbr.overwrite_lba0(&mut disk)?; // This writes protection MBR.
-
-    Ok(()) // Success
-}
-
-/// This function writes a new MBR [dos] table to a disk drive.
-fn new_dos_mbr(device_path: &str, iso_size: u64) -> Result<(), Box<dyn Error>> {
-    let ss = 512;
-    let iso_size = iso_size+512u64;
-    let mut disk = OpenOptions::new().write(true).read(true).open(&device_path)?;
-    let mut mbr = mbrman::MBR::new_from(&mut disk, ss as u32, [0xff;4])?;
-    mbr.write_into(&mut disk)?;
-    let free_part_number = mbr.iter().find(|(i,p)| p.is_unused()).map(|(i,_)| i)
-        .expect("No free partition");
-    let sectors = (iso_size / ss) as u32;
-    let starting_lba = mbr.find_optimal_place(sectors)
-        .expect("Couldn't find a place to put the partition.");
-
-        mbr[free_part_number] = mbrman::MBRPartitionEntry {
-            boot: mbrman::BOOT_INACTIVE,
-            first_chs: mbrman::CHS::empty(),
-            sys: 0x83,
-            last_chs: mbrman::CHS::empty(),
-            starting_lba,
-            sectors
-        };
-    mbr.write_into(&mut disk)?;
-    Ok(())
-}
+use clap::{CommandFactory, FromArgMatches, Parser};
+use clap::parser::ValueSource;
+use clap_complete::Shell;
+use std::io::{stdout, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use burn_rs::{parse_add_file, parse_size, BurnError, BurnJob, Filesystem, PartitionTable, RunOptions};
+use burn_rs::format::{check_cluster_size, fit_label, has_tool, label_len, max_label_len, max_volume_size, MAX_CLUSTER_SIZE, MIN_CLUSTER_SIZE};
+use burn_rs::checksum::{file_checksum, listed_checksum, to_hex, ChecksumAlgorithm};
+use burn_rs::device::{check_medium, check_permissions, drive_info, is_allowed, is_block, is_image_file, is_removable, is_root, is_system_disk, list_drives, read_allowlist, whole_disk, erase_block_size, Drive};
+use burn_rs::image::{check_block_size, check_buffers, check_iso, file_label, has_boot_catalog, is_hybrid, iso_files, iso_label, Compression, SourceType, DEFAULT_BUFFERS, MAX_BUFFERS, STDIN};
+use burn_rs::interrupt;
+use burn_rs::table::{erase_alignment, fit_partition_name, gpt_type, mbr_type, parse_layout, GptBackup, PartitionSize, DEFAULT_ALIGNMENT, PERSISTENCE_LABEL, WIPE_SIZE};
+use gpt::partition_types::{self, Type};
+use burn_rs::progress::{line_width, ProgressKind, Step, StepEnd, StepSink};
+use color::{ceprint, ceprintln, cprintln, ColorChoice};
 
-/// This function checks the permissions to read the source file and write to the destination file.
-fn check_permissions(file_path: &str, dest_path: &str) -> Result<(bool, bool), Box<dyn Error>> {
-    // Check read permission for the file and write permissions for the destination
-    let file_perm = match OpenOptions::new().read(true).open(file_path) {
-        Ok(_) => true,
-        Err(_) => false,
+mod batch;
+mod color;
+mod config;
+mod tui;
+
+/// Set by `--format json`: the result goes to stdout as JSON, everything else to stderr.
+static JSON_OUTPUT: AtomicBool = AtomicBool::new(false);
+/// What the fatal messages so far said, for the JSON error.
+static ERRORS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Prints a fatal error, like `ceprintln!` with the red `Fatal.` in front. Exit afterwards.
+macro_rules! fatal {
+    ($($arg:tt)*) => {
+        report_fatal(format!($($arg)*))
     };
-    let dest_perm = match OpenOptions::new().write(true).open(dest_path) {
-        Ok(_) => true,
-        Err(_) => false,
+}
+
+fn report_fatal(message: String) {
+    // Errors always get through --quiet, and so does whatever explains them after
+    color::set_quiet(false);
+    ceprintln!("\x1b[1m\x1b[31mFatal. \x1b[39m{}\x1b[0m", message);
+    record_error(&message);
+}
+
+/// Prints the error behind a fatal one, with a hint when it's down to permissions.
+fn print_error(e: &(dyn Error + 'static)) {
+    ceprintln!("\x1b[1m\x1b[31mError: {}\x1b[0m", e);
+    record_error(&e.to_string());
+    let io_denied = |e: &std::io::Error| e.kind() == std::io::ErrorKind::PermissionDenied;
+    let denied = match e.downcast_ref::<BurnError>() {
+        Some(BurnError::PermissionDenied(_)) => true,
+        Some(BurnError::Io(e)) => io_denied(e),
+        _ => e.downcast_ref::<std::io::Error>().is_some_and(io_denied),
     };
-    Ok((file_perm, dest_perm))
+    if denied {
+        permission_hint();
+    }
+}
+
+/// Tells users who aren't root why they were turned away, the usual reason a burn fails on the spot.
+fn permission_hint() {
+    if is_root() {
+        return;
+    }
+    if cfg!(windows) {
+        ceprintln!("\x1b[1mWriting to drives takes administrator rights, try running from an \x1b[33mAdministrator\x1b[39m prompt.\x1b[0m");
+    } else {
+        ceprintln!("\x1b[1mWriting to drives usually takes root, try running with \x1b[33msudo\x1b[39m.\x1b[0m");
+    }
+}
+
+fn record_error(message: &str) {
+    if let Ok(mut errors) = ERRORS.lock() {
+        errors.push(color::paint(message.to_string(), false).trim_end_matches('.').to_string());
+    }
+}
+
+/// Exits with `code`. With `--format json`, failures print `{"error":...}` to stdout on the way out.
+fn exit(code: i32) -> ! {
+    if code != 0 && JSON_OUTPUT.load(Ordering::Relaxed) {
+        let error = match ERRORS.lock().map(|errors| errors.join(": ")) {
+            Ok(error) if !error.is_empty() => error,
+            _ if code == 130 => "aborted by the user".to_string(),
+            _ => format!("exited with status {}", code),
+        };
+        println!("{{\"error\":{}}}", json_string(&error));
+    }
+    std::process::exit(code)
+}
+
+/// A SHA-256 as a JSON string, `null` when there is none (the files of the ISO were copied instead).
+fn json_checksum(sha256: &[u8]) -> String {
+    if sha256.is_empty() { "null".to_string() } else { json_string(&to_hex(sha256)) }
+}
+
+/// Quotes and escapes `s` as a JSON string.
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
 }
 
 #[derive(Parser)]
@@ -94,311 +114,1851 @@ fn check_permissions(file_path: &str, dest_path: &str) -> Result<(bool, bool), B
 /// A POSIX TUI/CLI program to burn an image to a drive written in rust.
 struct Args {
     /// Path to a file (an iso) you want to burn to a drive.
-    file: String,
-    /// Path to a drive you want to burn your image to
-    destination: String
+    #[arg(required_unless_present_any = ["tui", "list", "completions"])]
+    file: Option<String>,
+    /// Path to a drive you want to burn your image to. Leave out (or pass `auto`) to pick one from a list.
+    /// Pass several to burn the image to all of them at once.
+    destination: Vec<String>,
+    /// Partition table to create (dos, gpt, hybrid for GPT plus an MBR that BIOSes can read, or none for a superfloppy).
+    /// Skips the table menu, whose default the BURN_TABLE environment variable or the config file sets.
+    #[arg(long)]
+    table: Option<String>,
+    /// Format the whole drive as a "superfloppy", without a partition table. Some BIOSes only boot those.
+    /// Same as --table none.
+    #[arg(long, conflicts_with_all = ["table", "raw", "layout", "persistence", "mbr_type", "boot_flag", "gpt_type", "partition_label", "disk_guid", "partition_guid", "align"])]
+    no_partition_table: bool,
+    /// Accept a partition (/dev/sdb1) as the destination and format just that partition, or write the image into it with --raw.
+    /// The rest of the drive and its partition table are left alone.
+    #[arg(long, conflicts_with_all = ["table", "no_partition_table", "layout", "persistence", "mbr_type", "boot_flag", "gpt_type", "partition_label", "disk_guid", "partition_guid", "align", "image", "tui"])]
+    into_partition: bool,
+    /// Filesystem to format the volume with (fat32, fat16, fat12, exfat, ntfs, ext4, or none to leave it unformatted).
+    /// The files of the ISO are copied onto FAT, the others only make an empty volume with --empty-volume.
+    /// Skips the filesystem menu, whose default the BURN_FS environment variable or the config file sets.
+    #[arg(long)]
+    filesystem: Option<String>,
+    /// Volume label for the new filesystem. Derived from the image by default.
+    #[arg(long, conflicts_with = "raw")]
+    label: Option<String>,
+    /// Fail instead of shortening a --label that is too long for the filesystem.
+    #[arg(long, requires = "label")]
+    strict: bool,
+    /// FAT cluster size in bytes (a power of two, 512 to 65536). Picked from the volume size by default.
+    #[arg(long, value_name = "BYTES", conflicts_with = "raw")]
+    cluster_size: Option<u32>,
+    /// exFAT only: zero the whole volume instead of just writing the filesystem metadata. Wipes old data,
+    /// but takes about as long as writing the whole drive, where the quick default takes seconds.
+    #[arg(long, conflicts_with = "raw")]
+    full_format: bool,
+    /// exFAT only: pack the allocation bitmap in before the cluster heap, saving a little space.
+    #[arg(long, conflicts_with = "raw")]
+    pack_bitmap: bool,
+    /// MBR partition type byte in hex (e.g. 0c, 07, ef). Picked from the filesystem by default.
+    #[arg(long, value_name = "HEX", conflicts_with = "raw")]
+    mbr_type: Option<String>,
+    /// Mark the MBR partition active (bootable). Some old BIOSes refuse to boot a drive without an active partition.
+    #[arg(long, conflicts_with = "raw")]
+    boot_flag: bool,
+    /// Name of the GPT partition (up to 36 characters). Defaults to the volume label.
+    #[arg(long, value_name = "NAME", conflicts_with = "raw")]
+    partition_label: Option<String>,
+    /// GPT disk GUID, for reproducible images. Random by default.
+    #[arg(long, value_name = "UUID", conflicts_with = "raw")]
+    disk_guid: Option<String>,
+    /// GUID of the GPT partition, for reproducible images. Random by default.
+    #[arg(long, value_name = "UUID", conflicts_with = "raw")]
+    partition_guid: Option<String>,
+    /// GPT partition type: efi, basic, linux_fs (or any name the gpt crate knows) or a type GUID.
+    /// Picked from the filesystem by default, bootable FAT images get an EFI System Partition.
+    #[arg(long, value_name = "TYPE", conflicts_with = "raw")]
+    gpt_type: Option<String>,
+    /// Size of the chunks the image is written in, in KB (4 to 16384). Bigger is faster on fast drives.
+    #[arg(long, value_name = "KB", default_value_t = 64)]
+    block_size: usize,
+    /// Blocks to keep in flight, so reading the image overlaps writing the drive (1 to 16, 1 turns that off).
+    /// Each one takes a block size of memory.
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_BUFFERS)]
+    buffers: usize,
+    /// Cap the write speed at this many MB/s, so the rest of the system stays responsive.
+    #[arg(long, value_name = "MB/s")]
+    limit: Option<f64>,
+    /// Start writing this many bytes into the destination (e.g. 1M), like dd's seek= but in bytes.
+    /// Has to be whole sectors with --direct.
+    #[arg(long, value_name = "BYTES")]
+    seek: Option<String>,
+    /// Leave out this many bytes from the start of the image (e.g. 446), like dd's skip= but in bytes.
+    #[arg(long, value_name = "BYTES")]
+    skip: Option<String>,
+    /// Give up if the write makes no progress for this many seconds, instead of hanging on a wedged drive.
+    /// Waits forever by default. Slow drives can take a while flushing at the end, leave room for that.
+    #[arg(long, value_name = "SECONDS")]
+    timeout: Option<u64>,
+    /// Write around the page cache (O_DIRECT) so big images don't slow the whole system down. Linux only.
+    #[arg(long)]
+    direct: bool,
+    /// Start partitions on a multiple of this many MiB. Misaligned partitions are slower and wear flash faster.
+    /// SD cards default to their erase block size when it's larger.
+    #[arg(long, value_name = "MiB", default_value_t = 1, conflicts_with = "raw")]
+    align: u64,
+    /// Where the backup GPT goes: end (the last sectors of the drive, where firmware looks for it when the primary
+    /// is damaged) or adjacent (right after the last partition, image files only). Adjacent cuts the image off there,
+    /// for images meant to be written to drives of any size and have their backup moved to the end (sgdisk -e).
+    #[arg(long, value_name = "WHERE", conflicts_with_all = ["raw", "resume", "no_partition_table", "into_partition"])]
+    gpt_backup: Option<String>,
+    /// Leave out the backup GPT, for tools that write their own. The end of the drive is zeroed
+    /// so an old backup can't be mistaken for one. Same as --gpt-backup none.
+    #[arg(long, conflicts_with_all = ["gpt_backup", "raw", "resume", "no_partition_table", "into_partition"])]
+    no_table_backup: bool,
+    /// GPT partitions to make instead of the single one, as name:size:filesystem separated by commas,
+    /// e.g. esp:256M:fat32,data:rest:exfat. One partition can take the rest of the drive.
+    /// The names double as volume labels, a partition named esp becomes the EFI System Partition.
+    /// The files of the ISO go onto the first one if it's FAT.
+    #[arg(long, value_name = "SPEC", conflicts_with_all = ["raw", "filesystem", "label", "persistence", "mbr_type", "boot_flag", "gpt_type", "partition_label"])]
+    layout: Option<String>,
+    /// Make the partition table and the filesystem, but leave the volume empty instead of copying the files of the ISO
+    /// onto it. Needed for exFAT, NTFS, ext4 and none, which the files can't go onto, and for sources that aren't ISOs.
+    #[arg(long, conflicts_with_all = ["raw", "resume", "compare", "stdin", "tui", "add_file", "min_free"])]
+    empty_volume: bool,
+    /// Copy a file onto the new FAT volume along with the files of the ISO, as source:dest (e.g. ks.cfg:/ks.cfg),
+    /// for kickstart, preseed or autounattend.xml files. Can be given several times.
+    #[arg(long, value_name = "SRC:DEST", conflicts_with_all = ["raw", "resume", "compare", "stdin", "tui"])]
+    add_file: Vec<String>,
+    /// Make the volume this much larger than the files that go on it (e.g. 512M),
+    /// and fail if less than that is left free once they are copied.
+    #[arg(long, value_name = "SIZE", conflicts_with_all = ["raw", "resume", "compare", "stdin"])]
+    min_free: Option<String>,
+    /// Add an ext4 persistence partition (casper-rw) of this size after the main one, e.g. 4G. For live USBs.
+    #[arg(long, value_name = "SIZE", conflicts_with = "raw")]
+    persistence: Option<String>,
+    /// Continue an interrupted burn: skip partitioning and formatting and only write what isn't on the drive yet.
+    #[arg(long, conflicts_with_all = ["table", "filesystem", "label", "cluster_size", "layout"])]
+    resume: bool,
+    /// Zero the first and last 4 MiB of the drive first, like wipefs -a plus sgdisk --zap-all.
+    /// Gets rid of old partition tables and filesystems that firmware would still find on a reused drive.
+    #[arg(long, conflicts_with_all = ["resume", "compare"])]
+    wipe: bool,
+    /// How to show the write progress: human (a progress bar), json (one object per line on stdout, for frontends) or none.
+    #[arg(long, value_name = "KIND", default_value = "human")]
+    progress: String,
+    /// Copy the image as-is onto the drive (like dd): no partition table, no filesystem.
+    #[arg(long, conflicts_with_all = ["table", "filesystem"])]
+    raw: bool,
+    /// How to treat the file: iso (checked for an ISO9660 volume, whatever its name), raw (a disk image
+    /// written as it is, labelled after the file name) or auto (by the extension, .iso or .img). Defaults to auto.
+    #[arg(long, value_name = "TYPE", conflicts_with = "raw_image")]
+    source_type: Option<String>,
+    /// Treat the file as a raw disk image whatever its extension. Same as --source-type raw.
+    #[arg(long)]
+    raw_image: bool,
+    /// Read the image from standard input instead of a file, like `curl ... | burn --stdin --yes /dev/sdb`.
+    /// Only the destination is passed and the image goes on raw. The prompts would read the image, so this takes --yes.
+    #[arg(long, conflicts_with_all = ["tui", "table", "no_partition_table", "filesystem", "label", "layout", "persistence", "print_checksum", "expect_checksum", "checksum_file", "source_type", "raw_image"])]
+    stdin: bool,
+    /// Print the image's checksum (sha256, sha512 or md5) and exit without burning anything.
+    #[arg(long, value_name = "ALGORITHM")]
+    print_checksum: Option<String>,
+    /// Check the image against a published checksum (md5, sha256 or sha512, in hex) before touching the drive.
+    #[arg(long, value_name = "HEX")]
+    expect_checksum: Option<String>,
+    /// Check the image against its entry in a published checksum list (like SHA256SUMS) before touching the drive.
+    /// An image that isn't listed only gets a warning.
+    #[arg(long, value_name = "PATH", conflicts_with = "expect_checksum")]
+    checksum_file: Option<String>,
+    /// Check everything and print what would be done, then exit without touching the drive.
+    #[arg(long)]
+    dry_run: bool,
+    /// Compare the drive against the image instead of burning it: report where they first differ
+    /// and how much of the image is on the drive. Writes nothing. Honors --seek, --skip, --block-size and --buffers.
+    #[arg(long, conflicts_with_all = ["dry_run", "resume", "image", "print_checksum"])]
+    compare: bool,
+    /// Create the destination as a disk image file of this size (e.g. 8G) instead of burning to a drive.
+    /// For VMs. Existing files are burned into as they are without it.
+    #[arg(long, value_name = "SIZE", requires = "destination", conflicts_with_all = ["resume", "eject"])]
+    image: Option<String>,
+    /// Eject (power off) the drive when done, so it can be unplugged right away.
+    #[arg(long)]
+    eject: bool,
+    /// Read the drive back after writing and compare it against the image.
+    #[arg(long, overrides_with = "no_verify")]
+    verify: bool,
+    /// Don't read the drive back, even with verify turned on in the config file. The last of the two wins.
+    #[arg(long, overrides_with = "verify")]
+    no_verify: bool,
+    /// Mount the first partition read-only when done and list its root, to make sure the filesystem
+    /// on it is readable and not just written. Linux only.
+    #[arg(long, conflicts_with_all = ["tui", "image"])]
+    mount_check: bool,
+    /// Run this shell command after a successful burn, before ejecting. It gets BURN_DEVICE, BURN_SOURCE, BURN_LABEL,
+    /// BURN_TABLE and BURN_FILESYSTEM in its environment. If it fails, so does the burn.
+    #[arg(long, value_name = "COMMAND", conflicts_with_all = ["tui", "compare"])]
+    after: Option<String>,
+    /// Allow writing to the drive that holds the running system. You almost certainly don't want this.
+    #[arg(long)]
+    force_system_disk: bool,
+    /// Turn every safety check on the destination into a warning: the drive holding the running system
+    /// (like --force-system-disk), an internal drive (like --allow-internal), an image bigger than the drive
+    /// says it is, and partitions that can't be unmounted. The allowlist and the confirmation still apply.
+    #[arg(long)]
+    force: bool,
+    /// Only burn to the drives listed in this file, one serial number or device path per line (# starts a comment).
+    /// Anything else is refused, a guardrail for shared machines. See --list for the serial numbers.
+    #[arg(long, value_name = "PATH")]
+    allowlist: Option<String>,
+    /// Read defaults for table, filesystem, block-size, verify and color from this TOML file instead of
+    /// $XDG_CONFIG_HOME/burn-rs/config.toml. Flags and BURN_TABLE/BURN_FS still win over it.
+    #[arg(long, value_name = "PATH")]
+    config: Option<String>,
+    /// Allow writing to an internal (non-removable) drive without asking.
+    #[arg(long)]
+    allow_internal: bool,
+    /// Also list internal (non-removable) drives in the drive picker.
+    #[arg(long)]
+    all_disks: bool,
+    /// List the drives with their mounted partitions and free space, then exit.
+    #[arg(long, conflicts_with_all = ["tui", "compare"])]
+    list: bool,
+    /// Pick everything in a full-screen interface instead of with flags and prompts.
+    #[arg(long, conflicts_with_all = ["yes", "resume", "dry_run", "print_checksum", "expect_checksum", "checksum_file", "compare"])]
+    tui: bool,
+    /// When to use colors: auto (on terminals, unless NO_COLOR is set), always or never.
+    #[arg(long, value_name = "WHEN", default_value = "auto")]
+    color: String,
+    /// Show what is going on under the hood: -v for each step, -vv for sizes and offsets too.
+    /// RUST_LOG takes precedence when set.
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// Print the result as human messages, or as a JSON object on stdout (json) for scripts,
+    /// with everything else on stderr. Failures print {"error":...} and exit non-zero.
+    #[arg(long, value_name = "FORMAT", default_value = "human", conflicts_with_all = ["tui", "list"])]
+    format: String,
+    /// Do not prompt for anything (defaults to GPT + FAT32 unless --table/--filesystem are given).
+    #[arg(short, long)]
+    yes: bool,
+    /// Print nothing but errors: no status lines, progress or warnings. For scripts, so it takes --yes.
+    /// The JSON of --format json still goes to stdout.
+    #[arg(short, long, requires = "yes", conflicts_with_all = ["verbose", "progress", "list", "dry_run"])]
+    quiet: bool,
+    /// With several destinations, let the other drives finish when one fails instead of stopping them all.
+    /// The summary at the end tells which ones failed.
+    #[arg(long)]
+    keep_going: bool,
+    /// Confirm by typing the drive's path or size instead of just y, like internal drives always ask for.
+    #[arg(long, conflicts_with_all = ["yes", "tui"])]
+    confirm_phrase: bool,
+    /// Print the completion script for a shell (bash, zsh, fish, elvish or powershell) and exit. For packagers.
+    #[arg(long, value_name = "SHELL", hide = true, exclusive = true)]
+    completions: Option<Shell>,
 }
 
-fn is_block(path: &str) -> bool {
-    use std::os::unix::fs::FileTypeExt;
-    match std::fs::metadata(path) {
-        Ok(metadata) => metadata.file_type().is_block_device(),
-        Err(_) => false,
+/// Formats a byte count for humans, e.g. `29.8 GB`.
+fn human_size(bytes: u64) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
     }
+    format!("{:.1} {}", size, units[unit])
 }
 
+/// Prints every drive, internal ones included, with where its partitions are mounted and how full they are.
+fn list_devices() {
+    let drives = list_drives();
+    if drives.is_empty() {
+        cprintln!("\x1b[1mNo drives found.\x1b[0m");
+        return;
+    }
+    let disks = sysinfo::Disks::new_with_refreshed_list();
+    for drive in drives {
+        let model = if drive.model.is_empty() { "Unknown drive" } else { drive.model.as_str() };
+        let removable = if drive.removable { " \x1b[32m[removable]\x1b[39m" } else { "" };
+        let system = if is_system_disk(&drive.path) { " \x1b[31m[system]\x1b[39m" } else { "" };
+        let serial = if drive.serial.is_empty() { String::new() } else { format!(" (serial {})", drive.serial) };
+        cprintln!("\x1b[1m{}\x1b[0m {}, {}{}{}{}", drive.path, model, human_size(drive.size), serial, removable, system);
+        for disk in disks.list().iter().filter(|d| drive.mount_points.contains(&d.mount_point().display().to_string())) {
+            cprintln!("  {} on \x1b[1m{}\x1b[0m ({}, {} free of {})", disk.name().to_string_lossy(), disk.mount_point().display(),
+                disk.file_system().to_string_lossy(), human_size(disk.available_space()), human_size(disk.total_space()));
+        }
+    }
+}
 
-/// Entry point.
-fn main() -> Result<(), Box<dyn Error>> {
-    // Check if the system is POSIX
-    if !cfg!(target_os = "linux") && !cfg!(target_os = "macos") && !cfg!(target_os = "freebsd") && !cfg!(target_os = "openbsd") && !cfg!(target_os = "netbsd") && !cfg!(target_os = "dragonfly") {
-        eprintln!("\x1b[1m\x1b[31mFatal. \x1b[39mThis program is only supported on POSIX, MacOS, [Free,Open,Net,Dragonfly] BSD systems.\x1b[0m");
-        eprintln!("\x1b[1mPlease \x1b[31mUninstall the program. \x1b[39mRun: \x1b[33mcargo uninstall burn-rs\x1b[0m");
-        std::process::exit(1);
+/// Lets the user pick the destination from a list of drives.
+/// System disks are never offered, internal ones only with `all`, and with an allowlist only the listed ones.
+fn pick_drive(all: bool, allowlist: Option<&[String]>) -> String {
+    let drives: Vec<Drive> = list_drives().into_iter()
+        .filter(|d| all || d.removable)
+        .filter(|d| !is_system_disk(&d.path))
+        .filter(|d| allowlist.is_none_or(|allowlist| is_allowed(&d.path, allowlist)))
+        .collect();
+    if drives.is_empty() {
+        fatal!("No suitable drives found. Plug one in or pass the destination explicitly.");
+        exit(1);
+    }
+    cprintln!("\x1b[1mChoose destination drive:\x1b[0m");
+    loop {
+        for (i, drive) in drives.iter().enumerate() {
+            let model = if drive.model.is_empty() { "Unknown drive" } else { drive.model.as_str() };
+            let removable = if drive.removable { " \x1b[32m[removable]\x1b[39m" } else { "" };
+            let mounted = if drive.mount_points.is_empty() {
+                String::new()
+            } else {
+                format!(" (mounted on {})", drive.mount_points.join(", "))
+            };
+            cprintln!("{}. \x1b[1m{}\x1b[0m {}, {}{}{}", i + 1, drive.path, model, human_size(drive.size), removable, mounted);
+        }
+        cprintln!("{}. \x1b[1mCancel\x1b[0m", drives.len() + 1);
+        let mut input = String::new();
+        std::io::stdin().read_line(&mut input).expect("Error reading input");
+        let input = input.trim();
+        match input.parse::<usize>() {
+            Ok(n) if (1..=drives.len()).contains(&n) => return drives[n - 1].path.clone(),
+            Ok(n) if n == drives.len() + 1 => {
+                ceprintln!("\x1b[1mExiting...\x1b[0m");
+                exit(0);
+            }
+            _ if input.eq_ignore_ascii_case("cancel") => {
+                ceprintln!("\x1b[1mExiting...\x1b[0m");
+                exit(0);
+            }
+            _ => {
+                ceprintln!("\x1b[1m\x1b[31mInvalid input.\x1b[0m");
+                continue;
+            }
+        }
+    }
+}
+
+/// `--compare`: checks a drive burned earlier against the image and exits, with 0 if it holds all of it.
+fn compare(job: &BurnJob) -> ! {
+    if !is_block(&job.destination) && !is_image_file(&job.destination) {
+        fatal!("{} is not a drive or an image file.", job.destination);
+        exit(1);
     }
+    interrupt::install_handler();
+    let status = format!("Comparing {} with the image...", job.destination);
+    ceprint!("\x1b[1m[ .... ] {}\x1b[0m", status);
+    let _ = stdout().flush();
+    let comparison = match with_spinner(&status, || job.compare()) {
+        Ok(comparison) => comparison,
+        Err(e) => {
+            ceprint!("\r\x1b[1m[\x1b[31m FAILED \x1b[39m] {}\x1b[0m", status);
+            cprintln!();
+            exit_if_aborted();
+            fatal!("Error comparing the drive with the image.");
+            print_error(&e);
+            exit(1);
+        }
+    };
+    if let Some(offset) = comparison.first_difference {
+        ceprint!("\r\x1b[1m[\x1b[31m FAILED \x1b[39m] {}\x1b[0m", status);
+        cprintln!();
+        fatal!("The drive differs from the image from byte {} on, {} of {} bytes match.", offset, comparison.matching, comparison.total);
+        exit(1);
+    }
+    ceprint!("\r\x1b[1m[\x1b[32m DONE \x1b[39m] {}\x1b[0m", status);
+    cprintln!();
+    cprintln!("\x1b[1m\x1b[32mThe drive holds the image, all {} bytes match.\x1b[0m", comparison.total);
+    if JSON_OUTPUT.load(Ordering::Relaxed) {
+        println!(
+            "{{\"source\":{},\"destination\":{},\"bytes_compared\":{},\"matching_bytes\":{}}}",
+            json_string(&job.source), json_string(&job.destination), comparison.total, comparison.matching,
+        );
+    }
+    exit(0);
+}
 
-    let args = Args::parse();
+/// The safety checks that are only warnings this time, each from its own flag or from --force.
+#[derive(Clone, Copy)]
+struct Overrides {
+    /// Writing to the drive holding the running system.
+    system_disk: bool,
+    /// Writing to an internal drive, without asking with --yes.
+    internal: bool,
+    /// Writing an image bigger than the drive says it is.
+    capacity: bool,
+    /// Writing while partitions on the drive couldn't be unmounted.
+    busy: bool,
+}
 
-    let file_path = &args.file;
-    let dest_path = &args.destination;
+impl Overrides {
+    fn new(args: &Args) -> Self {
+        Overrides {
+            system_disk: args.force || args.force_system_disk,
+            internal: args.force || args.allow_internal,
+            capacity: args.force,
+            busy: args.force,
+        }
+    }
+}
 
-    // Check for file path
-    if !std::path::Path::new(file_path).exists() {
-        eprintln!("\x1b[1m\x1b[31mFatal. \x1b[39mFile does not exist.\x1b[0m");
-        std::process::exit(1);
+/// Checks that `dest_path` can be burned to: it exists, is a whole drive (or an image file), isn't
+/// the system disk and can be written. Stops with a fatal error if not, unless `overrides` says otherwise.
+/// Returns whether it is removable, internal drives take a typed confirmation.
+fn check_destination(args: &Args, overrides: Overrides, file_path: &str, dest_path: &str, image: bool, allowlist: Option<&[String]>) -> bool {
+    if image && args.eject {
+        fatal!("{} is an image file, there is nothing to eject.", dest_path);
+        exit(1);
+    }
+    if image && args.mount_check {
+        fatal!("--mount-check only applies to drives, not image files.");
+        exit(1);
     }
 
     // Check for destination path
-    if !std::path::Path::new(dest_path).exists() {
-        eprintln!("\x1b[1m\x1b[31mFatal. \x1b[39mDestination does not exist.\x1b[0m");
-        std::process::exit(1);
+    if args.image.is_none() && !std::path::Path::new(dest_path).exists() {
+        fatal!("Destination {} does not exist.", dest_path);
+        exit(1);
     }
 
-    // Check for file is actually being an iso
+    // Check for destination is actually being a drive
+
+    if !image && !is_block(dest_path) {
+        fatal!("Destination {} is not a block (disk) device.", dest_path);
+        exit(1);
+    }
 
+    // A partition can't hold a partition table, and its partitions' names would be nonsense (/dev/sdb11)
+    match whole_disk(dest_path) {
+        Some(disk) if !image && !args.into_partition => {
+            fatal!("{} is a partition, not a whole drive.", dest_path);
+            ceprintln!("\x1b[1mPass the drive it is on, \x1b[33m{}\x1b[39m, or \x1b[33m--into-partition\x1b[39m to only format this partition.\x1b[0m", disk);
+            exit(1);
+        }
+        None if args.into_partition => {
+            fatal!("--into-partition only applies to partitions, {} is a whole drive.", dest_path);
+            exit(1);
+        }
+        _ => {}
+    }
 
+    // Check for destination not being the drive we are running from
+    if !image && is_system_disk(dest_path) {
+        if !overrides.system_disk {
+            fatal!("Destination {} holds the running system (/ or /boot).", dest_path);
+            ceprintln!("\x1b[1mIf you really mean it, pass \x1b[33m--force-system-disk\x1b[39m.\x1b[0m");
+            exit(1);
+        }
+        ceprintln!("\x1b[1m\x1b[33mWarning! \x1b[39mDestination {} holds the running system (/ or /boot).\x1b[0m", dest_path);
+    }
 
-    if !std::path::Path::new(file_path).file_name().unwrap().to_str().unwrap().ends_with(".iso") {
-        eprintln!("\x1b[1m\x1b[31mFatal. \x1b[39mFile is not an iso disk image.\x1b[0m");
-        std::process::exit(1);
+    let removable = image || is_removable(dest_path);
+    if !removable && !overrides.internal {
+        if args.yes {
+            fatal!("{} is not a removable drive.", dest_path);
+            ceprintln!("\x1b[1mIf you really mean it, pass \x1b[33m--allow-internal\x1b[39m.\x1b[0m");
+            exit(1);
+        }
+        // The confirmation at the end asks for the path typed out, that's the one that counts
+        ceprintln!("\x1b[1m\x1b[33mWarning! \x1b[39m{} is not a removable drive, it may be an internal disk.\x1b[0m", dest_path);
     }
 
-    // Check for destination is actually being a drive
+    // An empty card reader can't be opened for writing either, that's no reason to blame permissions
+    if !image && let Err(BurnError::NoMedium(_)) = check_medium(dest_path) {
+        fatal!("There is no medium in {}, insert the card or disk first.", dest_path);
+        exit(1);
+    }
 
-    if !is_block(dest_path) {
-        eprintln!("\x1b[1m\x1b[31mFatal. \x1b[39mDestination is not a block (disk) device.\x1b[0m");
-        std::process::exit(1);
+    // On shared machines only the drives set aside for burning may be touched. Image files are no drives.
+    if !image && let Some(allowlist) = allowlist
+        && !is_allowed(dest_path, allowlist) {
+        fatal!("{} is not on the allowlist {}.", dest_path, args.allowlist.as_deref().unwrap_or_default());
+        if let Some(serial) = drive_info(dest_path).map(|d| d.serial).filter(|serial| !serial.is_empty()) {
+            ceprintln!("\x1b[1mIts serial number is \x1b[33m{}\x1b[39m, list that to allow it.\x1b[0m", serial);
+        }
+        exit(1);
     }
 
     // Check for permissions on the file and destination
-    let (file_perm, dest_perm) = check_permissions(file_path, dest_path).unwrap();
-    if !file_perm {
-        eprintln!("\x1b[1m\x1b[31mFatal. \x1b[39mNo read permission on the source file.\x1b[0m");
-        std::process::exit(1);
+    let (file_perm, dest_perm) = check_permissions(file_path, dest_path);
+    if let Err(e) = file_perm && !args.stdin {
+        fatal!("Could not open the source file for reading.");
+        print_error(&e);
+        exit(1);
     }
-    if !dest_perm {
-        eprintln!("\x1b[1m\x1b[31mFatal. \x1b[39mNo write permission on the destination.\x1b[0m");
-        std::process::exit(1);
+    // A new image file can't be opened before it's created
+    if let Err(e) = dest_perm && (args.image.is_none() || std::path::Path::new(dest_path).exists()) {
+        fatal!("Could not open {} for writing.", dest_path);
+        print_error(&e);
+        exit(1);
     }
+    removable
+}
+
+/// What the user recognizes a destination by: the model and size of the drive, paths are easy to mix up.
+/// Also returns the drive's details, there are none for image files (`image_size` is the one `--image` makes).
+fn describe_destination(dest_path: &str, image_size: Option<u64>) -> (Option<Drive>, String) {
+    if image_size.is_some() || is_image_file(dest_path) {
+        let size = image_size.unwrap_or_else(|| std::fs::metadata(dest_path).map(|m| m.len()).unwrap_or(0));
+        return (None, format!("disk image, {}", human_size(size)));
+    }
+    let drive = drive_info(dest_path);
+    let name = match &drive {
+        Some(d) if !d.model.is_empty() => format!("{}, {}", d.model, human_size(d.size)),
+        Some(d) => format!("Unknown drive, {}", human_size(d.size)),
+        None => "Unknown drive".to_string(),
+    };
+    (drive, name)
+}
+
+/// A destination for the summary: path, name and serial, if the drive reports one.
+fn destination_line(dest_path: &str, drive: Option<&Drive>, drive_name: &str) -> String {
+    match drive.map(|d| d.serial.as_str()).filter(|serial| !serial.is_empty()) {
+        Some(serial) => format!("\x1b[1m{} \u{2014} {}\x1b[0m (serial {})", dest_path, drive_name, serial),
+        None => format!("\x1b[1m{} \u{2014} {}\x1b[0m", dest_path, drive_name),
+    }
+}
+
+/// Burns to several drives at once and exits, with 0 if every one of them got the image.
+fn burn_batch(jobs: Vec<BurnJob>, options: batch::BatchOptions) -> ! {
+    let source = jobs[0].source.clone();
+    // Empty volumes get nothing of the image, they aren't "written"
+    let empty = !jobs[0].raw && !jobs[0].copies_files();
+    let (outcomes, stopped) = batch::run(jobs, options);
+    // The drives a failure stopped didn't fail themselves
+    let failed = outcomes.iter().filter(|outcome| outcome.result.is_err() && !(stopped && matches!(outcome.result, Err(BurnError::Aborted)))).count();
+    // The fatal goes first, so the results still show with --quiet
+    let code = if interrupt::requested() && !stopped {
+        ceprintln!("\x1b[1m\x1b[33mWarning! \x1b[39mAborted, the unfinished drives are in an inconsistent state.\x1b[0m");
+        130
+    } else if failed > 0 {
+        fatal!("{} of {} drives failed.", failed, outcomes.len());
+        1
+    } else {
+        0
+    };
+    cprintln!("\x1b[1mResults:\x1b[0m");
+    for outcome in &outcomes {
+        match &outcome.result {
+            Ok(_) if empty => cprintln!("  \x1b[1m{}\x1b[0m: \x1b[32mempty volume made\x1b[39m", outcome.destination),
+            Ok(written) => cprintln!("  \x1b[1m{}\x1b[0m: \x1b[32mwritten\x1b[39m ({})", outcome.destination, human_size(written.len)),
+            Err(BurnError::Aborted) if stopped => cprintln!("  \x1b[1m{}\x1b[0m: \x1b[33mstopped\x1b[39m (another drive failed)", outcome.destination),
+            Err(e) => cprintln!("  \x1b[1m{}\x1b[0m: \x1b[31mfailed\x1b[39m ({})", outcome.destination, e),
+        }
+        if let Some(warning) = &outcome.warning {
+            ceprintln!("\x1b[1m\x1b[33mWarning! \x1b[39m{}: {}.\x1b[0m", outcome.destination, warning);
+        }
+    }
+    if stopped {
+        ceprintln!("\x1b[1mThe other drives were stopped, pass \x1b[33m--keep-going\x1b[39m to let them finish.\x1b[0m");
+    } else if code == 0 && empty {
+        cprintln!("\x1b[1m\x1b[32mSuccessfully made an empty volume on all {} drives, nothing of the image was written!\x1b[0m", outcomes.len());
+    } else if code == 0 {
+        cprintln!("\x1b[1m\x1b[32mSuccessfully written the image to all {} drives!\x1b[0m", outcomes.len());
+    }
+    if JSON_OUTPUT.load(Ordering::Relaxed) {
+        let results: Vec<String> = outcomes.iter().map(|outcome| match &outcome.result {
+            Ok(written) => format!(
+                "{{\"destination\":{},\"bytes_written\":{},\"checksum\":{}}}",
+                json_string(&outcome.destination), written.len, json_checksum(&written.sha256),
+            ),
+            Err(BurnError::Aborted) if stopped => format!("{{\"destination\":{},\"error\":\"stopped, another drive failed\"}}", json_string(&outcome.destination)),
+            Err(e) => format!("{{\"destination\":{},\"error\":{}}}", json_string(&outcome.destination), json_string(&e.to_string())),
+        }).collect();
+        println!("{{\"source\":{},\"destinations\":[{}]}}", json_string(&source), results.join(","));
+        // The results already carry the errors, exit() would add an {"error":...} of its own
+        std::process::exit(code);
+    }
+    exit(code);
+}
+
+/// Reads a menu default from the environment variable `name`, unset or empty meaning none.
+/// Anything it can't parse is as fatal as the same value given as a flag.
+fn env_default<T: std::str::FromStr>(name: &str, what: &str, expected: &str) -> Option<T> {
+    let value = std::env::var(name).ok().filter(|value| !value.is_empty())?;
+    match value.parse() {
+        Ok(default) => Some(default),
+        Err(_) => {
+            fatal!("Unknown {} \x1b[33m{}\x1b[39m in {} (expected {}).", what, value, name, expected);
+            exit(1);
+        }
+    }
+}
+
+/// Reads a menu default from the config file's `key`, as strictly as [`env_default`] does.
+fn config_default<T: std::str::FromStr>(value: &Option<String>, path: &Option<std::path::PathBuf>, key: &str, what: &str, expected: &str) -> Option<T> {
+    let value = value.as_ref()?;
+    match value.parse() {
+        Ok(default) => Some(default),
+        Err(_) => {
+            let path = path.as_ref().map(|path| path.display().to_string()).unwrap_or_default();
+            fatal!("Unknown {} \x1b[33m{}\x1b[39m for {} in {} (expected {}).", what, value, key, path, expected);
+            exit(1);
+        }
+    }
+}
 
-    println!("\x1b[1mChoose partition table:\x1b[0m");
-    let mut table = String::new();
+/// Works out the partition table: the flag if given, `default` (from `BURN_TABLE` or the config file, else GPT) with `yes`,
+/// otherwise a menu where an empty line takes `default`.
+fn choose_table(table_arg: Option<PartitionTable>, default: Option<PartitionTable>, yes: bool) -> PartitionTable {
+    let table: PartitionTable;
+    if let Some(chosen) = table_arg {
+        table = chosen;
+    } else if yes {
+        table = default.unwrap_or(PartitionTable::Gpt);
+        ceprintln!("\x1b[1mNo partition table chosen, defaulting to \x1b[33m{}\x1b[39m.\x1b[0m", table);
+    } else {
+        match default {
+            Some(default) => cprintln!("\x1b[1mChoose partition table [{}]:\x1b[0m", default),
+            None => cprintln!("\x1b[1mChoose partition table:\x1b[0m"),
+        }
+        loop {
+            cprintln!("1. \x1b[1mMBR [dos]\x1b[0m");
+            cprintln!("2. \x1b[1mGPT\x1b[0m");
+            cprintln!("3. \x1b[1mHybrid\x1b[0m (GPT plus an MBR, for both UEFI and old BIOSes)");
+            cprintln!("4. \x1b[1mCancel\x1b[0m");
+            let mut input = String::new();
+            std::io::stdin().read_line(&mut input).expect("Error reading input");
+            let input = input.trim();
+            match input.to_lowercase().as_str() {
+                "" if let Some(default) = default => {
+                    table = default;
+                    break;
+                }
+                "1" | "dos" | "mbr" => {
+                    table = PartitionTable::Dos;
+                    break;
+                }
+                "2" | "gpt" => {
+                    table = PartitionTable::Gpt;
+                    break;
+                }
+                "3" | "hybrid" => {
+                    table = PartitionTable::Hybrid;
+                    break;
+                }
+                "4" | "cancel" => {
+                    ceprintln!("\x1b[1mExiting...\x1b[0m");
+                    exit(0);
+                }
+                _ => {
+                    ceprintln!("\x1b[1m\x1b[31mInvalid input.\x1b[0m");
+                    continue;
+                }
+            }
+
+        }
+    }
+    table
+}
+
+/// Works out the filesystem: the flag if given, `default` (from `BURN_FS` or the config file) or FAT32 with `yes` (exFAT if the
+/// volume is too large for either and `empty` asks for an empty one), otherwise a menu where an empty line takes `default`. The menu asks again
+/// when the pick can't be made `volume_size` bytes large, a filesystem given as a flag gets checked with the rest of the job.
+/// Also returns whether the volume is left empty: `empty`, or a pick from the menu that says so.
+fn choose_filesystem(fs_arg: Option<Filesystem>, default: Option<Filesystem>, yes: bool, empty: bool, volume_size: u64) -> (Filesystem, bool) {
+    let fits = |fs: Filesystem| max_volume_size(fs).is_none_or(|max| volume_size <= max);
+    if let Some(chosen) = fs_arg {
+        return (chosen, empty);
+    }
+    if yes {
+        let fs = match default {
+            Some(fs) if fits(fs) => fs,
+            _ if fits(Filesystem::Fat32) => Filesystem::Fat32,
+            _ if empty => Filesystem::Exfat,
+            _ => {
+                fatal!("The volume is {}, too large for FAT32, the only filesystem the files of the ISO go onto.", human_size(volume_size));
+                ceprintln!("\x1b[1mPass \x1b[33m--raw\x1b[39m to write the image as it is instead.\x1b[0m");
+                exit(1);
+            }
+        };
+        ceprintln!("\x1b[1mNo filesystem chosen, defaulting to \x1b[33m{}\x1b[39m.\x1b[0m", fs);
+        return (fs, empty);
+    }
+    match default {
+        Some(default) => cprintln!("\x1b[1mChoose filesystem [{}]:\x1b[0m", default),
+        None => cprintln!("\x1b[1mChoose filesystem:\x1b[0m"),
+    }
     loop {
-        println!("1. \x1b[1mMBR [dos]\x1b[0m");
-        println!("2. \x1b[1mGPT\x1b[0m");
-        println!("3. \x1b[1mCancel\x1b[0m");
+        // The files of the ISO are copied in through the FAT, the other filesystems are left empty
+        cprintln!("1. \x1b[1mFAT32\x1b[0m");
+        cprintln!("2. \x1b[1mFAT16\x1b[0m");
+        cprintln!("3. \x1b[1mFAT12\x1b[0m");
+        cprintln!("4. \x1b[1mexFAT\x1b[0m (left empty)");
+        cprintln!("5. \x1b[1mNTFS\x1b[0m (left empty)");
+        cprintln!("6. \x1b[1mext4\x1b[0m (left empty)");
+        cprintln!("7. \x1b[1mNone\x1b[0m (leave the partition unformatted)");
+        cprintln!("8. \x1b[1mCancel\x1b[0m");
         let mut input = String::new();
         std::io::stdin().read_line(&mut input).expect("Error reading input");
         let input = input.trim();
-        match input.to_lowercase().as_str() {
-            "1" | "dos" | "mbr" => {
-                table = "dos".to_string();
-                break;
-            }
-            "2" | "gpt" => {
-                table = "gpt".to_string();
-                break;
-            }
-            "3" | "cancel" => {
-                eprintln!("\x1b[1mExiting...\x1b[0m");
-                std::process::exit(0);
+        let fs = match input.to_lowercase().as_str() {
+            "" if let Some(default) = default => default,
+            "1" | "fat32" => Filesystem::Fat32,
+            "2" | "fat16" => Filesystem::Fat16,
+            "3" | "fat12" => Filesystem::Fat12,
+            "4" | "exfat" => Filesystem::Exfat,
+            "5" | "ntfs" => Filesystem::Ntfs,
+            "6" | "ext4" => Filesystem::Ext4,
+            "7" | "none" => Filesystem::None,
+            "8" | "cancel" => {
+                ceprintln!("\x1b[1mExiting...\x1b[0m");
+                exit(0);
             }
             _ => {
-                eprintln!("\x1b[1m\x1b[31mInvalid input.\x1b[0m");
+                ceprintln!("\x1b[1m\x1b[31mInvalid input.\x1b[0m");
                 continue;
             }
+        };
+        if let Some(max) = max_volume_size(fs)
+            && !fits(fs) {
+            ceprintln!("\x1b[1m\x1b[33mWarning! \x1b[39mThe volume is {}, too large for {} (at most {}). Pick another filesystem, {} fits.\x1b[0m",
+                human_size(volume_size), fs, human_size(max), larger_filesystem(volume_size));
+            continue;
         }
+        return (fs, empty || !matches!(fs, Filesystem::Fat32 | Filesystem::Fat16 | Filesystem::Fat12));
+    }
+}
 
+/// The smallest FAT that holds a volume of `size` bytes, exFAT past FAT32. What to suggest when one is too small.
+fn larger_filesystem(size: u64) -> Filesystem {
+    [Filesystem::Fat16, Filesystem::Fat32].into_iter()
+        .find(|fs| max_volume_size(*fs).is_some_and(|max| size <= max))
+        .unwrap_or(Filesystem::Exfat)
+}
+
+/// Runs `step` while the dots of its `[ .... ] status` line go round, so slow drives don't look frozen.
+/// The caller prints the status line before and its DONE or FAILED after, as usual.
+fn with_spinner<T>(status: &str, step: impl FnOnce() -> T) -> T {
+    let spinner = Spinner::start(status);
+    let result = step();
+    if let Some(spinner) = spinner {
+        spinner.stop();
     }
-    // eprintln!("\x1b[1mPartitioning table: {}\x1b[0m", table);
-    println!("\x1b[1mChoose filesystem:\x1b[0m");
-    let mut fs = String::new();
-    loop {
-        println!("1. \x1b[1mFAT32\x1b[0m");
-        println!("2. \x1b[1mFAT16\x1b[0m");
-        println!("3. \x1b[1mexFAT\x1b[0m");
-        println!("4. \x1b[1mCancel\x1b[0m");
-        let mut input = String::new();
-        std::io::stdin().read_line(&mut input).expect("Error reading input");
-        let input = input.trim();
-        match input.to_lowercase().as_str() {
-            "1" | "fat32" => {
-                fs = "fat32".to_string();
-                break;
+    result
+}
+
+/// The dots of a `[ .... ] status` line going round on a thread of their own, see [`with_spinner`].
+struct Spinner {
+    done: Arc<AtomicBool>,
+    thread: std::thread::JoinHandle<()>,
+}
+
+impl Spinner {
+    /// Starts the dots going, unless stderr is no terminal: redrawing the line only makes a mess of logs and pipes.
+    fn start(status: &str) -> Option<Spinner> {
+        if !std::io::stderr().is_terminal() {
+            return None;
+        }
+        let done = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let done = done.clone();
+            let status = status.to_string();
+            std::thread::spawn(move || {
+                let frames = [".   ", " .  ", "  . ", "   .", "  . ", " .  "];
+                for frame in frames.iter().cycle() {
+                    std::thread::sleep(Duration::from_millis(120));
+                    if done.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    ceprint!("\r\x1b[1m[ {} ] {}\x1b[0m", frame, status);
+                }
+            })
+        };
+        Some(Spinner { done, thread })
+    }
+
+    /// Stops the dots, once they're off the line it's free for the DONE or FAILED.
+    fn stop(self) {
+        self.done.store(true, Ordering::Relaxed);
+        let _ = self.thread.join();
+    }
+}
+
+/// The `[ .... ]` status line of each step of a burn, with the dots going round while it runs.
+/// A failed step is the end: its FAILED line, what went wrong, and out.
+struct StatusLines {
+    /// Only the filesystem on the destination partition gets cleared, not the table of its drive.
+    into_partition: bool,
+    spinner: Option<Spinner>,
+}
+
+impl StatusLines {
+    fn status(&self, job: &BurnJob, step: Step) -> String {
+        match step {
+            Step::CreateImage => format!("Creating the image file {}...", job.destination),
+            Step::Unmount => format!("Unmounting partitions on {}...", job.destination),
+            Step::Wipe => format!("Wiping old signatures on {}...", job.destination),
+            Step::Partition if self.into_partition => "Clearing the old filesystem on the partition...".to_string(),
+            Step::Write => "Writing the iso to the volume...".to_string(),
+            Step::Eject => format!("Ejecting {}...", job.destination),
+            step => step.status(job),
+        }
+    }
+}
+
+impl StepSink for StatusLines {
+    fn start(&mut self, job: &BurnJob, step: Step) {
+        let status = self.status(job, step);
+        match step {
+            // The command may print, so the status line comes once it's done
+            Step::After => {}
+            // The progress takes the line over
+            Step::Write => ceprint!("\x1b[1m[{}] {}\x1b[0m", " ".repeat(15), status),
+            _ => {
+                ceprint!("\x1b[1m[ .... ] {}\x1b[0m", status);
+                self.spinner = Spinner::start(&status);
             }
-            "2" | "fat16" => {
-                fs = "fat16".to_string();
-                break;
+        }
+        let _ = stdout().flush();
+    }
+
+    fn end(&mut self, job: &BurnJob, step: Step, end: StepEnd<'_>) {
+        if let Some(spinner) = self.spinner.take() {
+            spinner.stop();
+        }
+        let status = self.status(job, step);
+        match step {
+            Step::Write => ceprint!("\r{}\r", " ".repeat(line_width())),
+            Step::After => {}
+            _ => ceprint!("\r"),
+        }
+        let e = match end {
+            StepEnd::Done(None) => {
+                ceprint!("\x1b[1m[\x1b[32m DONE \x1b[39m] {}\x1b[0m", status);
+                cprintln!();
+                return;
             }
-            "3" | "exfat" => {
-                fs = "exfat".to_string();
-                break;
+            StepEnd::Done(Some(detail)) => {
+                ceprint!("\x1b[1m[\x1b[32m DONE \x1b[39m] {} ({})\x1b[0m", status, detail);
+                cprintln!();
+                return;
             }
-            "4" | "cancel" => {
-                eprintln!("\x1b[1mExiting...\x1b[0m");
-                std::process::exit(0);
+            StepEnd::Skipped(reason) => {
+                ceprint!("\x1b[1m[\x1b[33m SKIP \x1b[39m] {} ({})\x1b[0m", status, reason);
+                cprintln!();
+                return;
             }
-            _ => {
-                eprintln!("\x1b[1m\x1b[31mInvalid input.\x1b[0m");
-                continue;
+            StepEnd::Warning(warning) => {
+                // Not ejecting is a failure, only the burn goes on regardless.
+                if step == Step::Eject {
+                    ceprint!("\x1b[1m[\x1b[31m FAILED \x1b[39m] {}\x1b[0m", status);
+                } else {
+                    ceprint!("\x1b[1m[\x1b[33m SKIP \x1b[39m] {}\x1b[0m", status);
+                }
+                cprintln!();
+                ceprintln!("\x1b[1m\x1b[33mWarning! \x1b[39m{}.\x1b[0m", warning);
+                return;
+            }
+            StepEnd::Failed(e) => e,
+        };
+        ceprint!("\x1b[1m[\x1b[31m FAILED \x1b[39m] {}\x1b[0m", status);
+        let _ = stdout().flush();
+        cprintln!();
+        if step == Step::Verify && matches!(e, BurnError::Aborted) {
+            ceprintln!("\x1b[1m\x1b[33mWarning! \x1b[39mVerification aborted, the image itself was written completely.\x1b[0m");
+            exit(130);
+        }
+        exit_if_aborted();
+        match (step, e) {
+            (Step::Verify, BurnError::WriteFailed(_)) => {
+                fatal!("The data on the drive does not match the image.");
+                exit(1);
             }
+            (Step::Write, BurnError::WriteFailed(_)) => fatal!("The drive did not store the image, it may be failing."),
+            (Step::Write, BurnError::Timeout(_)) => fatal!("The drive stopped responding, the image was not written completely."),
+            (Step::Write, _) => fatal!("Error writing the image."),
+            (Step::CreateImage, _) => fatal!("Error creating the image file."),
+            (Step::Check, _) => fatal!("The destination can't take the image."),
+            (Step::Unmount, _) => fatal!("Error unmounting the destination."),
+            (Step::Wipe, _) => fatal!("Error wiping the destination."),
+            (Step::Partition, _) => fatal!("Error creating partition table."),
+            (Step::Format, _) => fatal!("Error formatting volume."),
+            (Step::AddFiles, _) => fatal!("Error adding the files to the volume."),
+            (Step::Persistence, _) => fatal!("Error formatting the persistence partition."),
+            (Step::CheckFree, _) => fatal!("The files were copied, but there is less free space left than --min-free asks for."),
+            (Step::Verify, _) => fatal!("Error verifying the image."),
+            (Step::MountCheck, _) => fatal!("The drive was written, but its first partition does not mount."),
+            (Step::After, _) => fatal!("The image was written, but the --after command did not succeed."),
+            (Step::Eject, _) => fatal!("Error ejecting the destination."),
         }
-
+        print_error(e);
+        exit(1);
     }
-    use std::os::unix::fs::MetadataExt;
-    let iso_size = std::path::Path::new(file_path).metadata()?.size();
-    let iso_file = File::open(file_path)?;
-    let mut read = ISO9660::from_device(FileDevice(iso_file));
-    let iso = read.read_root();
-    let mut label: &str = iso[2].name.as_ref();
-    if label.is_empty() {
-        label = "NO_NAME";
+}
+
+/// Stops with a warning if the user hit Ctrl-C during the last step.
+fn exit_if_aborted() {
+    if interrupt::requested() {
+        ceprintln!("\x1b[1m\x1b[33mWarning! \x1b[39mAborted, the disk is in an inconsistent state.\x1b[0m");
+        exit(130);
     }
-    let mut binding = label.replace(" ", "").replace(".", "").replace("-","");
-    if binding.len() > 11 {
-        // if length is more than 11 chars then make it ten [chop them off]
-        binding = binding.chars().take(10).collect::<String>();
+}
+
+/// Sends the library's diagnostics to stderr, styled like the rest of the output.
+fn init_logger(verbose: u8, quiet: bool) {
+    let level = match verbose {
+        0 if quiet => "error",
+        0 => "warn",
+        1 => "info",
+        _ => "debug",
+    };
+    let style = if color::stderr_enabled() { env_logger::WriteStyle::Always } else { env_logger::WriteStyle::Never };
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(level))
+        .write_style(style)
+        // Status lines wait on their DONE without a newline, clear them first; the DONE lands below.
+        .format(|buf, record| match record.level() {
+            log::Level::Error => writeln!(buf, "\r\x1b[2K\x1b[1m\x1b[31mError: {}\x1b[0m", record.args()),
+            log::Level::Warn => writeln!(buf, "\r\x1b[2K\x1b[1m\x1b[33mWarning! \x1b[39m{}\x1b[0m", record.args()),
+            level => writeln!(buf, "\r\x1b[2K\x1b[2m[{}] {}\x1b[0m", level.as_str().to_lowercase(), record.args()),
+        })
+        .init();
+}
+
+/// Entry point.
+fn main() {
+    if let Err(e) = run() {
+        print_error(e.as_ref());
+        exit(1);
     }
-    let label = binding.as_str().as_ref();
+}
 
-    // Summary
-    println!("\x1b[1mSummary:\x1b[0m");
-    println!("Writing \x1b[1m{}\x1b[0m to \x1b[1m{}.\x1b[0m", file_path.split("/").last().unwrap(), dest_path);
-    println!("Partitioning table: \x1b[1m{}\x1b[0m", table);
-    println!("Filesystem: \x1b[1m{}\x1b[0m", fs);
-    println!("Label: \x1b[1m{}\x1b[0m", label);
-    println!("\x1b[1m\x1b[33mWarning!\x1b[39m This will \x1b[31mDESTROY\x1b[39m all data on the destination drive.\x1b[0m");
-    let mut confirmation = String::new();
-    println!("\x1b[1mAre you sure you want to continue? [Y/n]\x1b[0m");
-    std::io::stdin().read_line(&mut confirmation).expect("Error reading input");
-    let confirmation = confirmation.trim();
-    if confirmation.to_lowercase() != "y" {
-        eprintln!("\x1b[1mExiting...\x1b[0m");
-        std::process::exit(0);
-    }
-    eprint!("\x1b[1m[ .... ] Creating a {} partition table...\x1b[0m", table);
-    stdout().flush()?;
-    let mut result: Result<(), Box<dyn Error>>;
-    match table.as_str() {
-        "dos" => {
-            result = new_dos_mbr(dest_path, iso_size);
-        }
-        "gpt" => {
-            result = new_gpt(dest_path, iso_size);
+fn run() -> Result<(), Box<dyn Error>> {
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    if let Some(shell) = args.completions {
+        // Completions go by the name of the installed binary, not the crate's.
+        // Generated up front, clap_complete panics when the pipe closes early (| head).
+        let mut script = Vec::new();
+        clap_complete::generate(shell, &mut Args::command(), "burn", &mut script);
+        let _ = stdout().write_all(&script);
+        return Ok(());
+    }
+    // An explicit --config has to be there, the default one doesn't.
+    let config_path = args.config.as_ref().map(std::path::PathBuf::from)
+        .or_else(|| config::default_path().filter(|path| path.exists()));
+    let config = match &config_path {
+        Some(path) => config::load(path).unwrap_or_else(|e| {
+            color::init(ColorChoice::Auto);
+            fatal!("Invalid config file \x1b[33m{}\x1b[39m: {}", path.display(), e.trim_end());
+            exit(1);
+        }),
+        None => config::Config::default(),
+    };
+    // The config only fills in what wasn't given on the command line.
+    let from_config = |id: &str| matches.value_source(id) != Some(ValueSource::CommandLine);
+    if let Some(color) = config.color.clone() && from_config("color") {
+        args.color = color;
+    }
+    if let Some(block_size) = config.block_size && from_config("block_size") {
+        args.block_size = block_size;
+    }
+    if let Some(verify) = config.verify && from_config("verify") && from_config("no_verify") {
+        args.verify = verify;
+    }
+    match args.color.parse::<ColorChoice>() {
+        Ok(choice) => color::init(choice),
+        Err(_) => {
+            color::init(ColorChoice::Auto);
+            fatal!("Unknown color choice \x1b[33m{}\x1b[39m (expected auto, always or never).", args.color);
+            exit(1);
+        }
+    }
+    match args.format.as_str() {
+        "human" => {}
+        "json" => {
+            JSON_OUTPUT.store(true, Ordering::Relaxed);
+            color::reserve_stdout();
         }
         _ => {
-            eprint!("\r\x1b[1m[\x1b[31m FAILED \x1b[39m] Creating a {} partition table...\x1b[0m", table);
-            stdout().flush()?;
-            println!();
-            eprintln!("\x1b[1m\x1b[31mFatal. \x1b[39mInvalid partition table.\x1b[0m");
-            std::process::exit(1);
+            fatal!("Unknown output format \x1b[33m{}\x1b[39m (expected human or json).", args.format);
+            exit(1);
         }
     }
-    if result.is_err() {
-        eprint!("\r\x1b[1m[\x1b[31m FAILED \x1b[39m] Creating a {} partition table...\x1b[0m", table);
-        stdout().flush()?;
-        println!();
-        eprintln!("\x1b[1m\x1b[31mFatal. \x1b[39mError creating partition table.\x1b[0m");
-        std::process::exit(1);
-    }
-    eprint!("\r\x1b[1m[\x1b[32m DONE \x1b[39m] Creating a {} partition table...\x1b[0m", table);
-    stdout().flush()?;
-    println!();
-    eprint!("\x1b[1m[ .... ] Formatting the volume as {}...\x1b[0m", fs);
-    let mut result: Result<(), Box<dyn Error>>;
-    match fs.as_str() {
-        "fat32" => result = make_fat(dest_path, label, 32),
-        "fat16" => result = make_fat(dest_path, label, 16),
-        "exfat" => result = make_exfat(dest_path, label, iso_size),
-        _ => {
-            eprint!("\r\x1b[1m[\x1b[31m FAILED \x1b[39m] Formatting the volume as {}...\x1b[0m", fs);
-            stdout().flush()?;
-            println!();
-            eprintln!("\x1b[1m\x1b[31mFatal. \x1b[39mInvalid filesystem.\x1b[0m");
-            std::process::exit(1);
+    // The TUI owns the terminal, log lines would scribble over it.
+    if !args.tui {
+        init_logger(args.verbose, args.quiet);
+    }
+    color::set_quiet(args.quiet);
+
+    // Partitions can't be opened by path on Windows, so there the image can only be written as-is.
+    if cfg!(windows) && !args.raw && !args.resume && !args.tui && !args.stdin {
+        fatal!("Only raw writes are supported on Windows for now, pass \x1b[33m--raw\x1b[39m.");
+        exit(1);
+    }
+
+    // Check the table and filesystem flags before anything else
+    let table_arg = match args.table.as_deref().map(str::parse::<PartitionTable>).transpose() {
+        // A partition holds one filesystem, formatted like a superfloppy
+        Ok(_) if args.no_partition_table || args.into_partition => Some(PartitionTable::None),
+        Ok(table) => table,
+        Err(_) => {
+            fatal!("Unknown partition table \x1b[33m{}\x1b[39m (expected dos, gpt, hybrid or none).", args.table.as_deref().unwrap_or_default());
+            exit(1);
+        }
+    };
+    let fs_arg = match args.filesystem.as_deref().map(str::parse::<Filesystem>).transpose() {
+        Ok(fs) => fs,
+        Err(_) => {
+            fatal!("Unknown filesystem \x1b[33m{}\x1b[39m (expected fat32, fat16, fat12, exfat, ntfs, ext4 or none).", args.filesystem.as_deref().unwrap_or_default());
+            exit(1);
+        }
+    };
+
+    // Defaults for the menus, for people who always pick the same
+    let table_default = env_default::<PartitionTable>("BURN_TABLE", "partition table", "dos, gpt, hybrid or none")
+        .or_else(|| config_default(&config.table, &config_path, "table", "partition table", "dos, gpt, hybrid or none"));
+    let fs_default = env_default::<Filesystem>("BURN_FS", "filesystem", "fat32, fat16, fat12, exfat, ntfs, ext4 or none")
+        .or_else(|| config_default(&config.filesystem, &config_path, "filesystem", "filesystem", "fat32, fat16, fat12, exfat, ntfs, ext4 or none"));
+
+    let source_type_arg = match args.source_type.as_deref().map(str::parse::<SourceType>).transpose() {
+        Ok(_) if args.raw_image => SourceType::Raw,
+        Ok(source_type) => source_type.unwrap_or(SourceType::Auto),
+        Err(_) => {
+            fatal!("Unknown source type \x1b[33m{}\x1b[39m (expected iso, raw or auto).", args.source_type.as_deref().unwrap_or_default());
+            exit(1);
         }
+    };
+
+    if let Some(cluster_size) = args.cluster_size
+        && check_cluster_size(cluster_size).is_err() {
+        fatal!("Invalid cluster size \x1b[33m{}\x1b[39m (expected a power of two from {} to {} bytes).", cluster_size, MIN_CLUSTER_SIZE, MAX_CLUSTER_SIZE);
+        exit(1);
     }
-    if result.is_err() {
-        eprint!("\r\x1b[1m[\x1b[31m FAILED \x1b[39m] Formatting the volume as {}...\x1b[0m", fs);
-        stdout().flush()?;
-        println!();
-        eprintln!("\x1b[1m\x1b[31mFatal. \x1b[39mError formatting volume.\x1b[0m");
-        eprintln!("\x1b[1m\x1b[31mError: {}\x1b[0m", result.unwrap_err());
-        std::process::exit(1);
+
+    let mbr_type_arg = match args.mbr_type.as_deref().map(|hex| u8::from_str_radix(hex.trim_start_matches("0x"), 16)).transpose() {
+        Ok(Some(0)) | Err(_) => {
+            fatal!("Invalid MBR partition type \x1b[33m{}\x1b[39m (expected a hex byte like 0c, 07 or ef).", args.mbr_type.as_deref().unwrap_or_default());
+            exit(1);
+        }
+        Ok(mbr_type) => mbr_type,
+    };
+    if mbr_type_arg.is_some() && matches!(table_arg, Some(PartitionTable::Gpt | PartitionTable::None)) {
+        fatal!("--mbr-type only applies to dos and hybrid partition tables.");
+        exit(1);
+    }
+    if args.boot_flag && matches!(table_arg, Some(PartitionTable::Gpt | PartitionTable::None)) {
+        fatal!("--boot-flag only applies to dos and hybrid partition tables, GPT drives boot without it.");
+        exit(1);
     }
-    eprint!("\r\x1b[1m[\x1b[32m DONE \x1b[39m] Formatting the volume as {}...\x1b[0m", fs);
-    println!();
-    eprint!("\x1b[1m[{}] Writing the iso to the volume...\x1b[0m", " ".repeat(15));
-    stdout().flush()?;
-    // start writing...
-    // write_image(file_path, dest_path)?;
-    eprint!("\r\x1b[1m[\x1b[32m DONE \x1b[39m] Writing the iso to the volume...{}\x1b[0m", "‎".repeat(32));
-    println!();
-    println!("Btw nothing happened..."); // UNFINISHED...
-    println!("\x1b[1m\x1b[32mSuccessfully written an image to disk!\x1b[0m");
 
+    let gpt_type_arg = match args.gpt_type.as_deref().map(str::parse::<Type>).transpose() {
+        Ok(gpt_type) => gpt_type,
+        Err(_) => {
+            fatal!("Unknown GPT partition type \x1b[33m{}\x1b[39m (expected efi, basic, linux_fs or a type GUID).", args.gpt_type.as_deref().unwrap_or_default());
+            exit(1);
+        }
+    };
+    if gpt_type_arg.is_some() && matches!(table_arg, Some(PartitionTable::Dos | PartitionTable::None)) {
+        fatal!("--gpt-type only applies to gpt partition tables.");
+        exit(1);
+    }
 
+    let parse_guid = |flag: &str, guid: &Option<String>| match guid.as_deref().map(uuid::Uuid::parse_str).transpose() {
+        Ok(guid) => guid,
+        Err(_) => {
+            fatal!("Invalid {} \x1b[33m{}\x1b[39m (expected a UUID like 01234567-89ab-cdef-0123-456789abcdef).", flag, guid.as_deref().unwrap_or_default());
+            exit(1);
+        }
+    };
+    let disk_guid_arg = parse_guid("disk GUID", &args.disk_guid);
+    let partition_guid_arg = parse_guid("partition GUID", &args.partition_guid);
+    if (disk_guid_arg.is_some() || partition_guid_arg.is_some()) && matches!(table_arg, Some(PartitionTable::Dos | PartitionTable::None)) {
+        fatal!("--disk-guid and --partition-guid only apply to gpt partition tables.");
+        exit(1);
+    }
 
+    let gpt_backup_arg = match args.gpt_backup.as_deref().map(str::parse::<GptBackup>).transpose() {
+        Ok(_) if args.no_table_backup => GptBackup::None,
+        Ok(backup) => backup.unwrap_or_default(),
+        Err(_) => {
+            fatal!("Unknown backup GPT placement \x1b[33m{}\x1b[39m (expected end, adjacent or none).", args.gpt_backup.as_deref().unwrap_or_default());
+            exit(1);
+        }
+    };
+    if gpt_backup_arg != GptBackup::End && matches!(table_arg, Some(PartitionTable::Dos | PartitionTable::None)) {
+        fatal!("--gpt-backup and --no-table-backup only apply to gpt and hybrid partition tables.");
+        exit(1);
+    }
 
-    Ok(())
-}
+    if args.partition_label.is_some() && matches!(table_arg, Some(PartitionTable::Dos | PartitionTable::None)) {
+        fatal!("--partition-label only applies to gpt partition tables, MBR partitions have no name.");
+        exit(1);
+    }
+    if let Some(name) = &args.partition_label
+        && fit_partition_name(name) != *name {
+        ceprintln!("\x1b[1m\x1b[33mWarning! \x1b[39mPartition label is too long, shortening it to \x1b[33m{}\x1b[39m.\x1b[0m", fit_partition_name(name));
+    }
+
+    if check_block_size(args.block_size.saturating_mul(1024)).is_err() {
+        fatal!("Invalid block size \x1b[33m{}\x1b[39m KB (expected 4 to 16384).", args.block_size);
+        exit(1);
+    }
+    if check_buffers(args.buffers).is_err() {
+        fatal!("Invalid buffer count \x1b[33m{}\x1b[39m (expected 1 to {}).", args.buffers, MAX_BUFFERS);
+        exit(1);
+    }
 
-/// Writes an image to the disk drive.
-/// TODO: Fix this function like what the hell it doesnt work as intended.
-fn write_image(file_path: &str, dest_path: &str) -> Result<(), Box<dyn Error>> {
-    let dest_path = format!("{}1", dest_path);
-    let mut file = OpenOptions::new().read(true).write(true).open(file_path)?;
-    let mut dest = OpenOptions::new().read(true).write(true).open(dest_path)?;
-    let file_size = file.metadata()?.len();
-    let mut bytes_written: u64 = 0;
+    if let Some(limit) = args.limit
+        && !(limit > 0.0 && limit.is_finite()) {
+        fatal!("Invalid speed limit \x1b[33m{}\x1b[39m MB/s (expected a positive number).", limit);
+        exit(1);
+    }
 
-    let mut buffer = [0u8; 65536]; // allocate a 64kb
+    if args.timeout == Some(0) {
+        fatal!("Invalid timeout \x1b[33m0\x1b[39m seconds (expected a positive number).");
+        exit(1);
+    }
 
-    loop {
-        let bytes_read = match file.read(&mut buffer) {
-            Ok(0) => break, // End of file
-            Ok(n) => n,
-            Err(e) => return Err(Box::new(e)),
+    let progress_kind = match args.progress.parse::<ProgressKind>() {
+        Ok(_) if args.quiet => ProgressKind::None,
+        Ok(kind) => kind,
+        Err(_) => {
+            fatal!("Unknown progress output \x1b[33m{}\x1b[39m (expected human, json or none).", args.progress);
+            exit(1);
+        }
+    };
+
+    let persistence = match args.persistence.as_deref().map(parse_size).transpose() {
+        Ok(size) => size,
+        Err(_) => {
+            fatal!("Invalid persistence size \x1b[33m{}\x1b[39m (expected e.g. 4G or 512M).", args.persistence.as_deref().unwrap_or_default());
+            exit(1);
+        }
+    };
+
+    if persistence.is_some() && table_arg == Some(PartitionTable::None) {
+        fatal!("--persistence needs a partition table to put the partition in.");
+        exit(1);
+    }
+
+    if args.align == 0 || args.align.checked_mul(1024 * 1024).is_none() {
+        fatal!("Invalid alignment \x1b[33m{}\x1b[39m MiB (expected a positive number).", args.align);
+        exit(1);
+    }
+    if args.align != 1 && table_arg == Some(PartitionTable::None) {
+        fatal!("--align only applies to partition tables, without one the filesystem starts at the beginning of the drive.");
+        exit(1);
+    }
+
+    let layout_arg = match args.layout.as_deref().map(parse_layout).transpose() {
+        Ok(layout) => layout,
+        Err(e) => {
+            fatal!("Invalid layout \x1b[33m{}\x1b[39m: {}.", args.layout.as_deref().unwrap_or_default(), e);
+            exit(1);
+        }
+    };
+    if layout_arg.is_some() && matches!(table_arg, Some(PartitionTable::Dos | PartitionTable::Hybrid | PartitionTable::None)) {
+        fatal!("--layout only applies to gpt partition tables.");
+        exit(1);
+    }
+
+    let mut add_files_arg = Vec::new();
+    for spec in &args.add_file {
+        let (source, dest) = match parse_add_file(spec) {
+            Ok(file) => file,
+            Err(e) => {
+                fatal!("Invalid file \x1b[33m{}\x1b[39m: {}.", spec, e);
+                exit(1);
+            }
         };
-        dest.write_all(&buffer[..bytes_read])?;
-        bytes_written += bytes_read as u64;
-        let progress = (bytes_written as f64 / file_size as f64) * 100.0;
-        let fill = progress.round() as f32 * 14.0_f32.round() / 100.0;
-        let empty_fill = 15_i32-fill.round() as i32;
-        let fill_chars = format!("{}>{}", "=".repeat(fill.round() as usize), " ".repeat(empty_fill as usize));
-        eprint!("\r[{}] {:.2}% ({}/{} mb) Writing the iso to the volume...", fill_chars, progress, bytes_written/1024/1024, file_size/1024/1024);
-        stdout().flush()?;
+        if !std::path::Path::new(&source).is_file() {
+            fatal!("File \x1b[33m{}\x1b[39m does not exist.", source);
+            exit(1);
+        }
+        add_files_arg.push((source, dest));
     }
-    dest.flush()?;
 
+    let min_free_arg = match args.min_free.as_deref().map(parse_size).transpose() {
+        Ok(size) => size,
+        Err(_) => {
+            fatal!("Invalid free space \x1b[33m{}\x1b[39m (expected e.g. 512M or 1G).", args.min_free.as_deref().unwrap_or_default());
+            exit(1);
+        }
+    };
 
-    
+    let image_arg = match args.image.as_deref().map(parse_size).transpose() {
+        Ok(Some(0)) | Err(_) => {
+            fatal!("Invalid image size \x1b[33m{}\x1b[39m (expected e.g. 8G or 512M).", args.image.as_deref().unwrap_or_default());
+            exit(1);
+        }
+        Ok(size) => size,
+    };
 
-    Ok(())
-}
+    let parse_offset = |flag: &str, offset: &Option<String>| match offset.as_deref().map(parse_size).transpose() {
+        Ok(offset) => offset.unwrap_or(0),
+        Err(_) => {
+            fatal!("Invalid {} offset \x1b[33m{}\x1b[39m (expected a byte count like 512 or 1M).", flag, offset.as_deref().unwrap_or_default());
+            exit(1);
+        }
+    };
+    let seek = parse_offset("--seek", &args.seek);
+    let skip = parse_offset("--skip", &args.skip);
+    // O_DIRECT (and Windows) only write whole sectors, 512 bytes at the least
+    if (args.direct || cfg!(windows)) && !seek.is_multiple_of(512) {
+        fatal!("--seek has to be a whole number of sectors with --direct, \x1b[33m{}\x1b[39m is not.", seek);
+        exit(1);
+    }
 
-fn make_exfat(drive_path: &str, label: &str,iso_size: u64) -> Result<(), Box<dyn Error>> {
-    let drive_path = format!("{}1", drive_path);
-    let mut file = OpenOptions::new().read(true).write(true).open(drive_path)?;
-    let label = Label::new(label.to_string());
-    // println!("{:?}", label); // debugging
-    let format_options = FormatVolumeOptionsBuilder::default()
-        .pack_bitmap(false)
-        .full_format(false)
-        .label(label.unwrap())
-        .dev_size(iso_size+512)
-        .bytes_per_sector(512)
-        .build()?;
+    let allowlist = match args.allowlist.as_deref().map(read_allowlist).transpose() {
+        Ok(Some(allowlist)) if allowlist.is_empty() => {
+            fatal!("The allowlist {} lists no drives.", args.allowlist.as_deref().unwrap_or_default());
+            exit(1);
+        }
+        Ok(allowlist) => allowlist,
+        Err(e) => {
+            fatal!("Could not read the allowlist {}.", args.allowlist.as_deref().unwrap_or_default());
+            print_error(&e);
+            exit(1);
+        }
+    };
 
-    let mut formatter = Exfat::try_from(format_options)?;
-    formatter.write(&mut file)?;
+    if args.list {
+        list_devices();
+        return Ok(());
+    }
 
-    Ok(())
-}
+    if args.tui {
+        let options = tui::TuiOptions {
+            source: args.file.clone(),
+            all_disks: args.all_disks,
+            source_type: source_type_arg,
+            block_size: args.block_size * 1024,
+            buffers: args.buffers,
+            direct: args.direct,
+            verify: args.verify,
+            allowlist: allowlist.clone(),
+        };
+        if !tui::run(options)? {
+            exit(1);
+        }
+        return Ok(());
+    }
 
-/// Use the fatfs crate to format the volume as fat.
-fn make_fat(drive_path: &str, label: &str, fat: u8) -> Result<(), Box<dyn Error>> {
-    let path_to_volume = format!("{}1", drive_path);
-    let mut file = OpenOptions::new().read(true).write(true).open(path_to_volume)?;
-    let mut fat_type: FatType;
-    match fat {
-        16 => {
-            fat_type = Fat16;
-        },
-        32 => {
-            fat_type = Fat32;
+    // With --stdin the one path given is the destination
+    let (file_path, destination_args) = if args.stdin {
+        if !args.destination.is_empty() {
+            fatal!("With --stdin, only pass the destination.");
+            exit(1);
+        }
+        if !args.yes && !args.compare {
+            fatal!("--stdin needs \x1b[33m--yes\x1b[39m, the prompts would read from the image.");
+            exit(1);
+        }
+        (STDIN, args.file.iter().map(String::as_str).collect::<Vec<_>>())
+    } else {
+        (args.file.as_deref().unwrap_or_default(), args.destination.iter().map(String::as_str).collect())
+    };
+
+    // Checksums only look at the image, so they go before anything touches a drive
+    if (args.print_checksum.is_some() || args.expect_checksum.is_some() || args.checksum_file.is_some()) && !std::path::Path::new(file_path).exists() {
+        fatal!("File does not exist.");
+        exit(1);
+    }
+    if let Some(name) = &args.print_checksum {
+        let algorithm = match name.parse::<ChecksumAlgorithm>() {
+            Ok(algorithm) => algorithm,
+            Err(_) => {
+                fatal!("Unknown checksum \x1b[33m{}\x1b[39m (expected sha256, sha512 or md5).", name);
+                exit(1);
+            }
+        };
+        // Same format as sha256sum and friends
+        println!("{}  {}", file_checksum(file_path, algorithm)?, file_path);
+        exit(0);
+    }
+    let listed = match &args.checksum_file {
+        Some(list_path) => match listed_checksum(list_path, file_path) {
+            Ok(Some(listed)) => Some(listed),
+            Ok(None) => {
+                ceprintln!("\x1b[1m\x1b[33mWarning! \x1b[39mThe image is not listed in {}, its checksum is not checked.\x1b[0m", list_path);
+                None
+            }
+            Err(e) => {
+                fatal!("Could not read the checksum file {}.", list_path);
+                print_error(&e);
+                exit(1);
+            }
         },
-        _ => {
-            return Err("Coder is stupid.".into());
-            // A case that should never happen.
+        None => None,
+    };
+    if let Some(expected) = args.expect_checksum.as_ref().or(listed.as_ref()) {
+        let expected = expected.trim().to_lowercase();
+        let algorithm = match ChecksumAlgorithm::from_hex_len(expected.len()) {
+            Some(algorithm) => algorithm,
+            None => {
+                fatal!("Can't tell the checksum type from its length (expected md5, sha256 or sha512 in hex).");
+                exit(1);
+            }
+        };
+        ceprint!("\x1b[1m[ .... ] Checking the {} checksum of the image...\x1b[0m", algorithm);
+        stdout().flush()?;
+        let actual = with_spinner(&format!("Checking the {} checksum of the image...", algorithm), || file_checksum(file_path, algorithm))?;
+        if actual != expected {
+            ceprint!("\r\x1b[1m[\x1b[31m FAILED \x1b[39m] Checking the {} checksum of the image...\x1b[0m", algorithm);
+            cprintln!();
+            fatal!("The image does not match the expected checksum, the download may be corrupt.");
+            ceprintln!("\x1b[1mExpected: {}\x1b[0m", expected);
+            ceprintln!("\x1b[1mGot:      {}\x1b[0m", actual);
+            exit(1);
+        }
+        ceprint!("\r\x1b[1m[\x1b[32m DONE \x1b[39m] Checking the {} checksum of the image...\x1b[0m", algorithm);
+        cprintln!();
+    }
+
+    let destinations = match destination_args.as_slice() {
+        [dest] if *dest != "auto" => vec![dest.to_string()],
+        [] | [_] if args.yes => {
+            fatal!("No destination given (can't pick one with --yes).");
+            exit(1);
+        }
+        [] | [_] => vec![pick_drive(args.all_disks, allowlist.as_deref())],
+        dests => dests.iter().map(|dest| dest.to_string()).collect(),
+    };
+    let batch = destinations.len() > 1;
+    if batch {
+        if destinations.iter().any(|dest| dest == "auto") {
+            fatal!("auto picks a single drive, pass every destination with several.");
+            exit(1);
+        }
+        if let Some((_, dest)) = destinations.iter().enumerate().find(|(i, dest)| destinations[..*i].contains(dest)) {
+            fatal!("{} is given twice.", dest);
+            exit(1);
+        }
+        if args.compare || args.resume || args.into_partition || image_arg.is_some() {
+            fatal!("--compare, --resume, --into-partition and --image only apply to a single destination.");
+            exit(1);
+        }
+        if disk_guid_arg.is_some() || partition_guid_arg.is_some() {
+            fatal!("--disk-guid and --partition-guid only apply to a single destination, no two drives should share a GUID.");
+            exit(1);
+        }
+        if progress_kind == ProgressKind::Json {
+            fatal!("--progress json only applies to a single destination.");
+            exit(1);
+        }
+    } else if args.keep_going {
+        fatal!("--keep-going only applies to several destinations.");
+        exit(1);
+    }
+    let dest_path = &destinations[0];
+
+    // Check for file path
+    if !args.stdin && !std::path::Path::new(file_path).exists() {
+        fatal!("File does not exist.");
+        exit(1);
+    }
+
+    if args.compare {
+        let mut job = BurnJob::raw(file_path, dest_path);
+        job.block_size = args.block_size * 1024;
+        job.buffers = args.buffers;
+        job.seek = seek;
+        job.skip = skip;
+        compare(&job);
+    }
+
+    // Check for file is actually being an iso (or a raw .img), unless --source-type says what it is
+    // (.gz, .xz and .zst get decompressed on the fly, so look past them)
+    let source_type = if args.stdin { Some(SourceType::Raw) } else { source_type_arg.resolve(file_path) };
+    let Some(source_type) = source_type else {
+        fatal!("File is not an iso or img disk image.");
+        ceprintln!("\x1b[1mIf it is a raw disk image anyway, pass \x1b[33m--source-type raw\x1b[39m.\x1b[0m");
+        exit(1);
+    };
+    let is_iso = source_type == SourceType::Iso;
+    // The extension alone proves nothing, renamed files and broken downloads get caught here
+    if is_iso && let Err(e) = check_iso(file_path) {
+        if source_type_arg == SourceType::Iso {
+            fatal!("File is not an iso, the download may be corrupt.");
+        } else {
+            fatal!("File is named like an iso but is not one, the download may be corrupt.");
+        }
+        print_error(&e);
+        ceprintln!("\x1b[1mIf it is a raw disk image anyway, pass \x1b[33m--source-type raw\x1b[39m.\x1b[0m");
+        exit(1);
+    }
+
+    // An image file to burn into instead of a drive, made by --image or already there
+    let image = destinations.iter().any(|dest| image_arg.is_some() || is_image_file(dest));
+    // Firmware only ever looks for the backup GPT in the last sector of a drive
+    if gpt_backup_arg == GptBackup::Adjacent && !destinations.iter().all(|dest| image_arg.is_some() || is_image_file(dest)) {
+        fatal!("--gpt-backup adjacent only applies to image files, on a drive the backup GPT has to be at the end.");
+        exit(1);
+    }
+    let overrides = Overrides::new(&args);
+    let mut removable = true;
+    for dest in &destinations {
+        removable &= check_destination(&args, overrides, file_path, dest, image_arg.is_some() || is_image_file(dest), allowlist.as_deref());
+    }
+
+    // Not fatal: some people burn data-only ISOs on purpose
+    if is_iso && Compression::detect(file_path)? == Compression::None && matches!(has_boot_catalog(file_path), Ok(false)) {
+        ceprintln!("\x1b[1m\x1b[33mWarning! \x1b[39mThe ISO has no El Torito boot catalog, the drive may not boot.\x1b[0m");
+    }
+
+    // Hybrid images carry their own partition table, recommend keeping it
+    // Stdin can't be sized up for a partition, so it always goes on raw
+    let mut raw = args.raw || args.stdin;
+    // An empty volume gets nothing of the image, its own layout doesn't matter then
+    if !raw && !args.resume && !args.empty_volume && matches!(is_hybrid(file_path), Ok(true)) {
+        ceprintln!("\x1b[1m\x1b[33mWarning! \x1b[39mThe image has its own partition table (isohybrid), it is meant to be written raw.\x1b[0m");
+        if table_arg.is_some() || fs_arg.is_some() || layout_arg.is_some() {
+            ceprintln!("\x1b[1mCopying its files onto the chosen partition table and filesystem. Pass \x1b[33m--raw\x1b[39m to keep the image's layout instead.\x1b[0m");
+        } else if args.yes {
+            ceprintln!("\x1b[1mWriting it raw.\x1b[0m");
+            raw = true;
+        } else {
+            cprintln!("\x1b[1mWrite it raw instead of copying its files onto a new partition table? [Y/n]\x1b[0m");
+            let mut input = String::new();
+            std::io::stdin().read_line(&mut input).expect("Error reading input");
+            raw = matches!(input.trim().to_lowercase().as_str(), "" | "y" | "yes");
         }
     }
-    let mut volume_label = [0u8; 11];
-    for (i, &b) in label.as_bytes().iter().take(11).enumerate() {
-        volume_label[i] = b;
+
+    // Only an uncompressed ISO has files to copy onto a new volume, anything else goes on as it is
+    // unless it's asked for an empty one. The image is never written over a partition table of ours,
+    // that would leave neither intact.
+    let copyable = is_iso && Compression::detect(file_path)? == Compression::None;
+    if !raw && !args.resume && !copyable && !args.empty_volume {
+        let partitioning = table_arg.is_some() || fs_arg.is_some() || layout_arg.is_some() || persistence.is_some()
+            || args.label.is_some() || !add_files_arg.is_empty() || min_free_arg.is_some()
+            || gpt_backup_arg != GptBackup::End;
+        if partitioning {
+            fatal!("Only the files of an uncompressed ISO can be copied onto a new volume, pass \x1b[33m--raw\x1b[39m to write the image as it is.");
+            ceprintln!("\x1b[1mPass \x1b[33m--empty-volume\x1b[39m to make the volume without them.\x1b[0m");
+            exit(1);
+        }
+        ceprintln!("\x1b[1m{} is not an uncompressed ISO, writing it raw.\x1b[0m", file_path.split("/").last().unwrap());
+        raw = true;
+    }
+    if !raw && !args.resume && (args.seek.is_some() || args.skip.is_some() || args.direct || args.limit.is_some() || args.timeout.is_some()) {
+        fatal!("--seek, --skip, --direct, --limit and --timeout only apply to raw burns, the files of the ISO are copied instead.");
+        exit(1);
+    }
+
+    // Resuming just continues the copy, so the job is a raw one either way
+    let mut job = if raw || args.resume {
+        BurnJob::raw(file_path, dest_path)
+    } else {
+        let table = match &layout_arg {
+            Some(_) => PartitionTable::Gpt,
+            None => choose_table(table_arg, table_default, args.yes),
+        };
+        // Filled in below, the filesystem menu needs the job to size up the volume
+        let mut job = BurnJob::new(file_path, dest_path, table, Filesystem::None, "");
+        job.image_size = image_arg;
+        job.min_free = min_free_arg;
+        // A layout brings its own filesystems, the first one stands in for the job's
+        let (fs, empty) = match &layout_arg {
+            Some(layout) => (layout[0].filesystem, args.empty_volume),
+            None => choose_filesystem(fs_arg, fs_default, args.yes, args.empty_volume, job.volume_size()?),
+        };
+        // Only uncompressed ISOs have a volume name we can read, use the file name for anything else.
+        let label = if let Some(label) = &args.label {
+            let max = max_label_len(fs);
+            if label_len(label, fs) > max {
+                if args.strict {
+                    let unit = if matches!(fs, Filesystem::Exfat | Filesystem::Ntfs) { "characters" } else { "bytes" };
+                    fatal!("Label \x1b[33m{}\x1b[39m is too long for {} (at most {} {}).", label, fs, max, unit);
+                    exit(1);
+                }
+                ceprintln!("\x1b[1m\x1b[33mWarning! \x1b[39mLabel is too long for {}, shortening it to \x1b[33m{}\x1b[39m.\x1b[0m", fs, fit_label(label, fs));
+            }
+            fit_label(label, fs)
+        } else if is_iso && Compression::detect(file_path)? == Compression::None {
+            fit_label(&iso_label(file_path)?, fs)
+        } else {
+            fit_label(&file_label(file_path), fs)
+        };
+        job.filesystem = fs;
+        job.empty = empty;
+        job.label = label;
+        job.cluster_size = args.cluster_size;
+        job.full_format = args.full_format;
+        job.pack_bitmap = args.pack_bitmap;
+        job.persistence = persistence;
+        job.mbr_type = mbr_type_arg;
+        job.boot_flag = args.boot_flag;
+        job.gpt_type = gpt_type_arg.clone();
+        job.partition_label = args.partition_label.clone();
+        job.disk_guid = disk_guid_arg;
+        job.partition_guid = partition_guid_arg;
+        job.layout = layout_arg.clone();
+        job.add_files = add_files_arg.clone();
+        job.alignment = args.align * 1024 * 1024;
+        job.gpt_backup = gpt_backup_arg;
+        // SD cards erase in blocks of several MiB, partitions straddling them are slower and wear the card out sooner.
+        // Their erase blocks set the default, an --align that doesn't fit them is only warned about.
+        if job.table != PartitionTable::None {
+            for dest in &destinations {
+                let Some(erase) = erase_block_size(dest) else { continue };
+                log::info!("{} erases in blocks of {} bytes", dest, erase);
+                if matches.value_source("align") == Some(ValueSource::CommandLine) {
+                    if !job.alignment.is_multiple_of(erase) {
+                        ceprintln!("\x1b[1m\x1b[33mWarning! \x1b[39m{} is an SD card that erases in blocks of {}, partitions aligned to {} straddle them.\x1b[0m",
+                            dest, human_size(erase), human_size(job.alignment));
+                        ceprintln!("\x1b[1mLeave out \x1b[33m--align\x1b[39m to align them to the erase blocks.\x1b[0m");
+                    }
+                } else if let Some(alignment) = erase_alignment(erase) {
+                    job.alignment = job.alignment.max(alignment);
+                }
+            }
+        }
+        job
+    };
+    job.block_size = args.block_size * 1024;
+    job.buffers = args.buffers;
+    job.direct = args.direct;
+    job.read_back = args.verify;
+    job.wipe = args.wipe;
+    job.resume = args.resume;
+    job.limit = args.limit.map(|mbps| ((mbps * 1024.0 * 1024.0) as u64).max(1));
+    job.timeout = args.timeout.map(Duration::from_secs);
+    job.seek = seek;
+    job.skip = skip;
+
+    if skip > 0 && !args.stdin && skip >= job.source_size()? {
+        fatal!("--skip \x1b[33m{}\x1b[39m leaves nothing to write, the image is only {}.", skip, human_size(job.source_size()?));
+        exit(1);
+    }
+    job.image_size = image_arg;
+    // The other drives of a batch get the same job
+    let others: Vec<BurnJob> = destinations[1..].iter().map(|dest| job.for_destination(dest)).collect();
+
+    // Every filesystem the job makes, the checks below go by these
+    let filesystems: Vec<Filesystem> = match &job.layout {
+        Some(layout) => layout.iter().map(|entry| entry.filesystem).collect(),
+        None => vec![job.filesystem],
+    };
+    let has_fat = filesystems.iter().any(|fs| matches!(fs, Filesystem::Fat32 | Filesystem::Fat16 | Filesystem::Fat12));
+
+    if filesystems.contains(&Filesystem::Exfat) && !has_fat && args.cluster_size.is_some() {
+        fatal!("The cluster size can only be chosen for FAT, not exFAT.");
+        exit(1);
+    }
+
+    // Nothing would be written, that has to be asked for
+    if !job.raw && !job.empty && !matches!(filesystems[0], Filesystem::Fat32 | Filesystem::Fat16 | Filesystem::Fat12) {
+        if filesystems[0] == Filesystem::None {
+            fatal!("The files of the ISO only go onto FAT, the partition would be left unformatted.");
+        } else {
+            fatal!("The files of the ISO only go onto FAT, {} would be left empty.", filesystems[0]);
+        }
+        ceprintln!("\x1b[1mPass \x1b[33m--empty-volume\x1b[39m to make it anyway, or \x1b[33m--raw\x1b[39m to write the image as it is.\x1b[0m");
+        exit(1);
+    }
+    if (!add_files_arg.is_empty() || job.min_free.is_some()) && !job.copies_files() {
+        let flag = if !add_files_arg.is_empty() { "--add-file" } else { "--min-free" };
+        fatal!("{} only applies to FAT volumes, the files are copied in without mounting it.", flag);
+        exit(1);
     }
 
-    format_volume(&mut file, FormatVolumeOptions::new().fat_type(fat_type).volume_label(volume_label))?;
+    // The files go in through the FAT itself, nothing gets mounted
+    if !job.raw && !job.empty && let Err(e) = job.check_files() {
+        fatal!("The files of the ISO can't be copied onto the new volume.");
+        print_error(&e);
+        ceprintln!("\x1b[1mPass \x1b[33m--raw\x1b[39m to write the image as it is instead.\x1b[0m");
+        exit(1);
+    }
+    if let Some(layout) = &job.layout
+        && job.copies_files()
+        && let PartitionSize::Bytes(size) = layout[0].size
+        && size < job.files_size()? {
+        fatal!("The files of the ISO take {}, more than the {} of {}, the first partition of the layout.", human_size(job.files_size()?), human_size(size), layout[0].name);
+        exit(1);
+    }
+
+    // Without a partition table the volume is as large as the drive, whatever --min-free asks for
+    if let Some(min_free) = job.min_free {
+        let (volume, used) = (job.volume_size()?, job.files_size()?);
+        if volume.saturating_sub(used) < min_free {
+            fatal!("Only {} would be left free on the volume, --min-free asks for {}.", human_size(volume.saturating_sub(used)), human_size(min_free));
+            exit(1);
+        }
+    }
+
+    if !job.raw && !filesystems.contains(&Filesystem::Exfat) && (args.full_format || args.pack_bitmap) {
+        fatal!("--full-format and --pack-bitmap only apply to exFAT.");
+        exit(1);
+    }
+
+    // Make sure the image fits before anything gets written
+    for job in std::iter::once(&job).chain(&others) {
+        let name = if batch { job.destination.as_str() } else { "the destination" };
+        match job.check_capacity() {
+            Ok(()) => {}
+            Err(BurnError::NoMedium(_)) => {
+                fatal!("There is no medium in {}, insert the card or disk first.", job.destination);
+                exit(1);
+            }
+            Err(BurnError::DeviceTooSmall(needed, available)) => {
+                let problem = if job.seek > 0 {
+                    format!("The image ends {} into {} with --seek, but it is only {}.", human_size(needed), name, human_size(available))
+                } else if job.raw || job.resume {
+                    format!("The image is {} but {} is only {}.", human_size(needed), name, human_size(available))
+                } else if job.layout.is_some() {
+                    format!("The layout needs {} with the partition table but {} is only {}.", human_size(needed), name, human_size(available))
+                } else {
+                    format!("The image needs {} with the partition table{} but {} is only {}.", human_size(needed), if job.persistence.is_some() { " and persistence" } else { "" }, name, human_size(available))
+                };
+                // Some card readers report the wrong size, the write fails where the drive really ends
+                if overrides.capacity {
+                    ceprintln!("\x1b[1m\x1b[33mWarning! \x1b[39m{}\x1b[0m", problem);
+                    continue;
+                }
+                fatal!("{}", problem);
+                exit(1);
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    if !job.raw && image && filesystems.contains(&Filesystem::Ntfs) {
+        fatal!("mkfs.ntfs can't format a partition inside an image file, pick another filesystem.");
+        exit(1);
+    }
+
+    if !job.raw && filesystems.contains(&Filesystem::Ntfs) && !has_tool("mkfs.ntfs") {
+        fatal!("mkfs.ntfs is not installed (it comes with \x1b[33mntfs-3g\x1b[39m), install it or pick another filesystem.");
+        exit(1);
+    }
+
+    if !job.raw && filesystems.contains(&Filesystem::Ext4) && !has_tool("mke2fs") {
+        fatal!("mke2fs is not installed (it comes with \x1b[33me2fsprogs\x1b[39m), install it or pick another filesystem.");
+        exit(1);
+    }
+
+    // FAT only addresses so much (FAT12 just floppies), catch that before touching the disk
+    match job.check_volume_size() {
+        Ok(()) => {}
+        Err(BurnError::VolumeTooLarge(_, size)) => {
+            let max = max_volume_size(job.filesystem).unwrap_or_default();
+            fatal!("The volume is {}, too large for {} (at most {}), use {}.", human_size(size), job.filesystem, human_size(max), larger_filesystem(size));
+            exit(1);
+        }
+        Err(e) => return Err(e.into()),
+    }
+
+    // UEFI boots copied files from the EFI folder, BIOSes only the image's own boot code, which --raw keeps
+    let contents = if job.copies_files() {
+        let efi = iso_files(file_path)?.iter().any(|file| file.path.to_lowercase().trim_start_matches('/').starts_with("efi/boot/"));
+        match (job.is_bootable(), efi) {
+            (false, _) => "\x1b[1mthe files of the ISO\x1b[0m (copied onto the volume)",
+            (true, true) => "\x1b[1mthe files of the ISO\x1b[0m (copied onto the volume, only UEFI boots it, BIOSes need --raw)",
+            (true, false) => "\x1b[1mthe files of the ISO\x1b[0m (copied onto the volume, the drive won't boot, --raw keeps it bootable)",
+        }
+    } else if job.layout.as_ref().map_or(job.filesystem, |layout| layout[0].filesystem) == Filesystem::None {
+        "\x1b[1mnothing\x1b[0m (the partition is left unformatted)"
+    } else {
+        "\x1b[1mnothing\x1b[0m (the volume is left empty)"
+    };
+
+    // Summary
+    cprintln!("\x1b[1mSummary:\x1b[0m");
+    let source_name = if args.stdin { "standard input" } else { file_path.split("/").last().unwrap() };
+    let (drive, drive_name) = describe_destination(dest_path, job.image_size);
+    if batch {
+        cprintln!("Writing \x1b[1m{}\x1b[0m to \x1b[1m{} drives:\x1b[0m", source_name, destinations.len());
+        for dest in &destinations {
+            let (drive, drive_name) = describe_destination(dest, None);
+            cprintln!("  {}", destination_line(dest, drive.as_ref(), &drive_name));
+        }
+    } else {
+        cprintln!("Writing \x1b[1m{}\x1b[0m to \x1b[1m{}.\x1b[0m", source_name, dest_path);
+        cprintln!("Destination: {}", destination_line(dest_path, drive.as_ref(), &drive_name));
+    }
+    if job.resume {
+        cprintln!("Mode: \x1b[1mresume\x1b[0m (blocks already on the drive are skipped)");
+    } else if job.raw {
+        cprintln!("Mode: \x1b[1mraw\x1b[0m (no partition table or filesystem)");
+    } else if let Some(layout) = &job.layout {
+        cprintln!("Partitioning table: \x1b[1m{}\x1b[0m", job.table);
+        cprintln!("Partitions:");
+        for (i, entry) in layout.iter().enumerate() {
+            let size = match entry.size {
+                PartitionSize::Bytes(size) => human_size(size),
+                PartitionSize::Rest => "rest of the drive".to_string(),
+            };
+            cprintln!("  {}. \x1b[1m{}\x1b[0m: {}, {}", i + 1, entry.name, size, entry.filesystem);
+        }
+        cprintln!("Contents: {}", contents);
+        if let Some(guid) = job.disk_guid {
+            cprintln!("Disk GUID: \x1b[1m{}\x1b[0m", guid);
+        }
+        if let Some(guid) = job.partition_guid {
+            cprintln!("Partition GUID: \x1b[1m{}\x1b[0m", guid);
+        }
+        if job.full_format {
+            cprintln!("Format: \x1b[1mfull\x1b[0m (zeroes the whole volume, this takes a while)");
+        }
+    } else {
+        if args.into_partition {
+            cprintln!("Partitioning table: \x1b[1mkept\x1b[0m (only {} is formatted)", dest_path);
+        } else if job.table == PartitionTable::None {
+            cprintln!("Partitioning table: \x1b[1mnone\x1b[0m (superfloppy, the filesystem takes the whole drive)");
+        } else {
+            cprintln!("Partitioning table: \x1b[1m{}\x1b[0m", job.table);
+        }
+        if matches!(job.table, PartitionTable::Gpt | PartitionTable::Hybrid) {
+            let gpt_type = job.gpt_type.clone().unwrap_or_else(|| gpt_type(job.filesystem, job.is_bootable()));
+            let name = match gpt_type {
+                t if t == partition_types::EFI => "EFI System Partition".to_string(),
+                t if t == partition_types::BASIC => "Basic data".to_string(),
+                t if t == partition_types::LINUX_FS => "Linux filesystem".to_string(),
+                t => t.guid.to_string(),
+            };
+            cprintln!("Partition type: \x1b[1m{}\x1b[0m", name);
+            cprintln!("Partition name: \x1b[1m{}\x1b[0m", fit_partition_name(job.partition_label.as_deref().unwrap_or(&job.label)));
+            if let Some(guid) = job.disk_guid {
+                cprintln!("Disk GUID: \x1b[1m{}\x1b[0m", guid);
+            }
+            if let Some(guid) = job.partition_guid {
+                cprintln!("Partition GUID: \x1b[1m{}\x1b[0m", guid);
+            }
+        }
+        if job.table == PartitionTable::Dos {
+            cprintln!("Partition type: \x1b[1m0x{:02x}\x1b[0m", job.mbr_type.unwrap_or_else(|| mbr_type(job.filesystem)));
+        }
+        if job.table == PartitionTable::Hybrid {
+            cprintln!("MBR partition type: \x1b[1m0x{:02x}\x1b[0m (the same partition, for BIOSes)", job.mbr_type.unwrap_or_else(|| mbr_type(job.filesystem)));
+        }
+        if matches!(job.table, PartitionTable::Dos | PartitionTable::Hybrid) && job.boot_flag {
+            cprintln!("Boot flag: \x1b[1mset\x1b[0m (the partition is marked active)");
+        }
+        cprintln!("Filesystem: \x1b[1m{}\x1b[0m", job.filesystem);
+        cprintln!("Label: \x1b[1m{}\x1b[0m", job.label);
+        if job.full_format {
+            cprintln!("Format: \x1b[1mfull\x1b[0m (zeroes the whole volume, this takes a while)");
+        }
+        if let Some(size) = job.persistence {
+            cprintln!("Persistence: \x1b[1m{}\x1b[0m (ext4, {})", human_size(size), PERSISTENCE_LABEL);
+        }
+        cprintln!("Contents: {}", contents);
+        if job.min_free.is_some() {
+            let (volume, used) = (job.volume_size()?, job.files_size()?);
+            cprintln!("Volume: \x1b[1m{}\x1b[0m ({} of files, {} left free)", human_size(volume), human_size(used), human_size(volume.saturating_sub(used)));
+        }
+        for (source, dest) in &job.add_files {
+            cprintln!("Extra file: \x1b[1m{}\x1b[0m (as /{})", source, dest);
+        }
+    }
+    if !job.raw && job.alignment != DEFAULT_ALIGNMENT {
+        cprintln!("Alignment: \x1b[1m{}\x1b[0m", human_size(job.alignment));
+    }
+    if !job.raw && matches!(job.table, PartitionTable::Gpt | PartitionTable::Hybrid) {
+        match job.gpt_backup {
+            GptBackup::End => {}
+            GptBackup::Adjacent => cprintln!("Backup GPT: \x1b[1mafter the last partition\x1b[0m (the image ends there)"),
+            GptBackup::None => cprintln!("Backup GPT: \x1b[1mnone\x1b[0m (the end of the drive is zeroed)"),
+        }
+    }
+    // The files of the ISO go on in chunks of their own
+    if job.raw {
+        cprintln!("Block size: \x1b[1m{}\x1b[0m", human_size(job.block_size as u64));
+    }
+    if job.raw && job.buffers != DEFAULT_BUFFERS {
+        cprintln!("Buffers: \x1b[1m{}\x1b[0m", job.buffers);
+    }
+    if let Some(limit) = args.limit {
+        cprintln!("Speed limit: \x1b[1m{} MB/s\x1b[0m", limit);
+    }
+    if job.wipe {
+        cprintln!("Wipe: \x1b[1m{}\x1b[0m at both ends of the destination are zeroed first", human_size(WIPE_SIZE));
+    }
+    if job.seek > 0 {
+        cprintln!("Seek: \x1b[1m{}\x1b[0m bytes into the destination", job.seek);
+    }
+    if job.skip > 0 {
+        cprintln!("Skip: \x1b[1m{}\x1b[0m bytes of the image", job.skip);
+    }
+    if args.dry_run {
+        cprintln!("\x1b[1mDry run, no changes made.\x1b[0m");
+        exit(0);
+    }
+    if batch {
+        cprintln!("\x1b[1m\x1b[33mWarning!\x1b[39m This will \x1b[31mDESTROY\x1b[39m all data on all {} destination drives.\x1b[0m", destinations.len());
+    } else {
+        cprintln!("\x1b[1m\x1b[33mWarning!\x1b[39m This will \x1b[31mDESTROY\x1b[39m all data on the destination drive.\x1b[0m");
+    }
+    if !args.yes && batch && (args.confirm_phrase || !removable) {
+        // No single path or size to type, the count makes sure the whole list was read
+        let mut confirmation = String::new();
+        cprintln!("\x1b[1mType the number of drives (\x1b[33m{}\x1b[39m) to erase them all and continue:\x1b[0m", destinations.len());
+        std::io::stdin().read_line(&mut confirmation).expect("Error reading input");
+        if confirmation.trim() != destinations.len().to_string() {
+            ceprintln!("\x1b[1mThat is not the number of drives, exiting...\x1b[0m");
+            exit(0);
+        }
+    } else if !args.yes && batch {
+        let mut confirmation = String::new();
+        cprintln!("\x1b[1mErase all \x1b[33m{}\x1b[39m drives and continue? [y/N]\x1b[0m", destinations.len());
+        std::io::stdin().read_line(&mut confirmation).expect("Error reading input");
+        if confirmation.trim().to_lowercase() != "y" {
+            ceprintln!("\x1b[1mExiting...\x1b[0m");
+            exit(0);
+        }
+    } else if !args.yes && (args.confirm_phrase || !removable) {
+        // A stray y is too easy to type for an internal disk, like gdisk have the drive named instead
+        let size = drive.as_ref().map(|d| human_size(d.size));
+        let mut confirmation = String::new();
+        match &size {
+            Some(size) => cprintln!("\x1b[1mType the path (\x1b[33m{}\x1b[39m) or the size (\x1b[33m{}\x1b[39m) of the drive to erase it and continue:\x1b[0m", dest_path, size),
+            None => cprintln!("\x1b[1mType the path (\x1b[33m{}\x1b[39m) of the drive to erase it and continue:\x1b[0m", dest_path),
+        }
+        std::io::stdin().read_line(&mut confirmation).expect("Error reading input");
+        let confirmation = confirmation.trim();
+        let same_size = |size: &String| confirmation.replace(' ', "").eq_ignore_ascii_case(&size.replace(' ', ""));
+        if confirmation != dest_path && !size.as_ref().is_some_and(same_size) {
+            ceprintln!("\x1b[1mThat is not the drive, exiting...\x1b[0m");
+            exit(0);
+        }
+    } else if !args.yes {
+        let mut confirmation = String::new();
+        cprintln!("\x1b[1mErase \x1b[33m{}\x1b[39m and continue? [y/N]\x1b[0m", drive_name);
+        std::io::stdin().read_line(&mut confirmation).expect("Error reading input");
+        let confirmation = confirmation.trim();
+        if confirmation.to_lowercase() != "y" {
+            ceprintln!("\x1b[1mExiting...\x1b[0m");
+            exit(0);
+        }
+    }
+    // From here on Ctrl-C stops at the next safe point instead of killing us mid-write.
+    interrupt::install_handler();
+    let options = RunOptions {
+        verify: args.verify,
+        mount_check: args.mount_check,
+        after: args.after.clone(),
+        // Its output would end up in the JSON
+        after_to_stderr: JSON_OUTPUT.load(Ordering::Relaxed),
+        eject: args.eject,
+        force_system_disk: overrides.system_disk,
+        force_capacity: overrides.capacity,
+        force_busy: overrides.busy,
+    };
+    if batch {
+        let options = batch::BatchOptions { run: options, keep_going: args.keep_going, progress: progress_kind };
+        burn_batch(std::iter::once(job).chain(others).collect(), options);
+    }
+    let mut steps = StatusLines { into_partition: args.into_partition, spinner: None };
+    let written = match job.run(&options, &mut steps, progress_kind.sink().as_mut()) {
+        Ok(written) => written,
+        // A failed step already said what went wrong, only Ctrl-C between steps gets here.
+        Err(e) => {
+            exit_if_aborted();
+            fatal!("Error burning the image.");
+            print_error(&e);
+            exit(1);
+        }
+    };
+    if job.raw || job.copies_files() {
+        cprintln!("\x1b[1m\x1b[32mSuccessfully written an image to disk!\x1b[0m");
+    } else {
+        cprintln!("\x1b[1m\x1b[32mSuccessfully made an empty volume, nothing of the image was written!\x1b[0m");
+    }
+    if JSON_OUTPUT.load(Ordering::Relaxed) {
+        let (table, filesystem, label) = if job.raw {
+            ("null".to_string(), "null".to_string(), "null".to_string())
+        } else {
+            (json_string(&job.table.to_string()), json_string(&job.filesystem.to_string()), json_string(&job.label))
+        };
+        println!(
+            "{{\"source\":{},\"destination\":{},\"table\":{},\"filesystem\":{},\"label\":{},\"bytes_written\":{},\"checksum\":{}}}",
+            json_string(file_path), json_string(dest_path), table, filesystem, label, written.len, json_checksum(&written.sha256),
+        );
+    }
+
+
+
 
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,317 @@
+//! Reporting how far a burn has got, for people or for programs driving us:
+//! which step it's on (see [`StepSink`]), and how far the write is (see [`ProgressSink`]).
+use std::fmt;
+use std::io::{stdout, IsTerminal, Write};
+use std::str::FromStr;
+use std::time::Instant;
+use crate::{BurnError, BurnJob, Filesystem};
+
+/// A snapshot of the copy.
+#[derive(Clone, Copy, Debug)]
+pub struct Progress {
+    /// Bytes of the image file done so far (compressed bytes for compressed images).
+    pub written: u64,
+    /// Size of the image file, 0 when it isn't known (an image piped in on stdin).
+    pub total: u64,
+    /// Throughput over roughly the last second, in bytes per second.
+    pub speed: f64,
+}
+
+impl Progress {
+    pub fn percent(&self) -> f64 {
+        if self.total == 0 {
+            return 100.0;
+        }
+        (self.written as f64 / self.total as f64) * 100.0
+    }
+
+    /// Estimated seconds left, if we know the speed and the size yet.
+    pub fn eta(&self) -> Option<u64> {
+        if self.speed > 0.0 && self.total > 0 {
+            Some((self.total.saturating_sub(self.written) as f64 / self.speed).round() as u64)
+        } else {
+            None
+        }
+    }
+}
+
+/// Something that shows progress updates.
+pub trait ProgressSink {
+    fn update(&mut self, progress: &Progress);
+}
+
+/// The steps of a burn, in the order [`BurnJob::run`] takes them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Step {
+    CreateImage,
+    Check,
+    Unmount,
+    Wipe,
+    Partition,
+    Format,
+    AddFiles,
+    Persistence,
+    Write,
+    CheckFree,
+    Verify,
+    MountCheck,
+    After,
+    Eject,
+}
+
+impl Step {
+    /// What the status line says while `job` is on this step.
+    pub fn status(self, job: &BurnJob) -> String {
+        match self {
+            Step::CreateImage => "Creating the image file...".to_string(),
+            Step::Check => "Checking the destination...".to_string(),
+            Step::Unmount => "Unmounting partitions...".to_string(),
+            Step::Wipe => "Wiping old signatures...".to_string(),
+            Step::Partition => match job.table {
+                crate::PartitionTable::None => "Clearing the old partition table...".to_string(),
+                table => format!("Creating a {} partition table...", table),
+            },
+            Step::Format => match (&job.layout, job.filesystem) {
+                (Some(layout), _) => format!("Formatting {} partitions...", layout.len()),
+                (None, Filesystem::None) => "Formatting the volume...".to_string(),
+                (None, filesystem) => format!("Formatting the volume as {}...", filesystem),
+            },
+            Step::AddFiles => format!("Adding {} file{} to the volume...", job.add_files.len(), if job.add_files.len() == 1 { "" } else { "s" }),
+            Step::Persistence => "Formatting the persistence partition as ext4...".to_string(),
+            Step::Write if job.raw => "Writing the image...".to_string(),
+            Step::Write if job.copies_files() => "Copying the files of the image...".to_string(),
+            Step::Write => "Checking the partition table...".to_string(),
+            Step::CheckFree => "Checking the free space on the volume...".to_string(),
+            Step::Verify => "Verifying the written image...".to_string(),
+            Step::MountCheck => "Mounting the volume to check it...".to_string(),
+            Step::After => "Running the --after command...".to_string(),
+            Step::Eject => "Ejecting...".to_string(),
+        }
+    }
+}
+
+/// How a step ended.
+pub enum StepEnd<'a> {
+    /// It went fine, with anything worth adding to the status (like what mounted).
+    Done(Option<String>),
+    /// There was nothing for it to do on this job, and why. Steps skipped up front were never started.
+    Skipped(&'static str),
+    /// It didn't work out, but the burn goes on anyway: what went wrong.
+    Warning(String),
+    /// It failed, the burn stops and [`BurnJob::run`] returns the error.
+    Failed(&'a BurnError),
+}
+
+/// Something that shows which step a burn is on.
+pub trait StepSink {
+    fn start(&mut self, job: &BurnJob, step: Step);
+    fn end(&mut self, job: &BurnJob, step: Step, end: StepEnd<'_>);
+}
+
+/// The progress bar on stderr, sized to fit the terminal.
+pub struct HumanProgress;
+
+/// What the progress line says after the numbers.
+const STEP: &str = "Writing the iso to the volume...";
+/// Widest the gauge gets, the `>` included.
+const GAUGE_WIDTH: usize = 15;
+/// Narrower than this, the gauge says too little to be worth the room, and is left out.
+const MIN_GAUGE_WIDTH: usize = 5;
+
+impl ProgressSink for HumanProgress {
+    fn update(&mut self, progress: &Progress) {
+        let width = line_width();
+        if progress.total == 0 {
+            // No idea how far along that is, just show that it moves.
+            let line = format!("[ {} mb, {:.1} MB/s ] {}", progress.written/1024/1024, progress.speed/1024.0/1024.0, STEP);
+            eprint!("\r{}", fit_line(&line, width));
+            let _ = stdout().flush();
+            return;
+        }
+        let eta = match progress.eta() {
+            Some(secs) => format!("{:02}:{:02}", secs / 60, secs % 60),
+            None => "--:--".to_string(),
+        };
+        let percent = progress.percent();
+        let stats = format!("{:.2}% ({}/{} mb, {:.1} MB/s, ETA {})", percent, progress.written/1024/1024, progress.total/1024/1024, progress.speed/1024.0/1024.0, eta);
+        // The step goes first when it's tight, then the gauge, the numbers stay as long as anything does.
+        let mut tail = format!(" {} {}", stats, STEP);
+        if width.saturating_sub(tail.len() + 2) < MIN_GAUGE_WIDTH {
+            tail = format!(" {}", stats);
+        }
+        let gauge_width = width.saturating_sub(tail.len() + 2).min(GAUGE_WIDTH);
+        let line = if gauge_width >= MIN_GAUGE_WIDTH {
+            format!("[{}]{}", gauge(percent, gauge_width), tail)
+        } else {
+            stats
+        };
+        eprint!("\r{}", fit_line(&line, width));
+        let _ = stdout().flush();
+    }
+}
+
+/// A gauge `width` characters wide: `=` for the part done, then `>`, then spaces for the rest.
+/// `percent` is clamped to 0 to 100 first (it goes past 100 when the image grew while we read it),
+/// and the fill to the gauge, so neither rounding nor odd sizes can push it past the end.
+fn gauge(percent: f64, width: usize) -> String {
+    if width == 0 {
+        return String::new();
+    }
+    let cells = width.saturating_sub(1);
+    let percent = if percent.is_nan() { 0.0 } else { percent.clamp(0.0, 100.0) };
+    let fill = ((percent / 100.0 * cells as f64).round() as usize).min(cells);
+    let empty_fill = cells.saturating_sub(fill);
+    format!("{}>{}", "=".repeat(fill), " ".repeat(empty_fill))
+}
+
+/// Cuts `line` down to `width` characters, or pads it with spaces to that, so it covers whatever the last one left.
+fn fit_line(line: &str, width: usize) -> String {
+    let line: String = line.chars().take(width).collect();
+    format!("{:<width$}", line, width = width)
+}
+
+/// Characters a line of progress can take: one less than the terminal is wide, so the cursor never wraps onto
+/// the next line. Taken as 80 columns wide when stderr isn't a terminal, or it can't tell.
+pub fn line_width() -> usize {
+    if !std::io::stderr().is_terminal() {
+        return 79;
+    }
+    match ratatui::crossterm::terminal::size() {
+        Ok((columns, _)) if columns > 0 => columns as usize - 1,
+        _ => 79,
+    }
+}
+
+/// One JSON object per line on stdout, at most ten times a second.
+pub struct JsonProgress {
+    last: Option<Instant>,
+}
+
+impl JsonProgress {
+    pub fn new() -> Self {
+        JsonProgress { last: None }
+    }
+}
+
+impl Default for JsonProgress {
+    fn default() -> Self {
+        JsonProgress::new()
+    }
+}
+
+impl ProgressSink for JsonProgress {
+    fn update(&mut self, progress: &Progress) {
+        let done = progress.total > 0 && progress.written >= progress.total;
+        if let Some(last) = self.last
+            && last.elapsed().as_millis() < 100 && !done {
+            return;
+        }
+        self.last = Some(Instant::now());
+        let eta = match progress.eta() {
+            Some(secs) => secs.to_string(),
+            None => "null".to_string(),
+        };
+        if progress.total == 0 {
+            println!("{{\"written\":{},\"total\":null,\"percent\":null,\"mbps\":{:.1},\"eta\":null}}", progress.written, progress.speed / 1024.0 / 1024.0);
+        } else {
+            println!("{{\"written\":{},\"total\":{},\"percent\":{:.1},\"mbps\":{:.1},\"eta\":{}}}", progress.written, progress.total, progress.percent(), progress.speed / 1024.0 / 1024.0, eta);
+        }
+        let _ = stdout().flush();
+    }
+}
+
+/// Shows nothing.
+pub struct NoProgress;
+
+impl ProgressSink for NoProgress {
+    fn update(&mut self, _progress: &Progress) {}
+}
+
+/// Which progress output to use, for frontends that take it as an option.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProgressKind {
+    Human,
+    Json,
+    None,
+}
+
+impl ProgressKind {
+    /// A sink that shows progress this way.
+    pub fn sink(self) -> Box<dyn ProgressSink> {
+        match self {
+            ProgressKind::Human => Box::new(HumanProgress),
+            ProgressKind::Json => Box::new(JsonProgress::new()),
+            ProgressKind::None => Box::new(NoProgress),
+        }
+    }
+}
+
+impl FromStr for ProgressKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "human" => Ok(ProgressKind::Human),
+            "json" => Ok(ProgressKind::Json),
+            "none" => Ok(ProgressKind::None),
+            _ => Err(format!("unknown progress output {} (expected human, json or none)", s)),
+        }
+    }
+}
+
+impl fmt::Display for ProgressKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProgressKind::Human => write!(f, "human"),
+            ProgressKind::Json => write!(f, "json"),
+            ProgressKind::None => write!(f, "none"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filled(gauge: &str) -> usize {
+        gauge.chars().filter(|c| *c == '=').count()
+    }
+
+    #[test]
+    fn gauge_goes_from_empty_to_full() {
+        assert_eq!(gauge(0.0, 11), ">          ");
+        assert_eq!(gauge(50.0, 11), "=====>     ");
+        assert_eq!(gauge(100.0, 11), "==========>");
+        let mut last = 0;
+        for tenths in 0..=1000 {
+            let fill = filled(&gauge(tenths as f64 / 10.0, 40));
+            assert!(fill >= last, "the gauge shrank at {}%", tenths as f64 / 10.0);
+            last = fill;
+        }
+        assert_eq!(last, 39);
+    }
+
+    #[test]
+    fn gauge_stays_within_its_width() {
+        for width in [0, 1, 2, 11, GAUGE_WIDTH] {
+            for percent in [0.0, 0.4, 50.0, 99.9, 100.0, 100.1, 250.0, -5.0, f64::NAN, f64::INFINITY] {
+                let gauge = gauge(percent, width);
+                assert_eq!(gauge.chars().count(), width, "{}% at width {}", percent, width);
+                assert!(filled(&gauge) <= width.saturating_sub(1), "{}% at width {}", percent, width);
+            }
+        }
+        assert_eq!(gauge(100.0, 1), ">");
+        assert_eq!(gauge(250.0, 11), gauge(100.0, 11));
+        assert_eq!(gauge(f64::NAN, 11), gauge(0.0, 11));
+    }
+
+    #[test]
+    fn percent_without_a_total() {
+        let unknown = Progress { written: 5, total: 0, speed: 0.0 };
+        assert_eq!(unknown.percent(), 100.0);
+        assert_eq!(unknown.eta(), None);
+        let grown = Progress { written: 150, total: 100, speed: 1.0 };
+        assert_eq!(gauge(grown.percent(), 11), "==========>");
+        assert_eq!(grown.eta(), Some(0));
+    }
+}
@@ -0,0 +1,856 @@
+//! Partition table creation.
+use gpt::{GptConfig, partition_types};
+use gpt::partition_types::Type;
+use gpt::mbr::ProtectiveMBR;
+use gpt::disk::LogicalBlockSize;
+use std::fmt::{self, Debug};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::process::Command;
+use std::str::FromStr;
+use std::time::Duration;
+use uuid::Uuid;
+use crate::device::{device_size, logical_sector_size, partition_path, reread_partitions, wait_for_node};
+use crate::error::BurnError;
+use crate::format::has_tool;
+use crate::{parse_size, Filesystem, PartitionTable};
+
+/// Name of the persistence partition, live systems (casper) look for it by this.
+pub const PERSISTENCE_LABEL: &str = "casper-rw";
+/// GPT partition names are at most this many UTF-16 code units.
+pub const GPT_NAME_MAX: usize = 36;
+/// How long the kernel gets to create the partition nodes after a new table is written.
+pub const PARTITION_TIMEOUT: Duration = Duration::from_secs(5);
+/// Room the partition table and partition alignment take up, generously.
+pub const TABLE_OVERHEAD: u64 = 4 * 1024 * 1024;
+/// Partitions start on a multiple of this many bytes unless told otherwise. Flash erases in blocks
+/// that big or a fraction of it, and partitioning tools have long agreed on 1 MiB.
+pub const DEFAULT_ALIGNMENT: u64 = 1024 * 1024;
+/// Largest alignment [`erase_alignment`] goes up to, anything past it is a card misreporting its erase blocks.
+pub const MAX_ERASE_ALIGNMENT: u64 = 64 * 1024 * 1024;
+/// How much [`wipe`] zeroes at each end of the drive.
+pub const WIPE_SIZE: u64 = 4 * 1024 * 1024;
+
+/// The MBR partition type byte that fits a filesystem. Firmware and some OSes go by it.
+pub fn mbr_type(filesystem: Filesystem) -> u8 {
+    match filesystem {
+        Filesystem::Fat32 => 0x0c, // FAT32 (LBA)
+        Filesystem::Fat16 => 0x0e, // FAT16 (LBA)
+        Filesystem::Fat12 => 0x01,
+        Filesystem::Exfat | Filesystem::Ntfs => 0x07,
+        Filesystem::Ext4 => 0x83,
+        Filesystem::None => 0xda, // Non-FS data
+    }
+}
+
+/// The GPT partition type that fits a filesystem. UEFI firmware only boots from a FAT
+/// partition typed as an EFI System Partition, so `bootable` images get that.
+pub fn gpt_type(filesystem: Filesystem, bootable: bool) -> Type {
+    match filesystem {
+        Filesystem::Fat32 | Filesystem::Fat16 | Filesystem::Fat12 if bootable => partition_types::EFI,
+        Filesystem::Ext4 => partition_types::LINUX_FS,
+        _ => partition_types::BASIC,
+    }
+}
+
+/// The alignment that starts partitions on both a MiB and an erase block of `erase_size` bytes
+/// (see [`crate::device::erase_block_size`]), so no write to the start of one spans two blocks.
+/// `None` if that would be past [`MAX_ERASE_ALIGNMENT`].
+pub fn erase_alignment(erase_size: u64) -> Option<u64> {
+    let (mut a, mut b) = (DEFAULT_ALIGNMENT, erase_size);
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    let alignment = DEFAULT_ALIGNMENT / a * erase_size;
+    (alignment <= MAX_ERASE_ALIGNMENT).then_some(alignment)
+}
+
+/// Shortens `name` to what fits in a GPT partition entry.
+pub fn fit_partition_name(name: &str) -> String {
+    let mut units = 0;
+    name.chars()
+        .take_while(|c| {
+            units += c.len_utf16();
+            units <= GPT_NAME_MAX
+        })
+        .collect()
+}
+
+/// Has the kernel pick up the table we just wrote to `disk` (the drive at `device_path`),
+/// and waits for the first `count` partitions to show up.
+pub fn settle_partitions(disk: &File, device_path: &str, count: usize) -> Result<(), BurnError> {
+    let reread = reread_partitions(disk);
+    log::debug!("re-reading the partition table of {}: {:?}", device_path, reread);
+    let first = partition_path(device_path, 1);
+    // Kernels without the parser for the table (or with a partition still busy) ignore the re-read;
+    // partprobe and partx add the partitions one by one instead.
+    if !wait_for_node(&first, Duration::from_secs(1)) {
+        log::info!("{} did not show up, asking partprobe/partx to add the partitions", first);
+        if has_tool("partprobe") {
+            let _ = Command::new("partprobe").arg(device_path).output();
+        } else if has_tool("partx") {
+            let _ = Command::new("partx").args(["-u", device_path]).output();
+        }
+    }
+    for number in 1..=count as u32 {
+        let path = partition_path(device_path, number);
+        if !wait_for_node(&path, PARTITION_TIMEOUT) {
+            let reason = match &reread {
+                Err(e) => format!("re-reading the partition table failed: {}", e),
+                Ok(()) => format!("waited {} seconds", PARTITION_TIMEOUT.as_secs()),
+            };
+            return Err(BurnError::PartitionTable(format!("{} did not show up ({})", path, reason)));
+        }
+    }
+    Ok(())
+}
+
+/// Byte offset and length of partition `number` (counting from 1) in the table on `disk`, the drive at `device_path`.
+/// Image files have no partition nodes, so their partitions are found by reading the table back.
+pub fn partition_range(mut disk: &File, device_path: &str, number: u32) -> Result<(u64, u64), BurnError> {
+    let ss = logical_sector_size(disk)? as u64;
+    let missing = || BurnError::PartitionTable(format!("{} has no partition {}", device_path, number));
+    let mbr = mbrman::MBR::read_from(&mut disk, ss as u32)?;
+    // A protective MBR holds a single 0xee partition, the real table is the GPT behind it.
+    if mbr.get(1).is_some_and(|p| p.sys == 0xee) {
+        let gpt = GptConfig::new()
+            .writable(false)
+            .logical_block_size(LogicalBlockSize::try_from(ss)?)
+            .open_from_device(&mut disk)?;
+        let partition = gpt.partitions().get(&number).ok_or_else(missing)?;
+        return Ok((partition.first_lba * ss, (partition.last_lba + 1 - partition.first_lba) * ss));
+    }
+    let entry = mbr.get(number as usize).filter(|p| p.is_used()).ok_or_else(missing)?;
+    Ok((entry.starting_lba as u64 * ss, entry.sectors as u64 * ss))
+}
+
+/// Checks that the first sector of `disk` still holds what [`crate::BurnJob::partition`] put there for `table`,
+/// see [`verify_table`].
+pub fn check_table(mut disk: &File, table: PartitionTable, filesystem: Filesystem, backup: GptBackup) -> Result<(), BurnError> {
+    let ss = logical_sector_size(disk)? as u64;
+    verify_table(&mut disk, ss, table, filesystem, backup)
+}
+
+/// Checks the first sector of `disk`, anything that reads, writes and seeks like a drive with `ss` byte sectors:
+/// an MBR with the first partition in use, a protective MBR in front of a GPT, or the hybrid MBR's pair of entries.
+/// Without a table it's the boot sector of the `filesystem` on the whole drive, if it has one (ext4 doesn't).
+/// GPTs are read back with [`verify_gpt`].
+pub fn verify_table<D: Read + Write + Seek + Debug>(disk: &mut D, ss: u64, table: PartitionTable, filesystem: Filesystem, backup: GptBackup) -> Result<(), BurnError> {
+    if table == PartitionTable::None && matches!(filesystem, Filesystem::Ext4 | Filesystem::None) {
+        return Ok(());
+    }
+    let mut lba0 = vec![0u8; ss as usize];
+    disk.seek(SeekFrom::Start(0))?;
+    disk.read_exact(&mut lba0)?;
+    let wrong = |what: &str| Err(BurnError::PartitionTable(format!("the first sector of the drive {}", what)));
+    if lba0[510..512] != [0x55, 0xaa] {
+        return wrong("has no boot signature");
+    }
+    if table == PartitionTable::None {
+        // FAT, exFAT and NTFS boot sectors all start with a jump over the BPB.
+        return if matches!(lba0[0], 0xeb | 0xe9) { Ok(()) } else { wrong("is not a boot sector") };
+    }
+    let mbr = mbrman::MBR::read_from(disk, ss as u32)?;
+    let first = mbr.get(1).filter(|p| p.is_used()).map(|p| p.sys);
+    let second = mbr.get(2).filter(|p| p.is_used()).map(|p| p.sys);
+    match (table, first, second) {
+        (PartitionTable::Dos, Some(sys), _) if sys != 0xee => Ok(()),
+        (PartitionTable::Gpt, Some(0xee), None) | (PartitionTable::Hybrid, Some(0xee), Some(_)) => verify_gpt(disk, ss, backup),
+        _ => wrong(&format!("does not hold the {} partition table", table)),
+    }
+}
+
+/// Size in bytes of a partition holding `iso_size` bytes: whole sectors, rounded up.
+fn partition_size(iso_size: u64, ss: u64) -> Result<u64, BurnError> {
+    if iso_size == 0 {
+        return Err(BurnError::PartitionTable("the image is empty".to_string()));
+    }
+    Ok(iso_size.div_ceil(ss) * ss)
+}
+
+/// How much of the drive a partition takes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PartitionSize {
+    /// This many bytes, rounded up to whole sectors.
+    Bytes(u64),
+    /// Whatever the other partitions leave free. Only one partition can have it.
+    Rest,
+}
+
+/// One partition of a `--layout` spec: its name (also the volume label), size and filesystem.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LayoutEntry {
+    pub name: String,
+    pub size: PartitionSize,
+    pub filesystem: Filesystem,
+}
+
+/// Parses a layout spec like `esp:256M:fat32,data:rest:exfat`: partitions separated by commas,
+/// each one `name:size:filesystem`. Sizes are what [`parse_size`] takes, or `rest` (at most once).
+pub fn parse_layout(spec: &str) -> Result<Vec<LayoutEntry>, String> {
+    let mut layout = Vec::new();
+    for part in spec.split(',') {
+        let fields: Vec<&str> = part.split(':').map(str::trim).collect();
+        let [name, size, filesystem] = fields[..] else {
+            return Err(format!("invalid partition {} (expected name:size:filesystem)", part));
+        };
+        if name.is_empty() {
+            return Err(format!("partition {} has no name", part));
+        }
+        let size = if size.eq_ignore_ascii_case("rest") {
+            if layout.iter().any(|entry: &LayoutEntry| entry.size == PartitionSize::Rest) {
+                return Err("only one partition can take the rest of the drive".to_string());
+            }
+            PartitionSize::Rest
+        } else {
+            match parse_size(size)? {
+                0 => return Err(format!("partition {} is empty", name)),
+                size => PartitionSize::Bytes(size),
+            }
+        };
+        layout.push(LayoutEntry { name: name.to_string(), size, filesystem: filesystem.parse()? });
+    }
+    Ok(layout)
+}
+
+/// Bytes the fixed-size partitions of a layout take together, the one taking the rest not counted.
+pub fn layout_size(layout: &[LayoutEntry]) -> u64 {
+    layout.iter()
+        .map(|entry| match entry.size {
+            PartitionSize::Bytes(size) => size,
+            PartitionSize::Rest => 0,
+        })
+        .sum()
+}
+
+/// One partition of a new GPT.
+#[derive(Clone, Debug)]
+pub struct GptPartition {
+    /// Partition name, shortened to fit.
+    pub name: String,
+    pub size: PartitionSize,
+    pub part_type: Type,
+    /// Partition GUID, `None` picks a random one.
+    pub guid: Option<Uuid>,
+}
+
+/// The partitions of a new GPT, in order, and the GUID of the table itself.
+#[derive(Clone, Debug)]
+pub struct GptOptions {
+    pub partitions: Vec<GptPartition>,
+    /// Every partition starts on a multiple of this many bytes, see [`DEFAULT_ALIGNMENT`].
+    pub alignment: u64,
+    /// Disk GUID, `None` picks a random one. Pin it and the partition GUIDs for reproducible images.
+    pub disk_guid: Option<Uuid>,
+    /// Mirror the first partition into the MBR as well, see [`write_hybrid_mbr`]. `None` writes a plain protective MBR.
+    pub hybrid: Option<HybridMbr>,
+    /// Where the backup GPT goes, if anywhere.
+    pub backup: GptBackup,
+}
+
+/// Where [`write_gpt`] puts the backup GPT header and its copy of the partition entries.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GptBackup {
+    /// In the last sectors of the drive, however small the partitions are. Firmware looks for it there.
+    #[default]
+    End,
+    /// Right after the last partition, with the image file cut off behind it. Image files only, for images
+    /// that get written to drives of all sizes and have their backup moved to the end there (`sgdisk -e`).
+    Adjacent,
+    /// Nowhere, for tools that write their own. The end of the drive is cleared so no stale backup is found there.
+    None,
+}
+
+impl FromStr for GptBackup {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "end" => Ok(GptBackup::End),
+            "adjacent" => Ok(GptBackup::Adjacent),
+            "none" => Ok(GptBackup::None),
+            _ => Err(format!("unknown backup GPT placement {} (expected end, adjacent or none)", s)),
+        }
+    }
+}
+
+impl fmt::Display for GptBackup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GptBackup::End => write!(f, "end"),
+            GptBackup::Adjacent => write!(f, "adjacent"),
+            GptBackup::None => write!(f, "none"),
+        }
+    }
+}
+
+/// The real partition entry of a hybrid MBR: its type byte, and whether it has the boot flag.
+#[derive(Clone, Copy, Debug)]
+pub struct HybridMbr {
+    pub sys: u8,
+    pub active: bool,
+}
+
+/// Zeroes the tables at both ends of `disk`: the MBR and primary GPT up front, and the backup GPT at the end.
+/// The filesystem that goes on next only writes its own boot sector over the first one.
+pub fn clear_table(disk: &File) -> Result<(), BurnError> {
+    let ss = logical_sector_size(disk)? as u64;
+    // The MBR, the GPT header and 32 sectors of entries; the backup leaves out the MBR.
+    zero_ends(disk, 34 * ss, 33 * ss)
+}
+
+/// Zeroes the first and last [`WIPE_SIZE`] bytes of `disk`, like `wipefs -a` plus `sgdisk --zap-all`.
+/// That takes out the MBR, both GPTs and the superblocks of whatever was on the drive
+/// (FAT and NTFS boot sectors, ext4 at 1 KiB, ISO 9660 at 32 KiB), which firmware would otherwise still find.
+pub fn wipe(disk: &File) -> Result<(), BurnError> {
+    zero_ends(disk, WIPE_SIZE, WIPE_SIZE)
+}
+
+/// Zeroes `head` bytes at the start of `disk` and `tail` bytes at its end, less on drives too small for both.
+fn zero_ends(mut disk: &File, head: u64, tail: u64) -> Result<(), BurnError> {
+    let size = device_size(disk)?;
+    let head = head.min(size);
+    let tail = tail.min(size - head);
+    let zeros = vec![0u8; head.max(tail) as usize];
+    disk.seek(SeekFrom::Start(0))?;
+    disk.write_all(&zeros[..head as usize])?;
+    disk.seek(SeekFrom::Start(size - tail))?;
+    disk.write_all(&zeros[..tail as usize])?;
+    disk.flush()?;
+    log::debug!("cleared {} bytes at the start and {} at the end", head, tail);
+    Ok(())
+}
+
+/// This function uses the `gpt` crate to create a new GPT table on `disk` with the partitions `options` lists.
+/// With [`GptBackup::Adjacent`] the image file is cut off right after the backup.
+pub fn new_gpt(mut disk: &File, options: &GptOptions) -> Result<(), BurnError> {
+    let ss = logical_sector_size(disk)? as u64;
+    check_room(disk, ss)?;
+    if options.backup == GptBackup::Adjacent && !disk.metadata()?.is_file() {
+        return Err(BurnError::PartitionTable("the backup GPT only goes right after the partitions in image files, \
+            firmware looks for it at the end of a drive".to_string()));
+    }
+    let size = write_gpt(&mut disk, ss, options)?;
+    if options.backup == GptBackup::Adjacent {
+        disk.set_len(size)?;
+        log::debug!("cut the image off after the backup GPT, at {} bytes", size);
+    }
+    Ok(())
+}
+
+/// Refuses drives too small to hold a partition table, [`TABLE_OVERHEAD`] at the least.
+/// Below that the table libraries fail in confusing ways, or lay out partitions that don't fit.
+fn check_room(disk: &File, ss: u64) -> Result<(), BurnError> {
+    let size = device_size(disk)?;
+    log::debug!("{} byte sectors, {} bytes", ss, size);
+    if size < TABLE_OVERHEAD {
+        return Err(BurnError::DeviceTooSmall(TABLE_OVERHEAD, size));
+    }
+    Ok(())
+}
+
+/// Writes a new GPT table to `disk`, anything that reads, writes and seeks like a drive with `ss` byte sectors.
+/// Returns how many bytes of `disk` the table covers: all of it, unless the backup goes right after the partitions.
+pub fn write_gpt<D: Read + Write + Seek + Debug>(disk: &mut D, ss: u64, options: &GptOptions) -> Result<u64, BurnError> {
+    let mut size = disk.seek(SeekFrom::End(0))?;
+    if options.backup == GptBackup::Adjacent {
+        // Lay the partitions out on the whole drive first, then again on a drive that ends right after the last one.
+        let gpt = lay_out_gpt(&mut *disk, ss, options)?;
+        let header = gpt.header();
+        let last_lba = gpt.partitions().values().map(|partition| partition.last_lba).max().unwrap_or(header.first_usable);
+        // The backup entries and header take as many sectors as they did at the end of the drive.
+        size = (last_lba + 1 + header.backup_lba - header.last_usable) * ss;
+        finish_gpt(&mut Shortened { inner: &mut *disk, len: size }, ss, options)?;
+    } else {
+        finish_gpt(disk, ss, options)?;
+    }
+    Ok(size)
+}
+
+/// Writes the table [`lay_out_gpt`] comes up with and the MBR in front of it, then reads it all back.
+fn finish_gpt<D: Read + Write + Seek + Debug>(disk: &mut D, ss: u64, options: &GptOptions) -> Result<(), BurnError> {
+    let gpt = lay_out_gpt(&mut *disk, ss, options)?;
+    // Everything from the last usable sector on is the backup's.
+    let backup_sectors = gpt.header().backup_lba - gpt.header().last_usable;
+    for (number, partition) in gpt.partitions() {
+        log::info!("GPT partition {} ({}): sectors {} to {}", number, partition.name, partition.first_lba, partition.last_lba);
+    }
+    // Write the GPT table back to the disk
+    let first = gpt.partitions().get(&1).map(|partition| (partition.first_lba, partition.last_lba));
+    gpt.write()?; // This writes the GPT partition table
+    if options.backup == GptBackup::None {
+        // A table that was here before left its backup behind, which would contradict the new one.
+        disk.seek(SeekFrom::End(-((backup_sectors * ss) as i64)))?;
+        disk.write_all(&vec![0u8; (backup_sectors * ss) as usize])?;
+    }
+    match (options.hybrid, first) {
+        (Some(hybrid), Some((first_lba, last_lba))) => write_hybrid_mbr(disk, ss, first_lba, last_lba, hybrid)?,
+        _ => {
+            let protective_mbr = ProtectiveMBR::new();
+            protective_mbr.overwrite_lba0(disk)?; // This writes protection MBR.
+        }
+    }
+    verify_gpt(disk, ss, options.backup)?;
+
+    Ok(()) // Success
+}
+
+/// A drive that seems to end after `len` bytes, to lay out a GPT whose backup sits right after its partitions.
+#[derive(Debug)]
+struct Shortened<D> {
+    inner: D,
+    len: u64,
+}
+
+impl<D: Read> Read for Shortened<D> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<D: Write> Write for Shortened<D> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<D: Seek> Seek for Shortened<D> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        match pos {
+            SeekFrom::End(offset) => {
+                let pos = self.len.checked_add_signed(offset)
+                    .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "seek before the start of the drive"))?;
+                self.inner.seek(SeekFrom::Start(pos))
+            }
+            pos => self.inner.seek(pos),
+        }
+    }
+}
+
+/// Lays the partitions of `options` out on `disk` in a new GPT, without writing anything yet.
+fn lay_out_gpt<D: Read + Write + Seek + Debug>(disk: D, ss: u64, options: &GptOptions) -> Result<gpt::GptDisk<D>, BurnError> {
+    // Initialize a new GPT partition table
+    let mut gpt = GptConfig::new()
+        .writable(true)
+        .logical_block_size(LogicalBlockSize::try_from(ss)?)
+        // The gpt crate leaves out the backup altogether when it's read-only.
+        .readonly_backup(options.backup == GptBackup::None)
+        .create_from_device(disk, Some(options.disk_guid.unwrap_or_else(Uuid::new_v4)))?;
+
+    // The table is new, so all the usable sectors are one free stretch.
+    let Some(&(first_usable, free)) = gpt.find_free_sectors().first() else {
+        return Err(BurnError::PartitionTable("the drive has no room for partitions".to_string()));
+    };
+    let end = first_usable + free;
+    let align = (options.alignment / ss).max(1);
+    let align_up = |lba: u64| lba.div_ceil(align) * align;
+    let sectors = options.partitions.iter()
+        .map(|partition| match partition.size {
+            PartitionSize::Bytes(size) => Ok(Some(partition_size(size, ss)? / ss)),
+            PartitionSize::Rest => Ok(None),
+        })
+        .collect::<Result<Vec<_>, BurnError>>()?;
+    // Partitions go one after the other, each starting on an alignment boundary.
+    let mut start = first_usable;
+    let mut guids = Vec::new();
+    for (i, partition) in options.partitions.iter().enumerate() {
+        start = align_up(start);
+        let length = match sectors[i] {
+            Some(length) => length,
+            // Up to where the partitions after it still fit, ending on a boundary so the next one starts on it.
+            None => {
+                let rest_end = match sectors[i + 1..].split_last() {
+                    None => end,
+                    Some((last, between)) => {
+                        let tail = between.iter().map(|length| align_up(length.unwrap_or(0))).sum::<u64>() + last.unwrap_or(0);
+                        end.saturating_sub(tail) / align * align
+                    }
+                };
+                match rest_end.checked_sub(start) {
+                    Some(length) if length > 0 => length,
+                    _ => return Err(BurnError::PartitionTable(format!("no room left for {}", partition.name))),
+                }
+            }
+        };
+        if start + length > end {
+            return Err(BurnError::PartitionTable(format!("{} does not fit on the drive", partition.name)));
+        }
+        let number = gpt.add_partition_at(
+            &fit_partition_name(&partition.name),
+            i as u32 + 1,
+            start,
+            length,
+            partition.part_type.clone(),
+            0,
+        )?;
+        if let Some(guid) = partition.guid {
+            guids.push((number, guid));
+        }
+        start += length;
+    }
+    // add_partition always makes up a GUID, swap in ours afterwards.
+    if !guids.is_empty() {
+        let mut partitions = gpt.partitions().clone();
+        for (number, guid) in guids {
+            if let Some(partition) = partitions.get_mut(&number) {
+                partition.part_guid = guid;
+            }
+        }
+        gpt.update_partitions(partitions)?;
+    }
+    Ok(gpt)
+}
+
+/// Writes a hybrid MBR to `disk`: the protective 0xEE entry first, covering the GPT up to the first partition,
+/// then a real entry for the sectors `first_lba` to `last_lba` of that partition. UEFI goes by the GPT,
+/// BIOSes (and Windows on them) only see the second entry. Nothing else of the drive shows up in the MBR,
+/// keeping the two tables from disagreeing about it.
+pub fn write_hybrid_mbr<D: Read + Write + Seek>(disk: &mut D, ss: u64, first_lba: u64, last_lba: u64, hybrid: HybridMbr) -> Result<(), BurnError> {
+    let too_large = || BurnError::PartitionTable("the first partition is too large for a hybrid MBR".to_string());
+    let starting_lba = u32::try_from(first_lba).map_err(|_| too_large())?;
+    let sectors = u32::try_from(last_lba + 1 - first_lba).map_err(|_| too_large())?;
+    let mut mbr = mbrman::MBR::new_from(disk, ss as u32, [0xff;4])?;
+    // Firmware and partition_range both take an 0xEE first entry to mean "read the GPT".
+    mbr[1] = mbrman::MBRPartitionEntry {
+        boot: mbrman::BOOT_INACTIVE,
+        first_chs: mbrman::CHS::empty(),
+        sys: 0xee,
+        last_chs: mbrman::CHS::empty(),
+        starting_lba: 1,
+        sectors: starting_lba - 1,
+    };
+    mbr[2] = mbrman::MBRPartitionEntry {
+        boot: if hybrid.active { mbrman::BOOT_ACTIVE } else { mbrman::BOOT_INACTIVE },
+        first_chs: mbrman::CHS::empty(),
+        sys: hybrid.sys,
+        last_chs: mbrman::CHS::empty(),
+        starting_lba,
+        sectors,
+    };
+    log::info!("hybrid MBR partition 2 (type 0x{:02x}): {} sectors from sector {}", hybrid.sys, sectors, starting_lba);
+    mbr.write_into(disk)?;
+    Ok(())
+}
+
+/// Reads back both GPT headers from `disk` and checks that they are intact and agree with each other.
+/// The backup has to sit in the very last sector of the drive, firmware goes looking for it there
+/// when the primary is damaged, however small the partitions are. With [`GptBackup::None`] only the primary is checked.
+pub fn verify_gpt<D: Read + Write + Seek + Debug>(disk: &mut D, ss: u64, backup: GptBackup) -> Result<(), BurnError> {
+    let last_lba = disk.seek(SeekFrom::End(0))? / ss - 1;
+    // The gpt crate checks the header CRCs, and reads the backup from the last sector.
+    let gpt = GptConfig::new()
+        .writable(false)
+        .logical_block_size(LogicalBlockSize::try_from(ss)?)
+        .open_from_device(&mut *disk)?;
+    let primary = gpt.primary_header()
+        .map_err(|e| BurnError::PartitionTable(format!("the primary GPT header did not read back: {}", e)))?
+        .clone();
+    if backup == GptBackup::None {
+        let mut entries = vec![0u8; primary.num_parts as usize * primary.part_size as usize];
+        disk.seek(SeekFrom::Start(primary.part_start * ss))?;
+        disk.read_exact(&mut entries)?;
+        if primary.current_lba != 1 || crc32fast::hash(&entries) != primary.crc32_parts {
+            return Err(BurnError::PartitionTable("the primary GPT does not check out".to_string()));
+        }
+        log::debug!("GPT header in sector 1 checks out, without a backup");
+        return Ok(());
+    }
+    let backup = gpt.backup_header()
+        .map_err(|e| BurnError::PartitionTable(format!("the backup GPT header is not in the last sector ({}): {}", last_lba, e)))?
+        .clone();
+    let mismatch = |what: &str| Err(BurnError::PartitionTable(format!("the GPT headers {}", what)));
+    if primary.current_lba != 1 || backup.current_lba != last_lba {
+        return mismatch("are not where they say they are");
+    }
+    if primary.backup_lba != last_lba || backup.backup_lba != 1 {
+        return mismatch(&format!("do not point at each other (backup at {}, the drive ends at {})", primary.backup_lba, last_lba));
+    }
+    if (primary.disk_guid, primary.first_usable, primary.last_usable, primary.num_parts, primary.part_size, primary.crc32_parts)
+        != (backup.disk_guid, backup.first_usable, backup.last_usable, backup.num_parts, backup.part_size, backup.crc32_parts) {
+        return mismatch("describe different disks");
+    }
+    // The backup entries sit between the last usable sector and the backup header.
+    if backup.part_start <= backup.last_usable || backup.part_start >= last_lba {
+        return mismatch("put the backup partition entries over the partitions");
+    }
+    for (name, header) in [("primary", &primary), ("backup", &backup)] {
+        let mut entries = vec![0u8; header.num_parts as usize * header.part_size as usize];
+        disk.seek(SeekFrom::Start(header.part_start * ss))?;
+        disk.read_exact(&mut entries)?;
+        if crc32fast::hash(&entries) != header.crc32_parts {
+            return Err(BurnError::PartitionTable(format!("the {} GPT partition entries do not match their checksum", name)));
+        }
+    }
+    log::debug!("GPT headers in sectors 1 and {} check out", last_lba);
+    Ok(())
+}
+
+/// This function writes a new MBR [dos] table to `disk`, the first partition gets the `sys` type byte.
+/// With `persistence`, a second partition of that many bytes follows the first one.
+/// Both start on a multiple of `alignment` bytes. `active` sets the boot flag on the first one, old BIOSes want it.
+pub fn new_dos_mbr(mut disk: &File, iso_size: u64, persistence: Option<u64>, sys: u8, active: bool, alignment: u64) -> Result<(), BurnError> {
+    let ss = logical_sector_size(disk)? as u64;
+    check_room(disk, ss)?;
+    write_dos_mbr(&mut disk, ss, iso_size, persistence, sys, active, alignment)
+}
+
+/// Writes a new MBR [dos] table to `disk`, anything that reads, writes and seeks like a drive with `ss` byte sectors.
+pub fn write_dos_mbr<D: Read + Write + Seek>(disk: &mut D, ss: u64, iso_size: u64, persistence: Option<u64>, sys: u8, active: bool, alignment: u64) -> Result<(), BurnError> {
+    let mut mbr = mbrman::MBR::new_from(disk, ss as u32, [0xff;4])?;
+    // find_optimal_place only hands out starts on this boundary, in sectors.
+    mbr.align = u32::try_from((alignment / ss).max(1))
+        .map_err(|_| BurnError::PartitionTable(format!("alignment {} is too large for MBR", alignment)))?;
+    mbr.write_into(disk)?;
+    let free_part_number = mbr.iter().find(|(_,p)| p.is_unused()).map(|(i,_)| i)
+        .ok_or_else(|| BurnError::PartitionTable("no free partition entry".to_string()))?;
+    // mbrman counts in sectors, round up so the last bit of the image isn't cut off.
+    let sectors = u32::try_from(partition_size(iso_size, ss)? / ss)
+        .map_err(|_| BurnError::PartitionTable("the image is too large for MBR".to_string()))?;
+    let starting_lba = mbr.find_optimal_place(sectors)
+        .ok_or_else(|| BurnError::PartitionTable("no room for the partition, the drive is too small".to_string()))?;
+    mbr[free_part_number] = mbrman::MBRPartitionEntry {
+        boot: if active { mbrman::BOOT_ACTIVE } else { mbrman::BOOT_INACTIVE },
+        first_chs: mbrman::CHS::empty(),
+        sys,
+        last_chs: mbrman::CHS::empty(),
+        starting_lba,
+        sectors,
+    };
+    if let Some(size) = persistence {
+        // MBR has no partition names, the filesystem label is all live systems get.
+        let part_number = mbr.iter().find(|(_,p)| p.is_unused()).map(|(i,_)| i)
+            .ok_or_else(|| BurnError::PartitionTable("no free entry for the persistence partition".to_string()))?;
+        let sectors = u32::try_from(size.div_ceil(ss))
+            .map_err(|_| BurnError::PartitionTable("the persistence partition is too large for MBR".to_string()))?;
+        let starting_lba = mbr.find_optimal_place(sectors)
+            .ok_or_else(|| BurnError::PartitionTable("no room for the persistence partition".to_string()))?;
+        mbr[part_number] = mbrman::MBRPartitionEntry {
+            boot: mbrman::BOOT_INACTIVE,
+            first_chs: mbrman::CHS::empty(),
+            sys: 0x83,
+            last_chs: mbrman::CHS::empty(),
+            starting_lba,
+            sectors
+        };
+    }
+    for (number, partition) in mbr.iter().filter(|(_, p)| p.is_used()) {
+        log::info!("MBR partition {} (type 0x{:02x}): {} sectors from sector {}", number, partition.sys, partition.sectors, partition.starting_lba);
+    }
+    mbr.write_into(disk)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const MIB: u64 = 1024 * 1024;
+    const TABLES: [PartitionTable; 4] = [PartitionTable::Dos, PartitionTable::Gpt, PartitionTable::Hybrid, PartitionTable::None];
+
+    fn gpt_options(size: u64, hybrid: Option<HybridMbr>) -> GptOptions {
+        GptOptions {
+            partitions: vec![GptPartition {
+                name: "ISO".to_string(),
+                size: PartitionSize::Bytes(size),
+                part_type: partition_types::BASIC,
+                guid: None,
+            }],
+            alignment: DEFAULT_ALIGNMENT,
+            disk_guid: None,
+            hybrid,
+            backup: GptBackup::End,
+        }
+    }
+
+    /// A 16 MiB drive in memory with `table` on it, a FAT boot sector for none.
+    fn laid_out(table: PartitionTable) -> Cursor<Vec<u8>> {
+        let mut disk = Cursor::new(vec![0u8; 16 * MIB as usize]);
+        match table {
+            PartitionTable::Dos => write_dos_mbr(&mut disk, 512, 4 * MIB, None, 0x0c, false, DEFAULT_ALIGNMENT).unwrap(),
+            PartitionTable::Gpt => {
+                write_gpt(&mut disk, 512, &gpt_options(4 * MIB, None)).unwrap();
+            }
+            PartitionTable::Hybrid => {
+                write_gpt(&mut disk, 512, &gpt_options(4 * MIB, Some(HybridMbr { sys: 0x0c, active: true }))).unwrap();
+            }
+            PartitionTable::None => fatfs::format_volume(&mut disk, fatfs::FormatVolumeOptions::new()).unwrap(),
+        }
+        disk
+    }
+
+    #[test]
+    fn check_table_accepts_each_table() {
+        for table in TABLES {
+            let result = verify_table(&mut laid_out(table), 512, table, Filesystem::Fat32, GptBackup::End);
+            assert!(result.is_ok(), "{}: {:?}", table, result);
+        }
+    }
+
+    #[test]
+    fn check_table_rejects_the_other_tables() {
+        for laid in TABLES {
+            for checked in TABLES.into_iter().filter(|checked| *checked != laid) {
+                let result = verify_table(&mut laid_out(laid), 512, checked, Filesystem::Fat32, GptBackup::End);
+                assert!(result.is_err(), "{} passed for {}", laid, checked);
+            }
+        }
+    }
+
+    #[test]
+    fn check_table_rejects_an_image_written_over_the_table() {
+        for table in TABLES {
+            let mut disk = laid_out(table);
+            // What an ISO has in its first sector: nothing, the system area is left zeroed.
+            disk.get_mut()[..512].fill(0);
+            assert!(verify_table(&mut disk, 512, table, Filesystem::Fat32, GptBackup::End).is_err(), "{}", table);
+        }
+    }
+
+    /// Image sizes around whole sectors of both sizes, the partition has to hold every byte of them.
+    const ISO_SIZES: [u64; 6] = [1, 511, 512, 513, 4095, 3 * MIB + 4097];
+
+    #[test]
+    fn gpt_partition_holds_the_whole_image() {
+        for ss in [512, 4096] {
+            for iso_size in ISO_SIZES {
+                let mut disk = Cursor::new(vec![0u8; 16 * MIB as usize]);
+                write_gpt(&mut disk, ss, &gpt_options(iso_size, None)).unwrap();
+                let gpt = GptConfig::new()
+                    .logical_block_size(LogicalBlockSize::try_from(ss).unwrap())
+                    .open_from_device(&mut disk)
+                    .unwrap();
+                let partition = &gpt.partitions()[&1];
+                let sectors = partition.last_lba + 1 - partition.first_lba;
+                assert!(sectors >= iso_size.div_ceil(ss), "{} bytes in {} sectors of {}", iso_size, sectors, ss);
+            }
+        }
+    }
+
+    #[test]
+    fn dos_partition_holds_the_whole_image() {
+        for ss in [512, 4096] {
+            for iso_size in ISO_SIZES {
+                let mut disk = Cursor::new(vec![0u8; 16 * MIB as usize]);
+                write_dos_mbr(&mut disk, ss, iso_size, None, 0x0c, false, DEFAULT_ALIGNMENT).unwrap();
+                let mbr = mbrman::MBR::read_from(&mut disk, ss as u32).unwrap();
+                let sectors = mbr[1].sectors as u64;
+                assert!(sectors >= iso_size.div_ceil(ss), "{} bytes in {} sectors of {}", iso_size, sectors, ss);
+            }
+        }
+    }
+
+    /// Sectors the backup GPT takes: its header and 128 entries of 128 bytes.
+    fn backup_sectors(ss: u64) -> u64 {
+        1 + (128 * 128u64).div_ceil(ss)
+    }
+
+    #[test]
+    fn adjacent_backup_ends_the_image() {
+        for ss in [512, 4096] {
+            let mut disk = Cursor::new(vec![0u8; 16 * MIB as usize]);
+            let options = GptOptions { backup: GptBackup::Adjacent, ..gpt_options(4 * MIB, None) };
+            let size = write_gpt(&mut disk, ss, &options).unwrap();
+            let gpt = GptConfig::new()
+                .logical_block_size(LogicalBlockSize::try_from(ss).unwrap())
+                .open_from_device(Shortened { inner: &mut disk, len: size })
+                .unwrap();
+            let last_lba = gpt.partitions().values().map(|partition| partition.last_lba).max().unwrap();
+            assert_eq!(size, (last_lba + 1 + backup_sectors(ss)) * ss, "{} byte sectors", ss);
+            disk.get_mut().truncate(size as usize);
+            let result = verify_table(&mut disk, ss, PartitionTable::Gpt, Filesystem::Fat32, GptBackup::Adjacent);
+            assert!(result.is_ok(), "{} byte sectors: {:?}", ss, result);
+        }
+    }
+
+    #[test]
+    fn no_backup_clears_the_end_of_the_drive() {
+        for ss in [512, 4096] {
+            // A table from before leaves its backup at the end, the new one has to clear it.
+            let mut disk = Cursor::new(vec![0u8; 16 * MIB as usize]);
+            write_gpt(&mut disk, ss, &gpt_options(4 * MIB, None)).unwrap();
+            let options = GptOptions { backup: GptBackup::None, ..gpt_options(4 * MIB, None) };
+            write_gpt(&mut disk, ss, &options).unwrap();
+            let end = disk.get_ref().len() - (backup_sectors(ss) * ss) as usize;
+            assert!(disk.get_ref()[end..].iter().all(|&b| b == 0), "{} byte sectors", ss);
+            let result = verify_table(&mut disk, ss, PartitionTable::Gpt, Filesystem::Fat32, GptBackup::None);
+            assert!(result.is_ok(), "{} byte sectors: {:?}", ss, result);
+        }
+    }
+
+    /// Changes the GPT header in sector `lba` of a 512 byte sector `disk` and fixes up its checksum,
+    /// so only what `change` did is wrong with it.
+    fn rewrite_header(disk: &mut Cursor<Vec<u8>>, lba: u64, change: impl FnOnce(&mut [u8])) {
+        let header = &mut disk.get_mut()[lba as usize * 512..][..92];
+        change(header);
+        header[16..20].fill(0);
+        let crc = crc32fast::hash(header);
+        header[16..20].copy_from_slice(&crc.to_le_bytes());
+    }
+
+    fn last_lba(disk: &Cursor<Vec<u8>>) -> u64 {
+        disk.get_ref().len() as u64 / 512 - 1
+    }
+
+    fn verify(disk: &mut Cursor<Vec<u8>>) -> Result<(), BurnError> {
+        verify_gpt(disk, 512, GptBackup::End)
+    }
+
+    #[test]
+    fn verify_gpt_accepts_a_fresh_table() {
+        assert!(verify(&mut laid_out(PartitionTable::Gpt)).is_ok());
+    }
+
+    #[test]
+    fn verify_gpt_rejects_a_misplaced_backup() {
+        // The drive goes on past the backup, as when an image is written to a bigger drive.
+        let mut disk = laid_out(PartitionTable::Gpt);
+        disk.get_mut().extend_from_slice(&[0u8; 512]);
+        assert!(verify(&mut disk).is_err());
+        // The backup header one sector early.
+        let mut disk = laid_out(PartitionTable::Gpt);
+        let last = last_lba(&disk) as usize * 512;
+        disk.get_mut().copy_within(last..last + 512, last - 512);
+        disk.get_mut()[last..].fill(0);
+        assert!(verify(&mut disk).is_err());
+    }
+
+    #[test]
+    fn verify_gpt_rejects_headers_that_dont_point_at_each_other() {
+        let mut disk = laid_out(PartitionTable::Gpt);
+        let last = last_lba(&disk);
+        rewrite_header(&mut disk, 1, |header| header[32..40].copy_from_slice(&(last - 1).to_le_bytes()));
+        assert!(verify(&mut disk).is_err());
+        let mut disk = laid_out(PartitionTable::Gpt);
+        rewrite_header(&mut disk, last, |header| header[32..40].copy_from_slice(&2u64.to_le_bytes()));
+        assert!(verify(&mut disk).is_err());
+        // A backup that claims to be somewhere else.
+        let mut disk = laid_out(PartitionTable::Gpt);
+        rewrite_header(&mut disk, last, |header| header[24..32].copy_from_slice(&(last - 1).to_le_bytes()));
+        assert!(verify(&mut disk).is_err());
+    }
+
+    #[test]
+    fn verify_gpt_rejects_backup_entries_over_the_partitions() {
+        let mut disk = laid_out(PartitionTable::Gpt);
+        let last = last_lba(&disk);
+        rewrite_header(&mut disk, last, |header| header[72..80].copy_from_slice(&2048u64.to_le_bytes()));
+        assert!(verify(&mut disk).is_err());
+    }
+
+    #[test]
+    fn verify_gpt_rejects_entries_that_dont_match_their_checksum() {
+        // The first entry's name, in the primary entries from sector 2 on and in the backup ones.
+        let mut disk = laid_out(PartitionTable::Gpt);
+        disk.get_mut()[2 * 512 + 56] ^= 0xff;
+        assert!(verify(&mut disk).is_err());
+        let mut disk = laid_out(PartitionTable::Gpt);
+        let backup_entries = (last_lba(&disk) - 32) as usize * 512;
+        disk.get_mut()[backup_entries + 56] ^= 0xff;
+        assert!(verify(&mut disk).is_err());
+    }
+}
@@ -0,0 +1,473 @@
+//! The full-screen front-end (`--tui`): pick the image, drive, table and filesystem with the keyboard,
+//! then watch the burn. Runs the same `BurnJob` steps as the CLI.
+use std::error::Error;
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::Duration;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Gauge, List, ListItem, ListState, Paragraph, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+use burn_rs::device::{erase_block_size, is_allowed, is_system_disk, list_drives, Drive};
+use burn_rs::format::{fit_label, has_tool};
+use burn_rs::image::{file_label, is_hybrid, iso_label, Compression, SourceType};
+use burn_rs::interrupt;
+use burn_rs::progress::{Progress, ProgressSink, Step, StepEnd, StepSink};
+use burn_rs::table::erase_alignment;
+use burn_rs::{BurnError, BurnJob, Filesystem, PartitionTable, RunOptions};
+use crate::human_size;
+
+/// Partition table choices, `None` writes the image as-is.
+/// Partitions can't be opened by path on Windows, so only raw writes are offered there.
+const TABLES: &[(Option<PartitionTable>, &str)] = if cfg!(windows) {
+    &[(None, "None, write the image as-is (raw)")]
+} else {
+    &[
+        (Some(PartitionTable::Gpt), "GPT"),
+        (Some(PartitionTable::Dos), "MBR (dos)"),
+        (Some(PartitionTable::Hybrid), "Hybrid, GPT plus an MBR for old BIOSes"),
+        (Some(PartitionTable::None), "None, format the whole drive (superfloppy)"),
+        (None, "None, write the image as-is (raw)"),
+    ]
+};
+
+/// Filesystem choices. The files of the ISO are copied in through the FAT, the others say they are left empty.
+const FILESYSTEMS: &[Filesystem] = &[
+    Filesystem::Fat32,
+    Filesystem::Fat16,
+    Filesystem::Fat12,
+    Filesystem::Exfat,
+    Filesystem::Ntfs,
+    Filesystem::Ext4,
+    Filesystem::None,
+];
+
+/// What the TUI takes over from the command line.
+pub struct TuiOptions {
+    pub source: Option<String>,
+    pub all_disks: bool,
+    pub source_type: SourceType,
+    pub block_size: usize,
+    pub buffers: usize,
+    pub direct: bool,
+    pub verify: bool,
+    /// Only these drives are offered, see `--allowlist`.
+    pub allowlist: Option<Vec<String>>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Screen {
+    Source,
+    Drive,
+    Table,
+    Filesystem,
+    Confirm,
+    Burning,
+    Done,
+}
+
+/// What the burn thread tells the screen.
+enum Update {
+    Step(String),
+    Progress(Progress),
+    Finished(Result<(), BurnError>),
+}
+
+/// Sends progress over to the screen.
+struct ChannelProgress(Sender<Update>);
+
+impl ProgressSink for ChannelProgress {
+    fn update(&mut self, progress: &Progress) {
+        let _ = self.0.send(Update::Progress(*progress));
+    }
+}
+
+/// Sends the step the burn is on over to the screen.
+struct ChannelSteps(Sender<Update>);
+
+impl StepSink for ChannelSteps {
+    fn start(&mut self, job: &BurnJob, step: Step) {
+        let _ = self.0.send(Update::Step(step.status(job)));
+    }
+
+    fn end(&mut self, _job: &BurnJob, _step: Step, _end: StepEnd<'_>) {}
+}
+
+struct App {
+    options: TuiOptions,
+    screen: Screen,
+    source: String,
+    drives: Vec<Drive>,
+    drive: ListState,
+    table: ListState,
+    filesystem: ListState,
+    /// Why the current screen can't go on.
+    error: Option<String>,
+    job: Option<BurnJob>,
+    /// The burn makes an empty volume, nothing of the image goes on the drive.
+    empty: bool,
+    step: String,
+    progress: Option<Progress>,
+    updates: Option<Receiver<Update>>,
+    result: Option<Result<(), BurnError>>,
+}
+
+impl App {
+    fn new(options: TuiOptions) -> Self {
+        let mut app = App {
+            source: options.source.clone().unwrap_or_default(),
+            options,
+            screen: Screen::Source,
+            drives: Vec::new(),
+            drive: ListState::default(),
+            table: ListState::default().with_selected(Some(0)),
+            filesystem: ListState::default().with_selected(Some(0)),
+            error: None,
+            job: None,
+            empty: false,
+            step: String::new(),
+            progress: None,
+            updates: None,
+            result: None,
+        };
+        app.refresh_drives();
+        app
+    }
+
+    /// System disks are never offered, internal ones only with --all-disks, and with an allowlist only the listed ones.
+    fn refresh_drives(&mut self) {
+        let all = self.options.all_disks;
+        let allowlist = self.options.allowlist.as_deref();
+        self.drives = list_drives().into_iter()
+            .filter(|d| all || d.removable)
+            .filter(|d| !is_system_disk(&d.path))
+            .filter(|d| allowlist.is_none_or(|allowlist| is_allowed(&d.path, allowlist)))
+            .collect();
+        self.drive.select(if self.drives.is_empty() { None } else { Some(0) });
+    }
+
+    fn selected_table(&self) -> Option<PartitionTable> {
+        TABLES[self.table.selected().unwrap_or(0)].0
+    }
+
+    fn selected_filesystem(&self) -> Filesystem {
+        FILESYSTEMS[self.filesystem.selected().unwrap_or(0)]
+    }
+
+    /// Checks the typed in source, the same way the CLI does.
+    fn check_source(&self) -> Result<(), String> {
+        let path = Path::new(&self.source);
+        if !path.is_file() {
+            return Err("File does not exist.".to_string());
+        }
+        if self.options.source_type.resolve(&self.source).is_none() {
+            return Err("File is not an iso or img disk image (pass --source-type raw if it is one anyway).".to_string());
+        }
+        Ok(())
+    }
+
+    /// Builds the job from what was picked and checks it can go ahead.
+    fn prepare_job(&mut self) -> Result<(), BurnError> {
+        self.job = None;
+        let Some(drive) = self.drive.selected().and_then(|i| self.drives.get(i)) else {
+            return Err(BurnError::NotBlockDevice("no drive selected".to_string()));
+        };
+        let mut job = match self.selected_table() {
+            None => BurnJob::raw(&self.source, &drive.path),
+            Some(table) => {
+                let fs = self.selected_filesystem();
+                let is_iso = self.options.source_type.resolve(&self.source) == Some(SourceType::Iso);
+                // Only uncompressed ISOs have a volume name we can read, use the file name for anything else.
+                let label = if is_iso && Compression::detect(&self.source)? == Compression::None {
+                    fit_label(&iso_label(&self.source)?, fs)
+                } else {
+                    fit_label(&file_label(&self.source), fs)
+                };
+                let mut job = BurnJob::new(&self.source, &drive.path, table, fs, &label);
+                job.empty = !matches!(fs, Filesystem::Fat32 | Filesystem::Fat16 | Filesystem::Fat12);
+                // SD cards get their partitions on erase block boundaries, like on the command line.
+                if table != PartitionTable::None && let Some(alignment) = erase_block_size(&drive.path).and_then(erase_alignment) {
+                    job.alignment = job.alignment.max(alignment);
+                }
+                job
+            }
+        };
+        job.block_size = self.options.block_size;
+        job.buffers = self.options.buffers;
+        job.direct = self.options.direct;
+        job.read_back = self.options.verify;
+        job.check()?;
+        job.check_capacity()?;
+        if !job.raw {
+            match job.filesystem {
+                Filesystem::Ntfs if !has_tool("mkfs.ntfs") => return Err(BurnError::MissingTool("mkfs.ntfs".to_string())),
+                Filesystem::Ext4 if !has_tool("mke2fs") => return Err(BurnError::MissingTool("mke2fs".to_string())),
+                _ => {}
+            }
+            job.check_volume_size()?;
+        }
+        self.job = Some(job);
+        Ok(())
+    }
+
+    /// Starts the burn on its own thread so the screen keeps updating.
+    fn start_burn(&mut self) {
+        let Some(mut job) = self.job.take() else { return };
+        self.empty = !job.raw && !job.copies_files();
+        let options = RunOptions { verify: self.options.verify, ..RunOptions::default() };
+        let (tx, rx) = mpsc::channel();
+        self.updates = Some(rx);
+        self.screen = Screen::Burning;
+        std::thread::spawn(move || {
+            let result = job.run(&options, &mut ChannelSteps(tx.clone()), &mut ChannelProgress(tx.clone())).map(drop);
+            let _ = tx.send(Update::Finished(result));
+        });
+    }
+
+    fn poll_updates(&mut self) {
+        let Some(updates) = &self.updates else { return };
+        while let Ok(update) = updates.try_recv() {
+            match update {
+                Update::Step(step) => self.step = step,
+                Update::Progress(progress) => self.progress = Some(progress),
+                Update::Finished(result) => {
+                    self.result = Some(result);
+                    self.screen = Screen::Done;
+                }
+            }
+        }
+    }
+
+    /// Handles a key, returns `true` once the TUI should close.
+    fn on_key(&mut self, key: KeyEvent) -> bool {
+        let ctrl_c = key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+        match self.screen {
+            Screen::Burning => {
+                // Ctrl-C is a key press in raw mode, stop at the next safe point like the CLI does.
+                if ctrl_c || key.code == KeyCode::Char('q') {
+                    interrupt::request();
+                    self.step = "Stopping...".to_string();
+                }
+                return false;
+            }
+            Screen::Done => return true,
+            _ if ctrl_c => return true,
+            _ => {}
+        }
+        if self.screen != Screen::Source && key.code == KeyCode::Char('q') {
+            return true;
+        }
+        match (self.screen, key.code) {
+            (Screen::Source, KeyCode::Esc) => return true,
+            (Screen::Source, KeyCode::Char(c)) => self.source.push(c),
+            (Screen::Source, KeyCode::Backspace) => { self.source.pop(); }
+            (Screen::Source, KeyCode::Enter) => match self.check_source() {
+                Ok(()) => {
+                    self.error = None;
+                    // Hybrid images carry their own partition table, suggest keeping it.
+                    if matches!(is_hybrid(&self.source), Ok(true)) {
+                        self.table.select(Some(TABLES.len() - 1));
+                    }
+                    self.screen = Screen::Drive;
+                }
+                Err(e) => self.error = Some(e),
+            },
+            (Screen::Drive, KeyCode::Char('r')) => self.refresh_drives(),
+            (Screen::Drive, KeyCode::Enter) if self.drive.selected().is_some() => self.screen = Screen::Table,
+            (Screen::Table, KeyCode::Enter) => {
+                if self.selected_table().is_some() {
+                    self.screen = Screen::Filesystem;
+                } else {
+                    self.confirm();
+                }
+            }
+            (Screen::Filesystem, KeyCode::Enter) => self.confirm(),
+            (Screen::Confirm, KeyCode::Char('y')) if self.job.is_some() => self.start_burn(),
+            (Screen::Drive | Screen::Table | Screen::Filesystem | Screen::Confirm, KeyCode::Esc | KeyCode::Backspace) => self.back(),
+            (_, KeyCode::Up | KeyCode::Char('k')) => {
+                if let Some(list) = self.list() {
+                    list.select_previous();
+                }
+            }
+            (_, KeyCode::Down | KeyCode::Char('j')) => self.move_down(),
+            _ => {}
+        }
+        false
+    }
+
+    fn confirm(&mut self) {
+        self.error = self.prepare_job().err().map(|e| e.to_string());
+        self.screen = Screen::Confirm;
+    }
+
+    fn back(&mut self) {
+        self.error = None;
+        self.screen = match self.screen {
+            Screen::Drive => Screen::Source,
+            Screen::Table => Screen::Drive,
+            Screen::Filesystem => Screen::Table,
+            Screen::Confirm if self.selected_table().is_some() => Screen::Filesystem,
+            Screen::Confirm => Screen::Table,
+            screen => screen,
+        };
+    }
+
+    fn list(&mut self) -> Option<&mut ListState> {
+        match self.screen {
+            Screen::Drive => Some(&mut self.drive),
+            Screen::Table => Some(&mut self.table),
+            Screen::Filesystem => Some(&mut self.filesystem),
+            _ => None,
+        }
+    }
+
+    /// Moves the selection down, without running off the end of the list.
+    fn move_down(&mut self) {
+        let len = match self.screen {
+            Screen::Drive => self.drives.len(),
+            Screen::Table => TABLES.len(),
+            Screen::Filesystem => FILESYSTEMS.len(),
+            _ => return,
+        };
+        if let Some(list) = self.list()
+            && list.selected().is_some_and(|i| i + 1 < len) {
+            list.select_next();
+        }
+    }
+}
+
+fn draw(frame: &mut Frame, app: &mut App) {
+    let [title, body, error, help] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Min(5),
+        Constraint::Length(2),
+        Constraint::Length(1),
+    ]).areas(frame.area());
+    frame.render_widget(Line::from(" burn-rs ").bold(), title);
+
+    let highlight = Style::default().add_modifier(Modifier::REVERSED);
+    let help_text = match app.screen {
+        Screen::Source => "Type the path of the image, Enter to continue, Esc to quit",
+        Screen::Drive => "Up/Down to choose, Enter to continue, r to rescan, Esc to go back, q to quit",
+        Screen::Table | Screen::Filesystem => "Up/Down to choose, Enter to continue, Esc to go back, q to quit",
+        Screen::Confirm if app.job.is_some() => "y to burn, Esc to go back, q to quit",
+        Screen::Confirm => "Esc to go back, q to quit",
+        Screen::Burning => "Ctrl-C or q to stop",
+        Screen::Done => "Press any key to exit",
+    };
+    frame.render_widget(Line::from(help_text).dim(), help);
+    if let Some(e) = &app.error {
+        frame.render_widget(Paragraph::new(e.as_str()).fg(Color::Red).wrap(Wrap { trim: true }), error);
+    }
+
+    match app.screen {
+        Screen::Source => {
+            let input = Paragraph::new(format!("{}_", app.source))
+                .block(Block::bordered().title(" Image to burn "));
+            frame.render_widget(input, body);
+        }
+        Screen::Drive => {
+            let items: Vec<ListItem> = if app.drives.is_empty() {
+                vec![ListItem::new("No suitable drives found, plug one in and press r.")]
+            } else {
+                app.drives.iter().map(|d| {
+                    let model = if d.model.is_empty() { "Unknown drive" } else { d.model.as_str() };
+                    let mut line = format!("{}  {}, {}", d.path, model, human_size(d.size));
+                    if !d.removable {
+                        line.push_str(" [internal]");
+                    }
+                    if !d.mount_points.is_empty() {
+                        line.push_str(&format!(" (mounted on {})", d.mount_points.join(", ")));
+                    }
+                    ListItem::new(line)
+                }).collect()
+            };
+            let list = List::new(items).block(Block::bordered().title(" Destination drive ")).highlight_style(highlight);
+            frame.render_stateful_widget(list, body, &mut app.drive);
+        }
+        Screen::Table => {
+            let list = List::new(TABLES.iter().map(|(_, name)| *name))
+                .block(Block::bordered().title(" Partition table ")).highlight_style(highlight);
+            frame.render_stateful_widget(list, body, &mut app.table);
+        }
+        Screen::Filesystem => {
+            let names = FILESYSTEMS.iter().map(|fs| match fs {
+                Filesystem::Fat32 | Filesystem::Fat16 | Filesystem::Fat12 => fs.to_string(),
+                _ => format!("{} (left empty)", fs),
+            });
+            let list = List::new(names)
+                .block(Block::bordered().title(" Filesystem ")).highlight_style(highlight);
+            frame.render_stateful_widget(list, body, &mut app.filesystem);
+        }
+        Screen::Confirm => {
+            let mut lines = vec![Line::from(format!("Writing {} to {}.", app.source, app.drive.selected().and_then(|i| app.drives.get(i)).map_or("", |d| d.path.as_str())))];
+            if let Some(job) = &app.job {
+                if job.raw {
+                    lines.push(Line::from("Mode: raw (no partition table or filesystem)"));
+                } else {
+                    lines.push(Line::from(format!("Partitioning table: {}", job.table)));
+                    lines.push(Line::from(format!("Filesystem: {}", job.filesystem)));
+                    lines.push(Line::from(format!("Label: {}", job.label)));
+                }
+                lines.push(Line::from(format!("Block size: {}", human_size(job.block_size as u64))));
+                lines.push(Line::from(""));
+                lines.push(Line::from("This will DESTROY all data on the destination drive.").fg(Color::Yellow).bold());
+            }
+            let summary = Paragraph::new(lines).block(Block::bordered().title(" Summary ")).wrap(Wrap { trim: false });
+            frame.render_widget(summary, body);
+        }
+        Screen::Burning => {
+            let [step, gauge] = Layout::vertical([Constraint::Length(1), Constraint::Length(3)]).areas(body);
+            frame.render_widget(Line::from(app.step.as_str()), step);
+            if let Some(progress) = &app.progress {
+                let eta = match progress.eta() {
+                    Some(secs) => format!("{:02}:{:02}", secs / 60, secs % 60),
+                    None => "--:--".to_string(),
+                };
+                let label = format!("{:.1}% ({}/{} mb, {:.1} MB/s, ETA {})", progress.percent(), progress.written / 1024 / 1024, progress.total / 1024 / 1024, progress.speed / 1024.0 / 1024.0, eta);
+                let gauge_widget = Gauge::default()
+                    .block(Block::bordered())
+                    .gauge_style(Style::default().fg(Color::Green))
+                    .ratio((progress.percent() / 100.0).clamp(0.0, 1.0))
+                    .label(label);
+                frame.render_widget(gauge_widget, gauge);
+            }
+        }
+        Screen::Done => {
+            let message = match &app.result {
+                Some(Ok(())) if app.empty => Line::from("Successfully made an empty volume, nothing of the image was written!").fg(Color::Green).bold(),
+                Some(Ok(())) => Line::from("Successfully written an image to disk!").fg(Color::Green).bold(),
+                Some(Err(BurnError::Aborted)) => Line::from("Aborted, the disk is in an inconsistent state.").fg(Color::Yellow).bold(),
+                Some(Err(e)) => Line::from(format!("Failed: {}", e)).fg(Color::Red).bold(),
+                None => Line::from(""),
+            };
+            frame.render_widget(Paragraph::new(message).wrap(Wrap { trim: true }), body);
+        }
+    }
+}
+
+fn event_loop(terminal: &mut DefaultTerminal, app: &mut App) -> std::io::Result<()> {
+    loop {
+        app.poll_updates();
+        terminal.draw(|frame| draw(frame, app))?;
+        if event::poll(Duration::from_millis(100))?
+            && let Event::Key(key) = event::read()?
+            && key.kind == KeyEventKind::Press
+            && app.on_key(key) {
+            return Ok(());
+        }
+    }
+}
+
+/// Runs the TUI until the user quits. Returns whether an image got burned successfully.
+pub fn run(options: TuiOptions) -> Result<bool, Box<dyn Error>> {
+    let mut app = App::new(options);
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, &mut app);
+    ratatui::restore();
+    result?;
+    // Quitting before the burn started isn't a failure.
+    Ok(!matches!(app.result, Some(Err(_))))
+}